@@ -1,15 +1,22 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     env,
     net::{IpAddr, SocketAddr},
+    ops::ControlFlow,
     path::PathBuf,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::{Duration, Instant, SystemTime},
 };
 
 use axum::{
     body::Body,
-    extract::{ConnectInfo, Path, State, Query},
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State, Query,
+    },
     http::{HeaderMap, Request, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
@@ -17,20 +24,38 @@ use axum::{
     middleware::Next,
 };
 use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
 use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
 use p256::pkcs8::DecodePublicKey;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use sha3::Keccak256;
 use time::OffsetDateTime;
-use tokio::{task::JoinHandle, time::sleep};
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+    time::sleep,
+};
 use tower_http::{
     cors::{Any, CorsLayer},
     limit::RequestBodyLimitLayer,
 };
 use uuid::Uuid;
 
-const ALLOWED_ACTIONS: &[&str] = &["transfer", "stake", "unstake"];
+const ALLOWED_ACTIONS: &[&str] = &["transfer", "stake", "unstake", "batch_transfer"];
+/// Sender of faucet credits; its nonce orders them and its balance stays 0.
+const FAUCET_ACCOUNT: &str = "faucet";
+/// Signing format covering action, memo, chain id and expiry.
+const TX_VERSION: u32 = 2;
+/// Original format (from/to/amount/fee/nonce only); accepted only with
+/// `BULEN_ALLOW_LEGACY_SIGNATURES`.
+const LEGACY_TX_VERSION: u32 = 1;
+const MAX_MULTISIG_KEYS: usize = 16;
+/// Signature schemes accepted for transactions and wallet challenges; a
+/// missing key type means p256.
+const KEY_TYPES: &[&str] = &["p256", "ed25519", "secp256k1"];
 
 type SharedState = Arc<RwLock<StateData>>;
 
@@ -38,6 +63,14 @@ fn default_action() -> String {
   "transfer".into()
 }
 
+fn legacy_tx_version() -> u32 {
+    LEGACY_TX_VERSION
+}
+
+fn default_key_type() -> String {
+    "p256".into()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Transaction {
     id: String,
@@ -55,14 +88,81 @@ struct Transaction {
     public_key: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_type: Option<String>,
+    #[serde(default = "legacy_tx_version")]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at_height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<TransferOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multisig: Option<MultisigPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    signatures: Vec<PartialSignature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lock: Option<LockSchedule>,
+}
+
+/// Keys and threshold controlling a multisig account; the account address is
+/// derived from it (see `derive_multisig_address`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MultisigPolicy {
+    threshold: usize,
+    #[serde(alias = "publicKeys")]
+    public_keys: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+struct PartialSignature {
+    #[serde(alias = "publicKey")]
+    public_key: String,
+    signature: String,
+    #[serde(default, alias = "keyType", skip_serializing_if = "Option::is_none")]
+    key_type: Option<String>,
+}
+
+/// One payee of a `batch_transfer`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TransferOutput {
+    to: String,
+    amount: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Account {
     balance: i128,
     stake: i128,
     nonce: u64,
     reputation: i64,
+    /// Part of `balance` that is not yet spendable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    locks: Vec<BalanceLock>,
+}
+
+/// Release schedule for locked funds. `unit` is "height" (block height) or
+/// "time" (unix seconds, compared against the latest block timestamp).
+/// Nothing is released before `cliff` (defaults to `start`); after that the
+/// amount vests linearly from `start` to `end`. A plain time lock has
+/// `start == end`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LockSchedule {
+    unit: String,
+    start: u64,
+    end: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cliff: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BalanceLock {
+    tx_id: String,
+    amount: u64,
+    schedule: LockSchedule,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +173,24 @@ struct Block {
     timestamp: String,
     producer: String,
     transactions: Vec<Transaction>,
+    /// `account_state_root` after the block, committed by its producer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_root: Option<String>,
+    /// Producer's reward inputs; blocks without one earn no reward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reward_claim: Option<RewardClaim>,
+}
+
+/// Reward inputs the producer claims for its block. The block hash covers
+/// them and `block_reward` caps them, so every node credits the same amount.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RewardClaim {
+    uptime_seconds: u64,
+    device_class: String,
+    reward_weight: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power_watts: Option<f64>,
 }
 
 #[derive(Clone)]
@@ -85,8 +203,18 @@ struct Config {
     block_interval_ms: u64,
     data_dir: PathBuf,
     peers: Vec<String>,
+    seed_nodes: Vec<String>,
+    /// URL peers should use to reach this node; advertised in handshakes.
+    public_url: Option<String>,
+    max_active_peers: usize,
+    max_known_peers: usize,
+    /// Learn peers on loopback, private and link-local hosts.
+    allow_private_peers: bool,
     p2p_token: Option<String>,
     require_signatures: bool,
+    allow_legacy_signatures: bool,
+    address_prefix: String,
+    allow_legacy_addresses: bool,
     enable_faucet: bool,
     rate_limit_window_ms: u64,
     rate_limit_max_requests: u32,
@@ -99,6 +227,55 @@ struct Config {
     loyalty_boost_steps: Vec<LoyaltyStep>,
     device_protection_boosts: HashMap<String, f64>,
     peer_sync_interval_ms: u64,
+    /// Blocks requested per `/p2p/blocks` call during sync.
+    sync_batch_blocks: u64,
+    /// Response size budget for `/p2p/blocks`.
+    p2p_range_max_bytes: usize,
+    peer_max_backoff_secs: u64,
+    peer_ban_secs: u64,
+    gossip_fanout: usize,
+    gossip_queue_size: usize,
+    gossip_seen_ttl_secs: u64,
+    /// Offer and dial persistent `/p2p/ws` links to peers.
+    p2p_websocket: bool,
+    /// Relay blocks over links as header plus short transaction ids.
+    compact_blocks: bool,
+    /// Follow headers and read proven account state from peers instead of
+    /// storing and executing blocks.
+    light_client: bool,
+    /// Peers that must serve the same header before a light node adopts it.
+    light_quorum: usize,
+    /// First height whose blocks must carry a `state_root`; older chains
+    /// set it past the blocks they made before roots existed.
+    state_root_height: u64,
+    /// Required in `x-bulen-device-token` for `/api/device/*`; loopback
+    /// callers only when unset.
+    device_token: Option<String>,
+    /// Battery level (0..1) below which the node sleeps unless charging.
+    low_battery_threshold: f64,
+    /// Multiplier on `peer_sync_interval_ms` while sleeping or metered.
+    power_sync_factor: u64,
+    p2p_session_ttl_secs: u64,
+    /// Required in `x-bulen-admin-token` for `/api/admin/*`; without it those
+    /// routes only answer loopback callers.
+    admin_token: Option<String>,
+    /// Accepted in `x-bulen-merchant-token` (besides the admin token) for
+    /// listing and cancelling payments.
+    merchant_token: Option<String>,
+    finality_min_depth: u64,
+    max_batch_outputs: usize,
+    batch_fee_per_output: u64,
+    /// Signs payment webhooks (`x-bulen-signature`) when set.
+    webhook_secret: Option<String>,
+    /// Refuse `webhookUrl` on payments while no secret is configured.
+    require_webhook_secret: bool,
+    /// Accept plain `http://` webhook URLs.
+    allow_insecure_webhooks: bool,
+    /// Accept webhook URLs on loopback, private and link-local hosts.
+    allow_private_webhooks: bool,
+    /// Delay before the first webhook retry; doubles per failed attempt.
+    webhook_retry_base_ms: u64,
+    webhook_max_attempts: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -119,6 +296,61 @@ struct StateData {
     block_weights: HashMap<String, f64>,
     #[serde(skip)]
     equivocations: HashMap<String, String>, // key: producer:height -> hash
+    #[serde(default)]
+    tx_index: HashMap<String, TxLocation>,
+    #[serde(default)]
+    address_index: HashMap<String, Vec<AddressEntry>>,
+    /// Undo records for the last `MAX_REORG_DEPTH` main-chain blocks.
+    #[serde(default)]
+    undo_log: VecDeque<BlockUndo>,
+}
+
+/// Period during which the node was online, in unix seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OnlineInterval {
+    start: u64,
+    end: u64,
+}
+
+/// Online time credited by `record_heartbeat`, persisted in `uptime.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UptimeLedger {
+    intervals: Vec<OnlineInterval>,
+    /// Total of intervals dropped beyond `MAX_UPTIME_INTERVALS`.
+    #[serde(default)]
+    archived_seconds: u64,
+    /// Last time a peer we dialed answered us over an authenticated
+    /// session.
+    #[serde(default)]
+    last_attested_at: u64,
+}
+
+/// What applying one block changed, so it can be rolled back on a reorg.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockUndo {
+    height: u64,
+    /// Accounts the block touched, as they were before it (`None` = absent).
+    accounts: Vec<(String, Option<Account>)>,
+    reward: f64,
+}
+
+/// Position of an applied transaction in the main chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TxLocation {
+    height: u64,
+    position: usize,
+}
+
+/// One balance-affecting event for an address, in chain order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AddressEntry {
+    height: u64,
+    /// Transaction position in the block; `None` for the producer reward.
+    position: Option<usize>,
+    direction: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reward: Option<i128>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -132,6 +364,218 @@ struct Payment {
     status: String,
     transaction_id: Option<String>,
     block_index: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    webhook_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merchant_order_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    customer_reference: Option<String>,
+}
+
+/// Creation time (unix ms) and id; the order of every payment index.
+type PaymentKey = (i64, String);
+
+fn payment_key(payment: &Payment) -> PaymentKey {
+    let created = parse_rfc3339(&payment.created_at)
+        .map(|dt| (dt.unix_timestamp_nanos() / 1_000_000) as i64)
+        .unwrap_or(0);
+    (created, payment.id.clone())
+}
+
+/// Payments by id with secondary indexes, so listing and search only walk
+/// matching keys.
+#[derive(Default)]
+struct PaymentStore {
+    payments: HashMap<String, Payment>,
+    by_created: BTreeSet<PaymentKey>,
+    by_status: HashMap<String, BTreeSet<PaymentKey>>,
+    by_destination: HashMap<String, BTreeSet<PaymentKey>>,
+    by_order_id: HashMap<String, BTreeSet<PaymentKey>>,
+    by_customer: HashMap<String, BTreeSet<PaymentKey>>,
+}
+
+impl PaymentStore {
+    fn new(payments: Vec<Payment>) -> Self {
+        let mut store = Self::default();
+        for payment in payments {
+            store.insert(payment);
+        }
+        store
+    }
+
+    fn index(&mut self, payment: &Payment, add: bool) {
+        let key = payment_key(payment);
+        if add {
+            self.by_created.insert(key.clone());
+        } else {
+            self.by_created.remove(&key);
+        }
+        for (index, value) in [
+            (&mut self.by_status, Some(&payment.status)),
+            (&mut self.by_destination, Some(&payment.to)),
+            (&mut self.by_order_id, payment.merchant_order_id.as_ref()),
+            (&mut self.by_customer, payment.customer_reference.as_ref()),
+        ] {
+            let Some(value) = value else {
+                continue;
+            };
+            if add {
+                index.entry(value.clone()).or_default().insert(key.clone());
+            } else if let Some(keys) = index.get_mut(value) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    index.remove(value);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, payment: Payment) {
+        if let Some(old) = self.payments.remove(&payment.id) {
+            self.index(&old, false);
+        }
+        self.index(&payment, true);
+        self.payments.insert(payment.id.clone(), payment);
+    }
+
+    fn get(&self, id: &str) -> Option<&Payment> {
+        self.payments.get(id)
+    }
+
+    fn len(&self) -> usize {
+        self.payments.len()
+    }
+
+    fn count_status(&self, status: &str) -> usize {
+        self.by_status.get(status).map(|keys| keys.len()).unwrap_or(0)
+    }
+
+    fn in_created_order(&self) -> Vec<&Payment> {
+        self.by_created.iter().map(|(_, id)| &self.payments[id]).collect()
+    }
+
+    /// Re-evaluates open (`pending`/`pending_block`) payments against the
+    /// chain and returns the ones whose status changed. Paid, expired and
+    /// cancelled payments are final and never rescanned.
+    fn refresh(&mut self, config: &Config, state: &StateData) -> Vec<Payment> {
+        let open: Vec<String> = ["pending", "pending_block"]
+            .iter()
+            .filter_map(|status| self.by_status.get(*status))
+            .flatten()
+            .map(|(_, id)| id.clone())
+            .collect();
+        let mut changed = vec![];
+        for id in open {
+            let Some(payment) = self.payments.get_mut(&id) else {
+                continue;
+            };
+            let before = payment.status.clone();
+            update_payment_status(config, payment, state);
+            if payment.status != before {
+                changed.push((before, payment.clone()));
+            }
+        }
+        for (before, payment) in &changed {
+            let key = payment_key(payment);
+            if let Some(keys) = self.by_status.get_mut(before) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    self.by_status.remove(before);
+                }
+            }
+            self.by_status.entry(payment.status.clone()).or_default().insert(key);
+        }
+        changed.into_iter().map(|(_, payment)| payment).collect()
+    }
+
+    /// Newest-first page of payments matching `query`, strictly older than
+    /// `after`, within `created` (unix ms, inclusive). Walks the smallest
+    /// index among the equality filters. The returned key is the cursor for
+    /// the next page, if there is one.
+    fn list(
+        &self,
+        query: &PaymentListQuery,
+        created: (i64, i64),
+        after: Option<PaymentKey>,
+        limit: usize,
+    ) -> (Vec<&Payment>, Option<PaymentKey>) {
+        let filters = [
+            (&self.by_status, query.status.as_ref()),
+            (&self.by_destination, query.to.as_ref()),
+            (&self.by_order_id, query.merchant_order_id.as_ref()),
+            (&self.by_customer, query.customer_reference.as_ref()),
+        ];
+        let empty = BTreeSet::new();
+        let mut keys = &self.by_created;
+        for (index, value) in filters {
+            if let Some(value) = value {
+                let candidates = index.get(value).unwrap_or(&empty);
+                if candidates.len() < keys.len() {
+                    keys = candidates;
+                }
+            }
+        }
+        let lower = (created.0, String::new());
+        let mut upper = (created.1.saturating_add(1), String::new());
+        if let Some(after) = after {
+            upper = upper.min(after);
+        }
+        if lower > upper {
+            return (vec![], None);
+        }
+        let matches = |payment: &&Payment| {
+            query.status.as_ref().is_none_or(|s| *s == payment.status)
+                && query.to.as_ref().is_none_or(|to| *to == payment.to)
+                && query
+                    .merchant_order_id
+                    .as_ref()
+                    .is_none_or(|id| payment.merchant_order_id.as_ref() == Some(id))
+                && query
+                    .customer_reference
+                    .as_ref()
+                    .is_none_or(|r| payment.customer_reference.as_ref() == Some(r))
+        };
+        let range = (std::ops::Bound::Included(lower), std::ops::Bound::Excluded(upper));
+        let mut page = vec![];
+        let mut next = None;
+        for key in keys.range(range).rev() {
+            let payment = &self.payments[&key.1];
+            if !matches(&payment) {
+                continue;
+            }
+            if page.len() == limit {
+                next = page.last().map(|p: &&Payment| payment_key(p));
+                break;
+            }
+            page.push(payment);
+        }
+        (page, next)
+    }
+}
+
+/// Signed callback for one payment status change, kept in the outbox
+/// (`webhooks.json`) until delivered or out of attempts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookDelivery {
+    id: String,
+    payment_id: String,
+    url: String,
+    /// Exact JSON body sent; signatures cover `{timestamp}.{body}`.
+    body: String,
+    /// "pending", "delivered" or "failed".
+    state: String,
+    created_at: u64,
+    next_attempt_at_ms: u64,
+    attempts: Vec<WebhookAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookAttempt {
+    at: u64,
+    status_code: Option<u16>,
+    error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -140,6 +584,8 @@ struct WalletChallenge {
     address: String,
     public_key: String,
     wallet_type: String,
+    #[serde(default = "default_key_type")]
+    key_type: String,
     nonce: String,
     message: String,
     created_at: String,
@@ -153,6 +599,8 @@ struct WalletSession {
     address: String,
     public_key: String,
     wallet_type: String,
+    #[serde(default = "default_key_type")]
+    key_type: String,
     created_at: String,
     expires_at: String,
 }
@@ -163,6 +611,17 @@ struct WalletStore {
     sessions: Vec<WalletSession>,
 }
 
+/// Multisig transaction waiting for co-signers before broadcast.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MultisigProposal {
+    id: String,
+    transaction: Transaction,
+    payload: String,
+    created_at: String,
+    expires_at: String,
+    status: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct LoyaltyStep {
     days: u64,
@@ -176,7 +635,8 @@ struct RateLimiter {
     buckets: Mutex<HashMap<IpAddr, (u32, Instant)>>,
 }
 
-#[derive(Debug, Clone)]
+/// Address-book entry for a peer URL, persisted in `peers.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct PeerStat {
     height: u64,
     last_seen: u64,
@@ -184,6 +644,20 @@ struct PeerStat {
     latest_hash: Option<String>,
     failures: u64,
     node_id: Option<String>,
+    #[serde(default)]
+    successes: u64,
+    /// How the address was learned: "static", "seed", "exchange" or "handshake".
+    #[serde(default)]
+    source: String,
+    /// Failed contacts since the last success; drives the backoff.
+    #[serde(default)]
+    consecutive_failures: u32,
+    /// Unix time before which the peer is not contacted again.
+    #[serde(default)]
+    next_attempt_at: u64,
+    /// Moving average of `/api/status` round trips.
+    #[serde(default)]
+    avg_latency_ms: Option<f64>,
 }
 
 impl RateLimiter {
@@ -207,15 +681,202 @@ impl RateLimiter {
     }
 }
 
+/// Long-lived p256 key identifying this node to peers, kept in
+/// `node_identity.pem` so restarts keep the same identity.
+struct NodeIdentity {
+    key: p256::ecdsa::SigningKey,
+    public_key: String,
+}
+
+/// Authenticated session a peer obtained through `/p2p/handshake`.
+#[derive(Debug, Clone)]
+struct PeerSession {
+    node_id: String,
+    expires_at: u64,
+    /// Address the handshake came from; penalties apply to it as well.
+    remote_ip: String,
+}
+
+/// Session this node holds with a remote peer (keyed by peer URL).
+#[derive(Debug, Clone)]
+struct OutboundSession {
+    token: String,
+    expires_at: u64,
+    node_id: String,
+    /// Both sides offered the WebSocket transport in the handshake.
+    websocket: bool,
+}
+
+#[derive(Default)]
+struct P2pSessions {
+    inbound: HashMap<String, PeerSession>,
+    outbound: HashMap<String, OutboundSession>,
+    /// Handshake nonces seen inside the clock-skew window (replay guard).
+    seen_nonces: HashMap<String, u64>,
+    /// Identity key first seen for each node id; a node id cannot later
+    /// handshake with a different key. Persisted in `peer_identities.json`.
+    known_keys: HashMap<String, KnownPeerKey>,
+    /// `known_keys` changed since it was last written.
+    identities_dirty: bool,
+    identities_saved_at: u64,
+    /// Misbehaviour records keyed by node id (peer URL when the id is unknown).
+    penalties: HashMap<String, PeerPenalty>,
+}
+
+/// Identity key pinned to a node id, dropped after `PEER_KEY_TTL_SECS`
+/// without a handshake.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KnownPeerKey {
+    public_key: String,
+    last_seen: u64,
+}
+
+/// Penalty points a peer collected for sending invalid data; reaching
+/// `PEER_BAN_THRESHOLD` bans it for `BULEN_PEER_BAN_SECONDS`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PeerPenalty {
+    points: u32,
+    invalid_blocks: u64,
+    invalid_txs: u64,
+    banned_until: u64,
+    reason: Option<String>,
+}
+
+/// Message waiting in a peer's outbound gossip queue.
+#[derive(Debug, Clone)]
+struct GossipItem {
+    id: String,
+    message: PeerMessage,
+    attempts: u32,
+}
+
+/// Peers (node ids or URLs) known to have a message, until `expires_at`.
+#[derive(Debug)]
+struct SeenEntry {
+    expires_at: u64,
+    peers: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct GossipCounters {
+    sent: AtomicU64,
+    relayed: AtomicU64,
+    duplicates: AtomicU64,
+    dropped: AtomicU64,
+    retries: AtomicU64,
+    compact_blocks: AtomicU64,
+    /// Transactions compact blocks lacked and had to be requested.
+    compact_missing: AtomicU64,
+}
+
+/// Seen-message entries with their expiry times in insertion order. The TTL
+/// is fixed, so the front of `expiry` is always the next entry to expire.
+#[derive(Debug, Default)]
+struct SeenCache {
+    entries: HashMap<String, SeenEntry>,
+    expiry: VecDeque<(u64, String)>,
+}
+
+/// Push gossip: seen-message cache, bounded per-peer outbound queues drained
+/// by `spawn_gossip_sender`, and counters exported in `/metrics`.
+#[derive(Debug, Default)]
+struct Gossip {
+    seen: Mutex<SeenCache>,
+    queues: Mutex<HashMap<String, VecDeque<GossipItem>>>,
+    /// Peers whose queue is currently being drained.
+    draining: Mutex<HashSet<String>>,
+    counters: GossipCounters,
+    wake: tokio::sync::Notify,
+}
+
+/// Latest report from `/api/device/battery` and `/api/device/energy`,
+/// persisted in `power.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PowerState {
+    battery_level: Option<f64>,
+    charging: bool,
+    metered: bool,
+    power_watts: Option<f64>,
+    /// Set while asleep on low battery: no blocks, slower sync, no uptime.
+    sleeping_since: Option<u64>,
+    /// Finished sleep periods, excluded from reward uptime.
+    slept_seconds: u64,
+}
+
+/// Live WebSocket links, keyed by peer URL (dialed by us) or node id
+/// (accepted from the peer). Each sender feeds the link's writer task.
+#[derive(Debug, Default)]
+struct PeerLinks {
+    links: HashMap<String, mpsc::Sender<String>>,
+    /// Peers whose WebSocket dial failed are reached over HTTP until then.
+    retry_at: HashMap<String, u64>,
+    /// Compact blocks waiting for transactions, keyed by block hash and the
+    /// node id asked for them; only that peer's answer fills them in.
+    pending_blocks: HashMap<(String, String), PendingBlock>,
+    /// When a header announcement last woke the sync loop.
+    last_sync_wake: Option<Instant>,
+}
+
+/// Compact block whose missing transactions were requested from the sender.
+#[derive(Debug)]
+struct PendingBlock {
+    header: BlockHeader,
+    transactions: Vec<Option<Transaction>>,
+    /// Positions asked for in the outstanding `GetBlockTxs`.
+    requested: Vec<usize>,
+    /// Every transaction was requested after a short id matched the wrong
+    /// mempool entry.
+    retried: bool,
+    received_at: u64,
+}
+
+/// Typed frame exchanged over a peer WebSocket link.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum PeerMessage {
+    Tx { transaction: Box<Transaction> },
+    Block { block: Block },
+    /// Announces a new block without its body; receivers that lack it sync.
+    Header { header: BlockHeader },
+    /// Block as header plus `short_tx_id`s; receivers rebuild it from their
+    /// mempool and fetch the rest with `GetBlockTxs`.
+    CompactBlock { header: BlockHeader, short_ids: Vec<String> },
+    GetBlockTxs { hash: String, indexes: Vec<usize> },
+    /// Answer to `GetBlockTxs`, in the requested order; empty when the
+    /// block is unknown.
+    BlockTxs { hash: String, transactions: Vec<Transaction> },
+    Ping { nonce: u64 },
+    Pong { nonce: u64 },
+}
+
 #[derive(Clone)]
 struct AppState {
     config: Config,
     state: SharedState,
     rate_limiter: Arc<RateLimiter>,
     client: Client,
-    payments: Arc<Mutex<Vec<Payment>>>,
+    /// See `webhook_client`.
+    webhook_client: Client,
+    payments: Arc<Mutex<PaymentStore>>,
     wallet_store: Arc<Mutex<WalletStore>>,
+    multisig_proposals: Arc<Mutex<Vec<MultisigProposal>>>,
     peer_stats: Arc<Mutex<HashMap<String, PeerStat>>>,
+    identity: Arc<NodeIdentity>,
+    p2p_sessions: Arc<Mutex<P2pSessions>>,
+    gossip: Arc<Gossip>,
+    peer_links: Arc<Mutex<PeerLinks>>,
+    /// Wakes the sync loop early, e.g. on a header announcement.
+    sync_wake: Arc<Notify>,
+    /// Header chain from genesis, followed in light mode.
+    light_headers: Arc<Mutex<Vec<BlockHeader>>>,
+    power: Arc<Mutex<PowerState>>,
+    uptime: Arc<Mutex<UptimeLedger>>,
+    webhooks: Arc<Mutex<Vec<WebhookDelivery>>>,
+    /// Wakes the webhook worker when a delivery is queued.
+    webhook_wake: Arc<Notify>,
 }
 
 fn parse_bool_env(name: &str, default: bool) -> bool {
@@ -267,13 +928,13 @@ fn parse_loyalty_steps(raw: &str) -> Vec<LoyaltyStep> {
     let mut out = vec![];
     for part in raw.split(',') {
         let mut pieces = part.split(':');
-        if let (Some(days), Some(mult)) = (pieces.next(), pieces.next()) {
-            if let (Ok(d), Ok(m)) = (days.trim().parse::<u64>(), mult.trim().parse::<f64>()) {
-                out.push(LoyaltyStep {
-                    days: d,
-                    multiplier: m,
-                });
-            }
+        if let (Some(days), Some(mult)) = (pieces.next(), pieces.next())
+            && let (Ok(d), Ok(m)) = (days.trim().parse::<u64>(), mult.trim().parse::<f64>())
+        {
+            out.push(LoyaltyStep {
+                days: d,
+                multiplier: m,
+            });
         }
     }
     out.sort_by_key(|s| s.days);
@@ -290,10 +951,10 @@ fn parse_device_boosts(raw: &str) -> HashMap<String, f64> {
     }
     for part in raw.split(',') {
         let mut pieces = part.split(':');
-        if let (Some(cls), Some(mult)) = (pieces.next(), pieces.next()) {
-            if let Ok(m) = mult.trim().parse::<f64>() {
-                map.insert(cls.trim().to_string(), m);
-            }
+        if let (Some(cls), Some(mult)) = (pieces.next(), pieces.next())
+            && let Ok(m) = mult.trim().parse::<f64>()
+        {
+            map.insert(cls.trim().to_string(), m);
         }
     }
     map
@@ -340,10 +1001,10 @@ fn compute_loyalty_boost(config: &Config, uptime_seconds: u64) -> f64 {
     boost
 }
 
-fn compute_device_boost(config: &Config) -> f64 {
+fn device_class_boost(config: &Config, device_class: &str) -> f64 {
     config
         .device_protection_boosts
-        .get(&config.device_class)
+        .get(device_class)
         .copied()
         .unwrap_or(1.0)
 }
@@ -352,10 +1013,166 @@ fn parse_rfc3339(value: &str) -> Option<OffsetDateTime> {
     OffsetDateTime::parse(value, &time::format_description::well_known::Rfc3339).ok()
 }
 
-fn reward_per_block(config: &Config, uptime_seconds: u64) -> f64 {
-    let loyalty = compute_loyalty_boost(config, uptime_seconds);
-    let device = compute_device_boost(config);
-    let hourly = config.base_uptime_reward_per_hour * config.reward_weight * loyalty * device;
+/// Tiered boost for low power draw, as in the JS node.
+fn compute_efficiency_boost(power_watts: Option<f64>) -> f64 {
+    match power_watts {
+        Some(w) if w > 0.0 && w <= 5.0 => 1.2,
+        Some(w) if w > 5.0 && w <= 10.0 => 1.1,
+        Some(w) if w > 10.0 && w <= 20.0 => 1.05,
+        _ => 1.0,
+    }
+}
+
+fn slept_seconds(power: &PowerState, now: u64) -> u64 {
+    power.slept_seconds + power.sleeping_since.map(|since| now.saturating_sub(since)).unwrap_or(0)
+}
+
+/// Seconds between heartbeats of the uptime tracker.
+const UPTIME_HEARTBEAT_SECS: u64 = 60;
+/// Online intervals kept individually; older ones only count in the total.
+const MAX_UPTIME_INTERVALS: usize = 1_000;
+
+fn uptime_file(config: &Config) -> PathBuf {
+    config.data_dir.join("uptime.json")
+}
+
+fn load_uptime_ledger(config: &Config) -> UptimeLedger {
+    std::fs::read_to_string(uptime_file(config))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_uptime_ledger(config: &Config, ledger: &UptimeLedger) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(uptime_file(config), serde_json::to_string(ledger).unwrap());
+}
+
+fn power_file(config: &Config) -> PathBuf {
+    config.data_dir.join("power.json")
+}
+
+fn load_power_state(config: &Config) -> PowerState {
+    std::fs::read_to_string(power_file(config))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_power_state(config: &Config, power: &PowerState) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(power_file(config), serde_json::to_string(power).unwrap());
+}
+
+fn online_seconds(ledger: &UptimeLedger) -> u64 {
+    let open: u64 = ledger.intervals.iter().map(|i| i.end - i.start).sum();
+    ledger.archived_seconds + open
+}
+
+fn attest_liveness(app: &AppState) {
+    app.uptime.lock().unwrap().last_attested_at = now_unix();
+}
+
+/// Credits the time since the previous heartbeat if the node is awake and
+/// a peer vouched for it within two heartbeats. Nodes without configured
+/// peers or seeds vouch for themselves. A gap longer than two heartbeats
+/// starts a new interval, so downtime is never credited.
+fn record_heartbeat(app: &AppState, now: u64) {
+    let standalone = app.config.peers.is_empty() && app.config.seed_nodes.is_empty();
+    let awake = app.power.lock().unwrap().sleeping_since.is_none();
+    let mut ledger = app.uptime.lock().unwrap();
+    let window = 2 * UPTIME_HEARTBEAT_SECS;
+    if awake && (standalone || now.saturating_sub(ledger.last_attested_at) <= window) {
+        match ledger.intervals.last_mut() {
+            Some(last) if now >= last.end && now - last.end <= window => last.end = now,
+            _ => ledger.intervals.push(OnlineInterval { start: now, end: now }),
+        }
+        if ledger.intervals.len() > MAX_UPTIME_INTERVALS {
+            let old = ledger.intervals.remove(0);
+            ledger.archived_seconds += old.end - old.start;
+        }
+        save_uptime_ledger(&app.config, &ledger);
+    }
+}
+
+fn spawn_uptime_tracker(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut heartbeat = tokio::time::interval(Duration::from_secs(UPTIME_HEARTBEAT_SECS));
+        loop {
+            heartbeat.tick().await;
+            record_heartbeat(&app, now_unix());
+        }
+    })
+}
+
+/// Verified online seconds that count toward uptime rewards.
+fn reward_uptime_seconds(app: &AppState) -> u64 {
+    online_seconds(&app.uptime.lock().unwrap())
+}
+
+struct UptimeReward {
+    loyalty_boost: f64,
+    device_boost: f64,
+    efficiency_boost: f64,
+    hourly: f64,
+}
+
+/// Highest reward weight of the node profiles; claims above it are capped.
+const MAX_REWARD_WEIGHT: f64 = 1.0;
+
+/// The one reward formula, shared by block rewards and the reward reports.
+fn compute_uptime_reward(config: &Config, claim: &RewardClaim) -> UptimeReward {
+    let loyalty_boost = compute_loyalty_boost(config, claim.uptime_seconds);
+    let device_boost = device_class_boost(config, &claim.device_class);
+    let efficiency_boost = compute_efficiency_boost(claim.power_watts);
+    UptimeReward {
+        loyalty_boost,
+        device_boost,
+        efficiency_boost,
+        hourly: config.base_uptime_reward_per_hour
+            * claim.reward_weight
+            * loyalty_boost
+            * device_boost
+            * efficiency_boost,
+    }
+}
+
+/// Reward inputs of this node; its blocks carry them as their claim.
+fn local_reward_claim(app: &AppState, uptime_seconds: u64, device_class: &str) -> RewardClaim {
+    RewardClaim {
+        uptime_seconds,
+        device_class: device_class.to_string(),
+        reward_weight: app.config.reward_weight,
+        power_watts: app.power.lock().unwrap().power_watts,
+    }
+}
+
+fn uptime_reward(app: &AppState, uptime_seconds: u64, device_class: &str) -> UptimeReward {
+    compute_uptime_reward(&app.config, &local_reward_claim(app, uptime_seconds, device_class))
+}
+
+/// Reward for `block` on top of `state`, from chain data only: the claimed
+/// uptime is capped at the chain's age at the block (counted from block 1)
+/// and the weight at `MAX_REWARD_WEIGHT`.
+fn block_reward(config: &Config, state: &StateData, block: &Block) -> f64 {
+    let Some(claim) = block.reward_claim.as_ref() else {
+        return 0.0;
+    };
+    let unix = |b: &Block| parse_rfc3339(&b.timestamp).map(|t| t.unix_timestamp().max(0) as u64);
+    let chain_age = match (state.blocks.get(1).and_then(unix), unix(block)) {
+        (Some(start), Some(at)) => at.saturating_sub(start),
+        _ => 0,
+    };
+    let capped = RewardClaim {
+        uptime_seconds: claim.uptime_seconds.min(chain_age),
+        reward_weight: claim.reward_weight.clamp(0.0, MAX_REWARD_WEIGHT),
+        ..claim.clone()
+    };
+    hourly_reward_per_block(config, compute_uptime_reward(config, &capped).hourly)
+}
+
+/// Share of an hourly reward paid per produced block.
+fn hourly_reward_per_block(config: &Config, hourly: f64) -> f64 {
     let blocks_per_hour = 3600.0 / (config.block_interval_ms as f64 / 1000.0);
     if blocks_per_hour <= 0.0 {
         0.0
@@ -371,54 +1188,348 @@ fn now_unix() -> u64 {
         .as_secs()
 }
 
-fn record_peer_stat(
+fn record_peer_success(
     stats: &mut HashMap<String, PeerStat>,
     peer: &str,
     height: u64,
-    ok: bool,
     hash: Option<String>,
     node_id: Option<String>,
+    latency_ms: f64,
 ) {
-    let entry = stats.entry(peer.to_string()).or_insert(PeerStat {
-        height: 0,
-        last_seen: 0,
-        ok: false,
-        latest_hash: None,
-        failures: 0,
-        node_id: None,
-    });
+    let entry = stats
+        .entry(peer.to_string())
+        .or_insert_with(|| new_peer_stat("static"));
     entry.height = height;
     entry.last_seen = now_unix();
-    entry.ok = ok;
+    entry.ok = true;
     entry.latest_hash = hash;
-    entry.node_id = node_id;
-    if !ok {
-        entry.failures += 1;
+    if node_id.is_some() {
+        entry.node_id = node_id;
     }
+    entry.successes += 1;
+    entry.consecutive_failures = 0;
+    entry.next_attempt_at = 0;
+    entry.avg_latency_ms = Some(match entry.avg_latency_ms {
+        Some(avg) => avg * 0.8 + latency_ms * 0.2,
+        None => latency_ms,
+    });
 }
 
-fn default_config() -> Config {
-    let profile_defaults = HashMap::from([
-        (
-            "desktop-full".to_string(),
-            (0.8f64, "desktop".to_string(), 8_000u64, true, "validator".to_string(), 4100u16),
-        ),
-        (
-            "server-full".to_string(),
-            (1.0f64, "server".to_string(), 6_000u64, false, "validator".to_string(), 4100u16),
-        ),
-        (
-            "mobile-light".to_string(),
-            (0.5f64, "phone".to_string(), 12_000u64, true, "validator".to_string(), 4110u16),
-        ),
-        (
-            "tablet-light".to_string(),
-            (0.6f64, "tablet".to_string(), 11_000u64, true, "validator".to_string(), 4112u16),
-        ),
-        (
-            "raspberry".to_string(),
-            (0.75f64, "raspberry".to_string(), 9_000u64, true, "validator".to_string(), 4120u16),
-        ),
+/// Marks `peer` unreachable and pushes its next attempt out exponentially,
+/// starting at the sync interval and capped at `BULEN_PEER_MAX_BACKOFF_SECONDS`.
+fn record_peer_failure(config: &Config, stats: &mut HashMap<String, PeerStat>, peer: &str) {
+    let entry = stats
+        .entry(peer.to_string())
+        .or_insert_with(|| new_peer_stat("static"));
+    let now = now_unix();
+    entry.last_seen = now;
+    entry.ok = false;
+    entry.failures += 1;
+    entry.consecutive_failures = entry.consecutive_failures.saturating_add(1);
+    let base = (config.peer_sync_interval_ms / 1000).max(1);
+    let backoff = base
+        .saturating_mul(1u64 << (entry.consecutive_failures - 1).min(20))
+        .min(config.peer_max_backoff_secs);
+    entry.next_attempt_at = now + backoff;
+}
+
+const INVALID_BLOCK_PENALTY: u32 = 50;
+const INVALID_TX_PENALTY: u32 = 10;
+const PEER_BAN_THRESHOLD: u32 = 100;
+
+/// Higher is better: successful contacts add up to 50, while consecutive
+/// failures, slow responses and penalty points subtract.
+fn peer_score(stat: &PeerStat, penalty: Option<&PeerPenalty>) -> i64 {
+    let latency = stat.avg_latency_ms.map(|ms| (ms / 100.0).min(20.0) as i64).unwrap_or(0);
+    100 + stat.successes.min(50) as i64
+        - 10 * stat.consecutive_failures as i64
+        - latency
+        - penalty.map(|p| p.points as i64).unwrap_or(0)
+}
+
+fn peer_banned(penalties: &HashMap<String, PeerPenalty>, key: &str, now: u64) -> bool {
+    penalties.get(key).is_some_and(|p| p.banned_until > now)
+}
+
+/// Penalizes the peer identified by `key` (node id, or URL) for sending an
+/// invalid block or transaction, banning it once it crosses the threshold.
+/// The listen URLs and remote addresses seen for a node id are penalized with
+/// it, so a fresh self-chosen node id does not clear the record.
+fn penalize_peer(app: &AppState, key: &str, invalid_block: bool, reason: &str) {
+    let now = now_unix();
+    let mut keys = vec![key.to_string()];
+    keys.extend(
+        app.peer_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, stat)| stat.node_id.as_deref() == Some(key))
+            .map(|(url, _)| url.clone()),
+    );
+    let mut sessions = app.p2p_sessions.lock().unwrap();
+    keys.extend(
+        sessions
+            .inbound
+            .values()
+            .filter(|s| s.node_id == key)
+            // Loopback is a local proxy; banning it would ban every peer.
+            .filter(|s| s.remote_ip.parse::<IpAddr>().is_ok_and(|ip| !ip.is_loopback()))
+            .map(|s| s.remote_ip.clone()),
+    );
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        add_peer_penalty(app, &mut sessions, &key, invalid_block, reason, now);
+    }
+}
+
+fn add_peer_penalty(
+    app: &AppState,
+    sessions: &mut P2pSessions,
+    key: &str,
+    invalid_block: bool,
+    reason: &str,
+    now: u64,
+) {
+    let penalty = sessions.penalties.entry(key.to_string()).or_default();
+    if penalty.banned_until != 0 && penalty.banned_until <= now {
+        // Ban served: start over.
+        penalty.points = 0;
+        penalty.banned_until = 0;
+    }
+    if invalid_block {
+        penalty.points += INVALID_BLOCK_PENALTY;
+        penalty.invalid_blocks += 1;
+    } else {
+        penalty.points += INVALID_TX_PENALTY;
+        penalty.invalid_txs += 1;
+    }
+    penalty.reason = Some(reason.to_string());
+    if penalty.points >= PEER_BAN_THRESHOLD && penalty.banned_until == 0 {
+        penalty.banned_until = now + app.config.peer_ban_secs;
+        eprintln!("banning peer {} until {}: {}", key, penalty.banned_until, reason);
+        sessions.inbound.retain(|_, s| s.node_id != key && s.remote_ip != key);
+    }
+}
+
+fn new_peer_stat(source: &str) -> PeerStat {
+    PeerStat {
+        height: 0,
+        last_seen: 0,
+        ok: false,
+        latest_hash: None,
+        failures: 0,
+        node_id: None,
+        successes: 0,
+        source: source.to_string(),
+        consecutive_failures: 0,
+        next_attempt_at: 0,
+        avg_latency_ms: None,
+    }
+}
+
+/// Canonical form of a peer URL (`http(s)://host[:port]`, no trailing slash).
+fn normalize_peer_url(raw: &str) -> Option<String> {
+    let url = raw.trim().trim_end_matches('/');
+    let valid = (url.starts_with("http://") || url.starts_with("https://"))
+        && url.len() <= 256
+        && !url.chars().any(char::is_whitespace);
+    valid.then(|| url.to_string())
+}
+
+/// Whether `ip` is reachable on the public internet: not loopback,
+/// private, link-local or otherwise internal.
+fn is_public_ip(ip: IpAddr) -> bool {
+    let public_v4 = |ip: std::net::Ipv4Addr| {
+        let [a, b, ..] = ip.octets();
+        !(ip.is_loopback()
+            || ip.is_private()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || ip.is_broadcast()
+            || ip.is_documentation()
+            || (a == 100 && (64..128).contains(&b)))
+    };
+    match ip {
+        IpAddr::V4(ip) => public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => public_v4(v4),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Whether `url` names a public host (see `is_public_ip`). Hostnames are
+/// judged by name only.
+fn is_public_host(url: &str) -> bool {
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    let Some(host) = parsed.host_str() else {
+        return false;
+    };
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => {
+            let name = host.trim_end_matches('.').to_ascii_lowercase();
+            !(name == "localhost"
+                || [".localhost", ".local", ".internal", ".lan"]
+                    .iter()
+                    .any(|suffix| name.ends_with(suffix))
+                || !name.contains('.'))
+        }
+    }
+}
+
+/// Adds a newly learned peer to the address book. When the book is full the
+/// worst discovered (non-static, non-seed) entry is evicted.
+fn add_known_peer(
+    config: &Config,
+    book: &mut HashMap<String, PeerStat>,
+    raw: &str,
+    source: &str,
+) -> bool {
+    let Some(url) = normalize_peer_url(raw) else {
+        return false;
+    };
+    if book.contains_key(&url) || config.public_url.as_deref() == Some(url.as_str()) {
+        return false;
+    }
+    if !config.allow_private_peers && !is_public_host(&url) {
+        return false;
+    }
+    if book.len() >= config.max_known_peers {
+        let worst = book
+            .iter()
+            .filter(|(_, s)| s.source != "static" && s.source != "seed")
+            .max_by_key(|(_, s)| (s.failures.saturating_sub(s.successes), u64::MAX - s.last_seen))
+            .map(|(url, _)| url.clone());
+        match worst {
+            Some(worst) => {
+                book.remove(&worst);
+            }
+            None => return false,
+        }
+    }
+    book.insert(url, new_peer_stat(source));
+    true
+}
+
+/// Peers this node talks to: static peers and seeds always, then the best
+/// scored entries of the address book up to `BULEN_MAX_ACTIVE_PEERS`. Peers in
+/// backoff or banned are left out until their time is up.
+fn active_peers(
+    config: &Config,
+    book: &HashMap<String, PeerStat>,
+    penalties: &HashMap<String, PeerPenalty>,
+) -> Vec<String> {
+    let now = now_unix();
+    let available = |url: &String| match book.get(url) {
+        Some(stat) => {
+            stat.next_attempt_at <= now
+                && !peer_banned(penalties, url, now)
+                && !stat.node_id.as_ref().is_some_and(|id| peer_banned(penalties, id, now))
+        }
+        None => true,
+    };
+    let mut active: Vec<String> = Vec::new();
+    for peer in config.peers.iter().chain(config.seed_nodes.iter()) {
+        if let Some(url) = normalize_peer_url(peer)
+            && !active.contains(&url)
+            && available(&url)
+        {
+            active.push(url);
+        }
+    }
+    let mut candidates: Vec<(&String, &PeerStat)> = book
+        .iter()
+        .filter(|(url, _)| !active.contains(url) && available(url))
+        .collect();
+    candidates.sort_by_key(|(url, s)| {
+        let penalty = s.node_id.as_ref().and_then(|id| penalties.get(id));
+        (!s.ok, -peer_score(s, penalty), u64::MAX - s.last_seen, (*url).clone())
+    });
+    let room = config.max_active_peers.saturating_sub(active.len());
+    active.extend(candidates.into_iter().take(room).map(|(url, _)| url.clone()));
+    active
+}
+
+fn current_active_peers(app: &AppState) -> Vec<String> {
+    let penalties = app.p2p_sessions.lock().unwrap().penalties.clone();
+    active_peers(&app.config, &app.peer_stats.lock().unwrap(), &penalties)
+}
+
+fn peers_file(config: &Config) -> PathBuf {
+    config.data_dir.join("peers.json")
+}
+
+fn load_address_book(config: &Config) -> HashMap<String, PeerStat> {
+    let mut book: HashMap<String, PeerStat> = std::fs::read_to_string(peers_file(config))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    // Entries learned before private hosts were filtered out.
+    book.retain(|url, _| config.allow_private_peers || is_public_host(url));
+    for (peers, source) in [(&config.peers, "static"), (&config.seed_nodes, "seed")] {
+        for peer in peers {
+            if let Some(url) = normalize_peer_url(peer) {
+                book.entry(url).or_insert_with(|| new_peer_stat(source)).source = source.into();
+            }
+        }
+    }
+    book
+}
+
+fn save_address_book(config: &Config, book: &HashMap<String, PeerStat>) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(peers_file(config), serde_json::to_string_pretty(book).unwrap());
+}
+
+fn light_headers_file(config: &Config) -> PathBuf {
+    config.data_dir.join("light_headers.json")
+}
+
+/// Headers followed in light mode; starts at genesis.
+fn load_light_headers(config: &Config) -> Vec<BlockHeader> {
+    std::fs::read_to_string(light_headers_file(config))
+        .ok()
+        .and_then(|raw| serde_json::from_str::<Vec<BlockHeader>>(&raw).ok())
+        .filter(|headers| !headers.is_empty())
+        .unwrap_or_else(|| vec![BlockHeader::from(&genesis_block(config))])
+}
+
+fn save_light_headers(config: &Config, headers: &[BlockHeader]) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(light_headers_file(config), serde_json::to_string(headers).unwrap());
+}
+
+fn default_config() -> Config {
+    let profile_defaults = HashMap::from([
+        (
+            "desktop-full".to_string(),
+            (0.8f64, "desktop".to_string(), 8_000u64, true, "validator".to_string(), 4100u16),
+        ),
+        (
+            "server-full".to_string(),
+            (1.0f64, "server".to_string(), 6_000u64, false, "validator".to_string(), 4100u16),
+        ),
+        (
+            "mobile-light".to_string(),
+            (0.5f64, "phone".to_string(), 12_000u64, true, "validator".to_string(), 4110u16),
+        ),
+        (
+            "tablet-light".to_string(),
+            (0.6f64, "tablet".to_string(), 11_000u64, true, "validator".to_string(), 4112u16),
+        ),
+        (
+            "raspberry".to_string(),
+            (0.75f64, "raspberry".to_string(), 9_000u64, true, "validator".to_string(), 4120u16),
+        ),
         (
             "gateway".to_string(),
             (0.9f64, "server".to_string(), 8_000u64, false, "observer".to_string(), 4130u16),
@@ -438,11 +1549,25 @@ fn default_config() -> Config {
         .map(PathBuf::from)
         .unwrap_or_else(|_| PathBuf::from("data-rs"));
     let peers = env::var("BULEN_PEERS").map(|v| parse_peers(&v)).unwrap_or_default();
+    let seed_nodes = env::var("BULEN_SEED_NODES").map(|v| parse_peers(&v)).unwrap_or_default();
+    let public_url = env::var("BULEN_PUBLIC_URL")
+        .ok()
+        .and_then(|v| normalize_peer_url(&v));
+    let max_active_peers = parse_number_env("BULEN_MAX_ACTIVE_PEERS", 8usize);
+    let max_known_peers = parse_number_env("BULEN_MAX_KNOWN_PEERS", 256usize);
+    let allow_private_peers = parse_bool_env("BULEN_ALLOW_PRIVATE_PEERS", false);
     let p2p_token = env::var("BULEN_P2P_TOKEN").ok().filter(|v| !v.is_empty());
     let require_signatures = parse_bool_env(
         "BULEN_REQUIRE_SIGNATURES",
         env::var("NODE_ENV").map(|v| v == "production").unwrap_or(false),
     );
+    let allow_legacy_signatures = parse_bool_env("BULEN_ALLOW_LEGACY_SIGNATURES", false);
+    let address_prefix = env::var("BULEN_ADDRESS_PREFIX")
+        .map(|v| v.trim().to_lowercase())
+        .ok()
+        .filter(|v| bech32::Hrp::parse(v).is_ok())
+        .unwrap_or_else(|| default_address_prefix(&chain_id));
+    let allow_legacy_addresses = parse_bool_env("BULEN_ALLOW_LEGACY_ADDRESSES", true);
     let enable_faucet = parse_bool_env(
         "BULEN_ENABLE_FAUCET",
         env::var("NODE_ENV")
@@ -468,6 +1593,34 @@ fn default_config() -> Config {
             parse_device_boosts("")
         });
     let peer_sync_interval_ms = parse_number_env("BULEN_PEER_SYNC_INTERVAL_MS", 5_000u64);
+    let sync_batch_blocks = parse_number_env("BULEN_SYNC_BATCH_BLOCKS", 200u64).clamp(1, MAX_RANGE_BLOCKS);
+    let p2p_range_max_bytes = parse_number_env("BULEN_P2P_RANGE_MAX_BYTES", 4_194_304usize);
+    let peer_max_backoff_secs = parse_number_env("BULEN_PEER_MAX_BACKOFF_SECONDS", 3_600u64);
+    let peer_ban_secs = parse_number_env("BULEN_PEER_BAN_SECONDS", 3_600u64);
+    let gossip_fanout = parse_number_env("BULEN_GOSSIP_FANOUT", 8usize);
+    let gossip_queue_size = parse_number_env("BULEN_GOSSIP_QUEUE_SIZE", 256usize).max(1);
+    let gossip_seen_ttl_secs = parse_number_env("BULEN_GOSSIP_SEEN_TTL_SECONDS", 600u64);
+    let p2p_websocket = parse_bool_env("BULEN_P2P_WEBSOCKET", true);
+    let compact_blocks = parse_bool_env("BULEN_COMPACT_BLOCKS", true);
+    let light_client = parse_bool_env("BULEN_LIGHT_CLIENT", node_profile.ends_with("-light"));
+    let light_quorum = parse_number_env("BULEN_LIGHT_QUORUM", 2usize).max(1);
+    let state_root_height = parse_number_env("BULEN_STATE_ROOT_HEIGHT", 1u64);
+    let device_token = env::var("BULEN_DEVICE_TOKEN").ok().filter(|v| !v.is_empty());
+    let low_battery_threshold = parse_number_env("BULEN_SUPERLIGHT_BATTERY_THRESHOLD", 0.15f64);
+    let power_sync_factor = parse_number_env("BULEN_POWER_SYNC_FACTOR", 4u64).max(1);
+    let p2p_session_ttl_secs = parse_number_env("BULEN_P2P_SESSION_TTL_SECONDS", 600u64);
+    let admin_token = env::var("BULEN_ADMIN_TOKEN").ok().filter(|v| !v.is_empty());
+    let merchant_token = env::var("BULEN_MERCHANT_TOKEN").ok().filter(|v| !v.is_empty());
+    let finality_min_depth = parse_number_env("BULEN_FINALITY_MIN_DEPTH", 2u64);
+    let max_batch_outputs = parse_number_env("BULEN_MAX_BATCH_OUTPUTS", 100usize);
+    let batch_fee_per_output = parse_number_env("BULEN_BATCH_FEE_PER_OUTPUT", 1u64);
+    let production = env::var("NODE_ENV").map(|v| v == "production").unwrap_or(false);
+    let webhook_secret = env::var("BULEN_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty());
+    let require_webhook_secret = parse_bool_env("BULEN_REQUIRE_WEBHOOK_SECRET", production);
+    let allow_insecure_webhooks = parse_bool_env("BULEN_ALLOW_INSECURE_WEBHOOKS", !production);
+    let allow_private_webhooks = parse_bool_env("BULEN_ALLOW_PRIVATE_WEBHOOKS", false);
+    let webhook_retry_base_ms = parse_number_env("BULEN_WEBHOOK_RETRY_BASE_MS", 5_000u64).max(1);
+    let webhook_max_attempts = parse_number_env("BULEN_WEBHOOK_MAX_ATTEMPTS", 8usize).max(1);
     let node_role = env::var("BULEN_NODE_ROLE").unwrap_or_else(|_| profile.4.clone());
 
     Config {
@@ -479,8 +1632,16 @@ fn default_config() -> Config {
         block_interval_ms,
         data_dir,
         peers,
+        seed_nodes,
+        public_url,
+        max_active_peers,
+        max_known_peers,
+        allow_private_peers,
         p2p_token,
         require_signatures,
+        allow_legacy_signatures,
+        address_prefix,
+        allow_legacy_addresses,
         enable_faucet,
         rate_limit_window_ms,
         rate_limit_max_requests,
@@ -493,6 +1654,45 @@ fn default_config() -> Config {
         loyalty_boost_steps,
         device_protection_boosts,
         peer_sync_interval_ms,
+        sync_batch_blocks,
+        p2p_range_max_bytes,
+        peer_max_backoff_secs,
+        peer_ban_secs,
+        gossip_fanout,
+        gossip_queue_size,
+        gossip_seen_ttl_secs,
+        p2p_websocket,
+        compact_blocks,
+        light_client,
+        light_quorum,
+        state_root_height,
+        device_token,
+        low_battery_threshold,
+        power_sync_factor,
+        p2p_session_ttl_secs,
+        admin_token,
+        merchant_token,
+        finality_min_depth,
+        max_batch_outputs,
+        batch_fee_per_output,
+        webhook_secret,
+        require_webhook_secret,
+        allow_insecure_webhooks,
+        allow_private_webhooks,
+        webhook_retry_base_ms,
+        webhook_max_attempts,
+    }
+}
+
+/// `bulen` on mainnet, `bulentest`/`bulendev` on test and dev networks, so an
+/// address for one chain is rejected on the others.
+fn default_address_prefix(chain_id: &str) -> String {
+    if chain_id.contains("testnet") {
+        "bulentest".into()
+    } else if chain_id.contains("devnet") {
+        "bulendev".into()
+    } else {
+        "bulen".into()
     }
 }
 
@@ -504,6 +1704,8 @@ fn genesis_block(config: &Config) -> Block {
         timestamp: now_iso(),
         producer: config.node_id.clone(),
         transactions: vec![],
+        state_root: None,
+        reward_claim: None,
     }
 }
 
@@ -525,6 +1727,9 @@ fn initial_state(config: &Config) -> StateData {
         best_tip_hash: Some(genesis.hash.clone()),
         block_weights: HashMap::from([(genesis.hash.clone(), 0.0)]),
         equivocations: HashMap::new(),
+        tx_index: HashMap::new(),
+        address_index: HashMap::new(),
+        undo_log: VecDeque::new(),
     }
 }
 
@@ -532,38 +1737,53 @@ fn payments_file(config: &Config) -> PathBuf {
     config.data_dir.join("payments.json")
 }
 
-fn load_payments(config: &Config) -> Vec<Payment> {
-    if let Ok(content) = std::fs::read_to_string(payments_file(config)) {
-        if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&content) {
-            if let Some(arr) = parsed.get("payments").and_then(|v| v.as_array()) {
-                let mut out = vec![];
-                for item in arr {
-                    if let Ok(p) = serde_json::from_value::<Payment>(item.clone()) {
-                        out.push(p);
-                    }
-                }
-                return out;
+fn load_payments(config: &Config) -> PaymentStore {
+    if let Ok(content) = std::fs::read_to_string(payments_file(config))
+        && let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&content)
+        && let Some(arr) = parsed.get("payments").and_then(|v| v.as_array())
+    {
+        let mut out = vec![];
+        for item in arr {
+            if let Ok(p) = serde_json::from_value::<Payment>(item.clone()) {
+                out.push(p);
             }
         }
+        return PaymentStore::new(out);
     }
-    vec![]
+    PaymentStore::default()
 }
 
-fn save_payments(config: &Config, payments: &[Payment]) {
+fn save_payments(config: &Config, payments: &PaymentStore) {
     std::fs::create_dir_all(&config.data_dir).ok();
-    let data = serde_json::json!({ "payments": payments });
+    let data = serde_json::json!({ "payments": payments.in_created_order() });
     let _ = std::fs::write(payments_file(config), serde_json::to_string_pretty(&data).unwrap());
 }
 
+fn webhooks_file(config: &Config) -> PathBuf {
+    config.data_dir.join("webhooks.json")
+}
+
+fn load_webhooks(config: &Config) -> Vec<WebhookDelivery> {
+    std::fs::read_to_string(webhooks_file(config))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_webhooks(config: &Config, deliveries: &[WebhookDelivery]) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(webhooks_file(config), serde_json::to_string(deliveries).unwrap());
+}
+
 fn wallet_file(config: &Config) -> PathBuf {
     config.data_dir.join("wallet_sessions.json")
 }
 
 fn load_wallet_store(config: &Config) -> WalletStore {
-    if let Ok(content) = std::fs::read_to_string(wallet_file(config)) {
-        if let Ok(parsed) = serde_json::from_str::<WalletStore>(&content) {
-            return parsed;
-        }
+    if let Ok(content) = std::fs::read_to_string(wallet_file(config))
+        && let Ok(parsed) = serde_json::from_str::<WalletStore>(&content)
+    {
+        return parsed;
     }
     WalletStore {
         challenges: vec![],
@@ -576,36 +1796,179 @@ fn save_wallet_store(config: &Config, store: &WalletStore) {
     let _ = std::fs::write(wallet_file(config), serde_json::to_string_pretty(store).unwrap());
 }
 
+fn multisig_file(config: &Config) -> PathBuf {
+    config.data_dir.join("multisig_proposals.json")
+}
+
+fn load_multisig_proposals(config: &Config) -> Vec<MultisigProposal> {
+    if let Ok(content) = std::fs::read_to_string(multisig_file(config))
+        && let Ok(parsed) = serde_json::from_str::<Vec<MultisigProposal>>(&content)
+    {
+        return parsed;
+    }
+    vec![]
+}
+
+fn save_multisig_proposals(config: &Config, proposals: &[MultisigProposal]) {
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(
+        multisig_file(config),
+        serde_json::to_string_pretty(proposals).unwrap(),
+    );
+}
+
+fn load_or_create_identity(config: &Config) -> NodeIdentity {
+    use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let path = config.data_dir.join("node_identity.pem");
+    let key = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|pem| p256::ecdsa::SigningKey::from_pkcs8_pem(&pem).ok())
+        .unwrap_or_else(|| {
+            let key = p256::ecdsa::SigningKey::random(&mut p256::elliptic_curve::rand_core::OsRng);
+            if let Ok(pem) = key.to_pkcs8_pem(LineEnding::LF) {
+                let _ = std::fs::write(&path, pem.as_bytes());
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+                }
+            }
+            key
+        });
+    let public_key = key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .expect("encode identity public key");
+    NodeIdentity { key, public_key }
+}
+
+fn peer_identities_file(config: &Config) -> PathBuf {
+    config.data_dir.join("peer_identities.json")
+}
+
+fn load_p2p_sessions(config: &Config) -> P2pSessions {
+    let content = std::fs::read_to_string(peer_identities_file(config)).unwrap_or_default();
+    let now = now_unix();
+    // Files written before keys expired map node ids straight to keys.
+    let known_keys = serde_json::from_str(&content)
+        .or_else(|_| {
+            serde_json::from_str::<HashMap<String, String>>(&content).map(|keys| {
+                keys.into_iter()
+                    .map(|(id, public_key)| (id, KnownPeerKey { public_key, last_seen: now }))
+                    .collect()
+            })
+        })
+        .unwrap_or_default();
+    P2pSessions {
+        known_keys,
+        ..Default::default()
+    }
+}
+
+/// Writes `peer_identities.json` if it changed, at most once every
+/// `PEER_IDENTITY_SAVE_SECS`.
+fn save_peer_identities(config: &Config, sessions: &mut P2pSessions, now: u64) {
+    if !sessions.identities_dirty || now < sessions.identities_saved_at + PEER_IDENTITY_SAVE_SECS {
+        return;
+    }
+    std::fs::create_dir_all(&config.data_dir).ok();
+    let _ = std::fs::write(
+        peer_identities_file(config),
+        serde_json::to_string_pretty(&sessions.known_keys).unwrap(),
+    );
+    sessions.identities_dirty = false;
+    sessions.identities_saved_at = now;
+}
+
 fn load_state(config: &Config) -> StateData {
     std::fs::create_dir_all(&config.data_dir).ok();
     let path = config.data_dir.join("state.json");
-    if let Ok(content) = std::fs::read_to_string(&path) {
-        if let Ok(state) = serde_json::from_str::<StateData>(&content) {
-            let mut block_store = HashMap::new();
-            let mut block_weights = HashMap::new();
-            let mut cumulative = 0.0;
-            for block in &state.blocks {
-                block_store.insert(block.hash.clone(), block.clone());
-                let stake = state
-                    .accounts
-                    .get(&block.producer)
-                    .map(|a| a.stake as f64)
-                    .unwrap_or(0.0);
-                cumulative += 1.0 + stake;
-                block_weights.insert(block.hash.clone(), cumulative);
-            }
-            return StateData {
-                block_store,
-                block_weights,
-                best_tip_hash: state.blocks.last().map(|b| b.hash.clone()),
-                equivocations: HashMap::new(),
-                ..state
-            };
+    if let Ok(content) = std::fs::read_to_string(&path)
+        && let Ok(mut state) = serde_json::from_str::<StateData>(&content)
+    {
+        migrate_legacy_accounts(config, &mut state);
+        let mut block_store = HashMap::new();
+        let mut block_weights = HashMap::new();
+        let mut tx_index = state.tx_index.clone();
+        let mut address_index = state.address_index.clone();
+        // State files written before the indexes existed: replay the chain so
+        // skipped transactions and block rewards are indexed as `apply_block`
+        // indexes them.
+        if state.blocks.len() > 1 && (tx_index.is_empty() || address_index.is_empty()) {
+            match rebuild_state_from_chain(config, &state.blocks) {
+                Ok(replayed) => {
+                    if tx_index.is_empty() {
+                        tx_index = replayed.tx_index;
+                    }
+                    if address_index.is_empty() {
+                        address_index = replayed.address_index;
+                    }
+                }
+                Err(err) => eprintln!("Could not rebuild indexes from the chain: {}", err),
+            }
+        }
+        let mut cumulative = 0.0;
+        for block in &state.blocks {
+            block_store.insert(block.hash.clone(), block.clone());
+            cumulative += block_weight(&state.accounts, &block.producer);
+            block_weights.insert(block.hash.clone(), cumulative);
         }
+        return StateData {
+            block_store,
+            block_weights,
+            best_tip_hash: state.blocks.last().map(|b| b.hash.clone()),
+            equivocations: HashMap::new(),
+            tx_index,
+            address_index,
+            ..state
+        };
     }
     initial_state(config)
 }
 
+/// Folds accounts and history kept under legacy `addr_`/`msig_` keys into
+/// their checksummed keys. Nonces take the larger of the two.
+fn migrate_legacy_accounts(config: &Config, state: &mut StateData) {
+    let legacy: Vec<String> = state
+        .accounts
+        .keys()
+        .filter(|key| canonical_address(config, key) != **key)
+        .cloned()
+        .collect();
+    for key in legacy {
+        let Some(old) = state.accounts.remove(&key) else {
+            continue;
+        };
+        let account = state
+            .accounts
+            .entry(canonical_address(config, &key))
+            .or_default();
+        account.balance += old.balance;
+        account.stake += old.stake;
+        account.nonce = account.nonce.max(old.nonce);
+        account.reputation += old.reputation;
+        account.locks.extend(old.locks);
+    }
+    let legacy: Vec<String> = state
+        .address_index
+        .keys()
+        .filter(|key| canonical_address(config, key) != **key)
+        .cloned()
+        .collect();
+    for key in legacy {
+        let Some(old) = state.address_index.remove(&key) else {
+            continue;
+        };
+        let entries = state
+            .address_index
+            .entry(canonical_address(config, &key))
+            .or_default();
+        entries.extend(old);
+        entries.sort_by_key(|e| (e.height, e.position.is_none(), e.position));
+    }
+}
+
 fn save_state(config: &Config, state: &StateData) {
     let path = config.data_dir.join("state.json");
     let mut snapshot = state.clone();
@@ -618,120 +1981,647 @@ fn save_state(config: &Config, state: &StateData) {
     }
 }
 
-fn derive_address(public_key: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(public_key.as_bytes());
-    let hash = hasher.finalize();
-    let hex = format!("{:x}", hash);
-    let short = &hex[..40.min(hex.len())];
-    format!("addr_{}", short)
+/// Bech32m encoding of a 20-byte account hash under the chain's address
+/// prefix, e.g. `bulendev1...`.
+fn encode_address(prefix: &str, hash: &[u8]) -> String {
+    let hrp = bech32::Hrp::parse(prefix).expect("address prefix validated in config");
+    bech32::encode::<bech32::Bech32m>(hrp, hash).expect("20-byte hash fits in an address")
 }
 
-fn canonical_payload(tx: &Transaction) -> String {
-    serde_json::json!({
-        "from": tx.from,
-        "to": tx.to,
-        "amount": tx.amount,
-        "fee": tx.fee,
-        "nonce": tx.nonce,
-    })
-    .to_string()
+/// Whether `address` is the checksummed form of `hash` or, while legacy
+/// addresses are accepted, its `<legacy_prefix><hex>` form.
+fn address_matches(config: &Config, address: &str, hash: &[u8], legacy_prefix: &str) -> bool {
+    address == encode_address(&config.address_prefix, hash)
+        || (config.allow_legacy_addresses
+            && address == format!("{}{}", legacy_prefix, hex::encode(hash)))
 }
 
-fn verify_signature(config: &Config, state: &StateData, tx: &Transaction) -> Result<(), String> {
-    if !config.require_signatures {
-        return Ok(());
-    }
-    let public_key = tx.public_key.as_ref().ok_or("missing publicKey")?;
-    let signature_b64 = tx.signature.as_ref().ok_or("missing signature")?;
+/// The hex hash of a legacy `addr_`/`msig_` address, if `address` is one.
+fn legacy_address_hash(address: &str) -> Option<&str> {
+    address
+        .strip_prefix("addr_")
+        .or_else(|| address.strip_prefix("msig_"))
+}
 
-    let expected_address = derive_address(public_key);
-    if tx.from != expected_address {
-        return Err("from does not match publicKey".to_string());
-    }
+/// Account key for `address`: legacy forms map to the checksummed address
+/// of the same hash, so both spellings share one balance and nonce.
+fn canonical_address(config: &Config, address: &str) -> String {
+    legacy_address_hash(address)
+        .and_then(|hex_part| hex::decode(hex_part).ok())
+        .filter(|hash| hash.len() == 20)
+        .map(|hash| encode_address(&config.address_prefix, &hash))
+        .unwrap_or_else(|| address.to_string())
+}
 
-    let signature_bytes = base64::engine::general_purpose::STANDARD
-        .decode(signature_b64.as_bytes())
-        .map_err(|_| "invalid signature encoding")?;
-    let signature = Signature::from_der(&signature_bytes).map_err(|_| "invalid signature")?;
-    let verifying_key =
-        VerifyingKey::from_public_key_pem(public_key).map_err(|_| "invalid publicKey pem")?;
+/// `tx` with its sender and destinations replaced by their account keys.
+/// The original stays in the block, since signatures and the id cover it.
+fn canonical_transaction(config: &Config, tx: &Transaction) -> Transaction {
+    let mut canonical = tx.clone();
+    canonical.from = canonical_address(config, &tx.from);
+    if !tx.to.is_empty() {
+        canonical.to = canonical_address(config, &tx.to);
+    }
+    for output in &mut canonical.outputs {
+        output.to = canonical_address(config, &output.to);
+    }
+    canonical
+}
 
-    verifying_key
-        .verify(canonical_payload(tx).as_bytes(), &signature)
-        .map_err(|_| "signature verification failed")?;
+/// Destination check for user-supplied addresses: a checksummed address for
+/// this chain, or a legacy `addr_`/`msig_` address while
+/// `BULEN_ALLOW_LEGACY_ADDRESSES` is on.
+fn validate_address(config: &Config, address: &str) -> Result<(), String> {
+    if legacy_address_hash(address).is_some() && !config.allow_legacy_addresses {
+        return Err("legacy addresses are no longer accepted".into());
+    }
+    check_address_format(config, address)
+}
 
-    let current_nonce = state.accounts.get(&tx.from).map(|a| a.nonce).unwrap_or(0);
-    if tx.nonce != current_nonce + 1 {
+/// Well-formedness of an address regardless of the legacy policy, so blocks
+/// replay the same on every node.
+fn check_address_format(config: &Config, address: &str) -> Result<(), String> {
+    if let Some(hex_part) = legacy_address_hash(address) {
+        if hex_part.len() != 40
+            || !hex_part.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        {
+            return Err("invalid legacy address".into());
+        }
+        return Ok(());
+    }
+    if address != address.to_lowercase() {
+        return Err("addresses must be lowercase".into());
+    }
+    let checked = bech32::primitives::decode::CheckedHrpstring::new::<bech32::Bech32m>(address)
+        .map_err(|_| "invalid address (bad format or checksum)")?;
+    if checked.hrp().to_string() != config.address_prefix {
         return Err(format!(
-            "invalid nonce: expected {}, got {}",
-            current_nonce + 1,
-            tx.nonce
+            "address prefix must be {} on this chain",
+            config.address_prefix
         ));
     }
-
+    if checked.byte_iter().count() != 20 {
+        return Err("invalid address length".into());
+    }
     Ok(())
 }
 
-fn validate_transaction(state: &StateData, tx: &Transaction) -> Result<(), String> {
-    let action = tx.action.as_str();
-    if !ALLOWED_ACTIONS.contains(&action) {
-        return Err("Invalid action".into());
-    }
-    if tx.amount == 0 {
-        return Err("amount must be > 0".to_string());
+fn tx_destinations(tx: &Transaction) -> Vec<&str> {
+    if tx.action == "batch_transfer" {
+        return tx.outputs.iter().map(|o| o.to.as_str()).collect();
     }
-    if tx.from.is_empty() || tx.to.is_empty() {
-        return Err("missing from/to".to_string());
-    }
-    let from = state.accounts.get(&tx.from).cloned().unwrap_or(Account {
-        balance: 0,
-        stake: 0,
-        nonce: 0,
-        reputation: 0,
-    });
-    if tx.nonce != from.nonce + 1 {
-        return Err(format!(
-            "invalid nonce: expected {}, got {}",
-            from.nonce + 1,
-            tx.nonce
-        ));
+    vec![tx.to.as_str()]
+}
+
+fn validate_destinations(config: &Config, tx: &Transaction) -> Result<(), String> {
+    tx_destinations(tx)
+        .into_iter()
+        .try_for_each(|to| validate_address(config, to))
+}
+
+fn validate_multisig_policy(policy: &MultisigPolicy) -> Result<(), String> {
+    if policy.public_keys.is_empty() || policy.public_keys.len() > MAX_MULTISIG_KEYS {
+        return Err(format!("multisig needs 1-{} public keys", MAX_MULTISIG_KEYS));
     }
-    if action == "transfer" || action == "stake" {
-        let total = tx
-            .amount
-            .checked_add(tx.fee)
-            .ok_or_else(|| "amount overflow".to_string())?;
-        if from.balance < total as i128 {
-            return Err("insufficient balance".to_string());
-        }
+    if policy.threshold == 0 || policy.threshold > policy.public_keys.len() {
+        return Err("invalid multisig threshold".into());
     }
-    if action == "unstake" {
-        if from.stake < tx.amount as i128 {
-            return Err("insufficient stake".to_string());
-        }
-        if from.balance < tx.fee as i128 {
-            return Err("insufficient balance for fee".to_string());
-        }
+    let unique: HashSet<&str> = policy.public_keys.iter().map(|k| k.trim()).collect();
+    if unique.len() != policy.public_keys.len() {
+        return Err("duplicate multisig public key".into());
     }
     Ok(())
 }
 
-fn apply_transaction(state: &mut StateData, tx: &Transaction) -> Result<(), String> {
-    validate_transaction(state, tx)?;
+/// Multisig counterpart of `key_address_hash`; the key order does not matter.
+/// Legacy multisig addresses are `msig_` + hex of this hash.
+fn multisig_address_hash(policy: &MultisigPolicy) -> [u8; 20] {
+    let mut keys: Vec<&str> = policy.public_keys.iter().map(|k| k.trim()).collect();
+    keys.sort_unstable();
+    let canonical = serde_json::json!({
+        "threshold": policy.threshold,
+        "publicKeys": keys,
+    })
+    .to_string();
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.as_bytes());
+    hasher.finalize()[..20].try_into().unwrap()
+}
 
-    state.accounts.entry(tx.from.clone()).or_insert(Account {
-        balance: 0,
+fn derive_multisig_address(config: &Config, policy: &MultisigPolicy) -> String {
+    encode_address(&config.address_prefix, &multisig_address_hash(policy))
+}
+
+/// Account hash for a key of the given scheme. p256 keeps the original
+/// derivation (hash of the PEM text); the other schemes hash a scheme tag plus
+/// the raw key bytes, so one key can never map to the same account under two
+/// schemes. Legacy addresses are `addr_` + hex of this hash.
+fn key_address_hash(key_type: &str, public_key: &str) -> Result<[u8; 20], String> {
+    let raw = match key_type {
+        "p256" => public_key.as_bytes().to_vec(),
+        "ed25519" => parse_ed25519_key(public_key)?.to_bytes().to_vec(),
+        "secp256k1" => parse_secp256k1_key(public_key)?
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec(),
+        other => return Err(format!("unsupported keyType {}", other)),
+    };
+    let mut hasher = Sha256::new();
+    if key_type != "p256" {
+        hasher.update(key_type.as_bytes());
+        hasher.update(b":");
+    }
+    hasher.update(&raw);
+    Ok(hasher.finalize()[..20].try_into().unwrap())
+}
+
+fn derive_address(config: &Config, key_type: &str, public_key: &str) -> Result<String, String> {
+    Ok(encode_address(&config.address_prefix, &key_address_hash(key_type, public_key)?))
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.trim().trim_start_matches("0x")).map_err(|_| "invalid hex encoding".to_string())
+}
+
+/// Ed25519 public keys are 32 bytes, hex encoded.
+fn parse_ed25519_key(public_key: &str) -> Result<ed25519_dalek::VerifyingKey, String> {
+    let bytes: [u8; 32] = decode_hex(public_key)?
+        .try_into()
+        .map_err(|_| "ed25519 publicKey must be 32 bytes")?;
+    ed25519_dalek::VerifyingKey::from_bytes(&bytes).map_err(|_| "invalid ed25519 publicKey".to_string())
+}
+
+/// secp256k1 public keys are SEC1 (compressed or uncompressed), hex encoded.
+fn parse_secp256k1_key(public_key: &str) -> Result<k256::ecdsa::VerifyingKey, String> {
+    k256::ecdsa::VerifyingKey::from_sec1_bytes(&decode_hex(public_key)?)
+        .map_err(|_| "invalid secp256k1 publicKey".to_string())
+}
+
+/// Ethereum `personal_sign` digest of `message`.
+fn eth_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Recovers the signer of a `personal_sign` signature (hex r || s || v).
+fn recover_personal_sign(
+    message: &[u8],
+    signature_hex: &str,
+) -> Result<k256::ecdsa::VerifyingKey, String> {
+    let bytes = decode_hex(signature_hex)?;
+    if bytes.len() != 65 {
+        return Err("personal_sign signature must be 65 bytes".into());
+    }
+    let signature =
+        k256::ecdsa::Signature::from_slice(&bytes[..64]).map_err(|_| "invalid signature")?;
+    let v = bytes[64];
+    let recovery_id = k256::ecdsa::RecoveryId::from_byte(if v >= 27 { v - 27 } else { v })
+        .ok_or("invalid recovery id")?;
+    k256::ecdsa::VerifyingKey::recover_from_prehash(&eth_message_hash(message), &signature, recovery_id)
+        .map_err(|_| "signature recovery failed".to_string())
+}
+
+/// Ethereum address (`0x` + last 20 bytes of keccak256 of the public point).
+fn eth_address(key: &k256::ecdsa::VerifyingKey) -> String {
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    format!("0x{}", hex::encode(&hash[12..]))
+}
+
+/// Verifies `signature` over `message` for any supported scheme. p256 and
+/// ed25519 signatures are base64 (DER and raw respectively); secp256k1 takes
+/// base64 DER/compact ECDSA-SHA256, or a `0x` hex `personal_sign` signature
+/// as produced by MetaMask and Ledger.
+fn verify_scheme_signature(
+    key_type: &str,
+    public_key: &str,
+    message: &[u8],
+    signature: &str,
+) -> Result<(), String> {
+    match key_type {
+        "p256" => verify_p256_signature(public_key, message, signature),
+        "ed25519" => {
+            let key = parse_ed25519_key(public_key)?;
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(signature.as_bytes())
+                .map_err(|_| "invalid signature encoding")?;
+            let signature =
+                ed25519_dalek::Signature::from_slice(&bytes).map_err(|_| "invalid signature")?;
+            key.verify_strict(message, &signature)
+                .map_err(|_| "signature verification failed".to_string())
+        }
+        "secp256k1" => {
+            let key = parse_secp256k1_key(public_key)?;
+            if signature.starts_with("0x") {
+                return if recover_personal_sign(message, signature)? == key {
+                    Ok(())
+                } else {
+                    Err("signature verification failed".into())
+                };
+            }
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(signature.as_bytes())
+                .map_err(|_| "invalid signature encoding")?;
+            let signature = k256::ecdsa::Signature::from_der(&bytes)
+                .or_else(|_| k256::ecdsa::Signature::from_slice(&bytes))
+                .map_err(|_| "invalid signature")?;
+            key.verify(message, &signature)
+                .map_err(|_| "signature verification failed".to_string())
+        }
+        other => Err(format!("unsupported keyType {}", other)),
+    }
+}
+
+fn verify_p256_signature(public_key: &str, message: &[u8], signature_b64: &str) -> Result<(), String> {
+    let signature_bytes = base64::engine::general_purpose::STANDARD
+        .decode(signature_b64.as_bytes())
+        .map_err(|_| "invalid signature encoding")?;
+    let signature = Signature::from_der(&signature_bytes).map_err(|_| "invalid signature")?;
+    let verifying_key =
+        VerifyingKey::from_public_key_pem(public_key).map_err(|_| "invalid publicKey pem")?;
+    verifying_key
+        .verify(message, &signature)
+        .map_err(|_| "signature verification failed".to_string())
+}
+
+/// Checks one co-signer's signature over the transaction payload.
+fn verify_partial_signature(
+    policy: &MultisigPolicy,
+    tx: &Transaction,
+    partial: &PartialSignature,
+) -> Result<(), String> {
+    if !policy
+        .public_keys
+        .iter()
+        .any(|k| k.trim() == partial.public_key.trim())
+    {
+        return Err("public key is not part of the multisig policy".into());
+    }
+    verify_scheme_signature(
+        partial.key_type.as_deref().unwrap_or("p256"),
+        &partial.public_key,
+        canonical_payload(tx).as_bytes(),
+        &partial.signature,
+    )
+}
+
+fn verify_multisig(config: &Config, policy: &MultisigPolicy, tx: &Transaction) -> Result<(), String> {
+    validate_multisig_policy(policy)?;
+    if tx.version == LEGACY_TX_VERSION {
+        return Err("multisig requires signing format v2".into());
+    }
+    if !address_matches(config, &tx.from, &multisig_address_hash(policy), "msig_") {
+        return Err("from does not match multisig policy".into());
+    }
+    let mut signers = HashSet::new();
+    for partial in &tx.signatures {
+        verify_partial_signature(policy, tx, partial)?;
+        signers.insert(partial.public_key.trim());
+    }
+    if signers.len() < policy.threshold {
+        return Err(format!(
+            "not enough signatures: {} of {}",
+            signers.len(),
+            policy.threshold
+        ));
+    }
+    Ok(())
+}
+
+fn canonical_payload(tx: &Transaction) -> String {
+    if tx.version == LEGACY_TX_VERSION {
+        return serde_json::json!({
+            "from": tx.from,
+            "to": tx.to,
+            "amount": tx.amount,
+            "fee": tx.fee,
+            "nonce": tx.nonce,
+        })
+        .to_string();
+    }
+    let mut payload = serde_json::json!({
+        "version": tx.version,
+        "chainId": tx.chain_id,
+        "from": tx.from,
+        "to": tx.to,
+        "amount": tx.amount,
+        "fee": tx.fee,
+        "nonce": tx.nonce,
+        "action": tx.action,
+        "memo": tx.memo,
+        "expiresAtHeight": tx.expires_at_height,
+    });
+    if let Some(lock) = &tx.lock {
+        payload["lock"] = serde_json::json!({
+            "unit": lock.unit,
+            "start": lock.start,
+            "end": lock.end,
+            "cliff": lock.cliff,
+        });
+    }
+    if tx.action == "batch_transfer" {
+        payload["outputs"] = tx
+            .outputs
+            .iter()
+            .map(|o| serde_json::json!({ "to": o.to, "amount": o.amount, "memo": o.memo }))
+            .collect();
+    }
+    payload.to_string()
+}
+
+/// Transaction ids are the hash of the signed payload, so the same signed
+/// transaction gets the same id on every node (and in the SDK).
+fn compute_transaction_id(tx: &Transaction) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(canonical_payload(tx).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn is_known_transaction(state: &StateData, id: &str) -> bool {
+    state.tx_index.contains_key(id) || state.mempool.iter().any(|t| t.id == id)
+}
+
+/// Signature and key checks only; the nonce is a state check left to
+/// `validate_transaction`, so a stale nonce is not mistaken for a forgery.
+fn verify_signature(config: &Config, tx: &Transaction) -> Result<(), String> {
+    if !config.require_signatures {
+        return Ok(());
+    }
+    if let Some(policy) = &tx.multisig {
+        verify_multisig(config, policy, tx)?;
+    } else {
+        let public_key = tx.public_key.as_ref().ok_or("missing publicKey")?;
+        let signature_b64 = tx.signature.as_ref().ok_or("missing signature")?;
+
+        let key_type = tx.key_type.as_deref().unwrap_or("p256");
+        let hash = key_address_hash(key_type, public_key)?;
+        if !address_matches(config, &tx.from, &hash, "addr_") {
+            return Err("from does not match publicKey".to_string());
+        }
+        verify_scheme_signature(
+            key_type,
+            public_key,
+            canonical_payload(tx).as_bytes(),
+            signature_b64,
+        )?;
+    }
+    Ok(())
+}
+
+/// Signing-format checks: version support, chain id replay protection and
+/// expiry against the height of the next block.
+fn validate_envelope(config: &Config, state: &StateData, tx: &Transaction) -> Result<(), String> {
+    match tx.version {
+        TX_VERSION => {
+            if tx.chain_id.as_deref() != Some(config.chain_id.as_str()) {
+                return Err("chain id mismatch".into());
+            }
+        }
+        LEGACY_TX_VERSION => {}
+        other => return Err(format!("unsupported transaction version {}", other)),
+    }
+    if let Some(expires_at_height) = tx.expires_at_height {
+        let next_height = state.blocks.len() as u64;
+        if next_height > expires_at_height {
+            return Err("transaction expired".into());
+        }
+    }
+    Ok(())
+}
+
+/// Mempool admission policy for the signing format. Blocks are not held to
+/// it, so history with legacy transactions replays the same on every node
+/// whatever `BULEN_ALLOW_LEGACY_SIGNATURES` says.
+fn admit_signing_format(config: &Config, tx: &Transaction) -> Result<(), String> {
+    if tx.version == LEGACY_TX_VERSION && !config.allow_legacy_signatures {
+        return Err("legacy transaction format not accepted".into());
+    }
+    Ok(())
+}
+
+fn validate_batch_outputs(config: &Config, tx: &Transaction) -> Result<(), String> {
+    if tx.version == LEGACY_TX_VERSION {
+        return Err("batch_transfer requires signing format v2".into());
+    }
+    if tx.outputs.is_empty() {
+        return Err("batch_transfer needs at least one output".into());
+    }
+    if tx.outputs.len() > config.max_batch_outputs {
+        return Err(format!(
+            "too many outputs (max {})",
+            config.max_batch_outputs
+        ));
+    }
+    let mut total: u64 = 0;
+    for output in &tx.outputs {
+        if output.to.is_empty() || output.amount == 0 {
+            return Err("each output needs a destination and amount > 0".into());
+        }
+        if output.memo.as_ref().is_some_and(|m| m.chars().count() > 256) {
+            return Err("memo too long (max 256 chars)".into());
+        }
+        total = total
+            .checked_add(output.amount)
+            .ok_or_else(|| "amount overflow".to_string())?;
+    }
+    if tx.amount != total {
+        return Err("amount must equal the sum of outputs".into());
+    }
+    let min_fee = config
+        .batch_fee_per_output
+        .saturating_mul(tx.outputs.len() as u64);
+    if tx.fee < min_fee {
+        return Err(format!("fee too low for {} outputs (min {})", tx.outputs.len(), min_fee));
+    }
+    Ok(())
+}
+
+/// Height of the next block and timestamp of the latest one; the clock that
+/// lock schedules are evaluated against.
+fn chain_clock(state: &StateData) -> (u64, u64) {
+    let height = state.blocks.len() as u64;
+    let time = state
+        .blocks
+        .last()
+        .and_then(|b| parse_rfc3339(&b.timestamp))
+        .map(|dt| dt.unix_timestamp().max(0) as u64)
+        .unwrap_or(0);
+    (height, time)
+}
+
+fn unlocked_amount(lock: &BalanceLock, clock: (u64, u64)) -> u64 {
+    let schedule = &lock.schedule;
+    let now = if schedule.unit == "height" { clock.0 } else { clock.1 };
+    if now < schedule.cliff.unwrap_or(schedule.start) {
+        return 0;
+    }
+    if now >= schedule.end {
+        return lock.amount;
+    }
+    let elapsed = now.saturating_sub(schedule.start) as u128;
+    let span = (schedule.end - schedule.start) as u128;
+    (lock.amount as u128 * elapsed / span) as u64
+}
+
+fn locked_balance(account: &Account, clock: (u64, u64)) -> i128 {
+    account
+        .locks
+        .iter()
+        .map(|l| (l.amount - unlocked_amount(l, clock)) as i128)
+        .sum()
+}
+
+fn validate_lock_schedule(tx: &Transaction, schedule: &LockSchedule) -> Result<(), String> {
+    if tx.action != "transfer" || tx.from == tx.to {
+        return Err("lock is only allowed on transfers to another address".into());
+    }
+    if tx.version == LEGACY_TX_VERSION {
+        return Err("lock requires signing format v2".into());
+    }
+    if schedule.unit != "height" && schedule.unit != "time" {
+        return Err("lock unit must be height or time".into());
+    }
+    if schedule.start > schedule.end {
+        return Err("lock start must not be after end".into());
+    }
+    if let Some(cliff) = schedule.cliff
+        && (cliff < schedule.start || cliff > schedule.end)
+    {
+        return Err("lock cliff must be between start and end".into());
+    }
+    Ok(())
+}
+
+/// Whether `chain_id` names a test network, the only ones with a faucet.
+fn is_faucet_chain(chain_id: &str) -> bool {
+    chain_id.contains("testnet") || chain_id.contains("devnet")
+}
+
+/// Faucet credits mint coins, so blocks carry them like any transaction and
+/// only test networks accept them.
+fn validate_faucet_transaction(
+    config: &Config,
+    state: &StateData,
+    tx: &Transaction,
+) -> Result<(), String> {
+    if !is_faucet_chain(&config.chain_id) {
+        return Err("faucet transactions are only valid on test networks".into());
+    }
+    if tx.from != FAUCET_ACCOUNT
+        || tx.amount == 0
+        || tx.fee != 0
+        || !tx.outputs.is_empty()
+        || tx.lock.is_some()
+        || tx.multisig.is_some()
+    {
+        return Err("invalid faucet transaction".into());
+    }
+    check_address_format(config, &tx.to)?;
+    let nonce = state.accounts.get(FAUCET_ACCOUNT).map(|a| a.nonce).unwrap_or(0);
+    if tx.nonce != nonce + 1 {
+        return Err(format!("invalid nonce: expected {}, got {}", nonce + 1, tx.nonce));
+    }
+    Ok(())
+}
+
+/// Faucet credits enter the mempool only through `/api/faucet`: on this node,
+/// or `relayed` from the peer that served it on a test network.
+fn admit_action(config: &Config, tx: &Transaction, relayed: bool) -> Result<(), String> {
+    if tx.action == "faucet" && !(relayed && is_faucet_chain(&config.chain_id)) {
+        return Err("faucet transactions are only created by the faucet".into());
+    }
+    Ok(())
+}
+
+fn validate_transaction(config: &Config, state: &StateData, tx: &Transaction) -> Result<(), String> {
+    validate_envelope(config, state, tx)?;
+    let action = tx.action.as_str();
+    if action == "faucet" {
+        return validate_faucet_transaction(config, state, tx);
+    }
+    if !ALLOWED_ACTIONS.contains(&action) {
+        return Err("Invalid action".into());
+    }
+    if tx.amount == 0 {
+        return Err("amount must be > 0".to_string());
+    }
+    if action == "batch_transfer" {
+        validate_batch_outputs(config, tx)?;
+    } else if !tx.outputs.is_empty() {
+        return Err("outputs are only allowed for batch_transfer".into());
+    }
+    if tx.from.is_empty() || (tx.to.is_empty() && action != "batch_transfer") {
+        return Err("missing from/to".to_string());
+    }
+    if action != "stake" && action != "unstake" {
+        tx_destinations(tx)
+            .into_iter()
+            .try_for_each(|to| check_address_format(config, to))?;
+    }
+    let tx = &canonical_transaction(config, tx);
+    if let Some(schedule) = &tx.lock {
+        validate_lock_schedule(tx, schedule)?;
+    }
+    let from = state.accounts.get(&tx.from).cloned().unwrap_or(Account {
+        balance: 0,
         stake: 0,
         nonce: 0,
         reputation: 0,
+        locks: Vec::new(),
     });
-    state.accounts.entry(tx.to.clone()).or_insert(Account {
+    if tx.nonce != from.nonce + 1 {
+        return Err(format!(
+            "invalid nonce: expected {}, got {}",
+            from.nonce + 1,
+            tx.nonce
+        ));
+    }
+    let spendable = from.balance - locked_balance(&from, chain_clock(state));
+    if action == "transfer" || action == "stake" || action == "batch_transfer" {
+        let total = tx
+            .amount
+            .checked_add(tx.fee)
+            .ok_or_else(|| "amount overflow".to_string())?;
+        if spendable < total as i128 {
+            return Err("insufficient balance".to_string());
+        }
+    }
+    if action == "unstake" {
+        if from.stake < tx.amount as i128 {
+            return Err("insufficient stake".to_string());
+        }
+        if spendable < tx.fee as i128 {
+            return Err("insufficient balance for fee".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn apply_transaction(config: &Config, state: &mut StateData, tx: &Transaction) -> Result<(), String> {
+    validate_transaction(config, state, tx)?;
+    let tx = &canonical_transaction(config, tx);
+    let clock = chain_clock(state);
+    if let Some(from) = state.accounts.get_mut(&tx.from) {
+        from.locks.retain(|l| unlocked_amount(l, clock) < l.amount);
+    }
+
+    state.accounts.entry(tx.from.clone()).or_insert(Account {
         balance: 0,
         stake: 0,
         nonce: 0,
         reputation: 0,
+        locks: Vec::new(),
     });
+    if !tx.to.is_empty() {
+        state.accounts.entry(tx.to.clone()).or_insert(Account {
+            balance: 0,
+            stake: 0,
+            nonce: 0,
+            reputation: 0,
+            locks: Vec::new(),
+        });
+    }
 
     if tx.from == tx.to && tx.action == "transfer" {
         let from = state.accounts.get_mut(&tx.from).expect("sender exists");
@@ -750,6 +2640,13 @@ fn apply_transaction(state: &mut StateData, tx: &Transaction) -> Result<(), Stri
             }
             let to = state.accounts.get_mut(&tx.to).expect("receiver exists");
             to.balance += tx.amount as i128;
+            if let Some(schedule) = &tx.lock {
+                to.locks.push(BalanceLock {
+                    tx_id: tx.id.clone(),
+                    amount: tx.amount,
+                    schedule: schedule.clone(),
+                });
+            }
         }
         "stake" => {
             let total = tx.amount + tx.fee;
@@ -765,12 +2662,81 @@ fn apply_transaction(state: &mut StateData, tx: &Transaction) -> Result<(), Stri
             from.balance -= tx.fee as i128;
             from.nonce += 1;
         }
+        "faucet" => {
+            state.accounts.get_mut(&tx.from).expect("faucet exists").nonce += 1;
+            let to = state.accounts.get_mut(&tx.to).expect("receiver exists");
+            to.balance += tx.amount as i128;
+        }
+        "batch_transfer" => {
+            // Validated above, so every output can be credited without failing.
+            let total = tx.amount + tx.fee;
+            {
+                let from = state.accounts.get_mut(&tx.from).expect("sender exists");
+                from.balance -= total as i128;
+                from.nonce += 1;
+            }
+            for output in &tx.outputs {
+                let to = state.accounts.entry(output.to.clone()).or_insert(Account {
+                    balance: 0,
+                    stake: 0,
+                    nonce: 0,
+                    reputation: 0,
+                    locks: Vec::new(),
+                });
+                to.balance += output.amount as i128;
+            }
+        }
         _ => {}
     }
     Ok(())
 }
 
+fn index_transaction(
+    index: &mut HashMap<String, Vec<AddressEntry>>,
+    height: u64,
+    position: usize,
+    tx: &Transaction,
+) {
+    let mut push = |address: &str, direction: &str| {
+        index.entry(address.to_string()).or_default().push(AddressEntry {
+            height,
+            position: Some(position),
+            direction: direction.to_string(),
+            reward: None,
+        });
+    };
+    if tx.action == "batch_transfer" {
+        push(&tx.from, "out");
+        let mut seen = HashSet::new();
+        for output in &tx.outputs {
+            if output.to != tx.from && seen.insert(output.to.as_str()) {
+                push(&output.to, "in");
+            }
+        }
+    } else if tx.from == tx.to {
+        push(&tx.from, "self");
+    } else {
+        push(&tx.from, "out");
+        push(&tx.to, "in");
+    }
+}
+
+/// Applies a received block, which from `state_root_height` on must commit
+/// to the state it produces.
 fn apply_block(config: &Config, state: &mut StateData, block: &Block) -> Result<(), String> {
+    if block.state_root.is_none() && block.index >= config.state_root_height {
+        return Err("missing state root".to_string());
+    }
+    apply_block_contents(config, state, block)
+}
+
+/// Applies `block`'s transactions and reward, checking its `state_root` when
+/// set. Producers call it on a block without one to compute the root.
+fn apply_block_contents(
+    config: &Config,
+    state: &mut StateData,
+    block: &Block,
+) -> Result<(), String> {
     let expected_index = state.blocks.len() as u64;
     let prev_hash = state
         .blocks
@@ -783,18 +2749,46 @@ fn apply_block(config: &Config, state: &mut StateData, block: &Block) -> Result<
     if block.previous_hash != prev_hash {
         return Err("previous hash mismatch".to_string());
     }
-    for tx in &block.transactions {
-        if let Err(err) = apply_transaction(state, tx) {
+    if block.transactions.iter().any(|tx| tx.id != compute_transaction_id(tx)) {
+        return Err("transaction id does not match payload".to_string());
+    }
+    let canonical: Vec<Transaction> = block
+        .transactions
+        .iter()
+        .map(|tx| canonical_transaction(config, tx))
+        .collect();
+    let mut touched: Vec<&String> = vec![&block.producer];
+    for tx in &canonical {
+        touched.push(&tx.from);
+        touched.push(&tx.to);
+        touched.extend(tx.outputs.iter().map(|o| &o.to));
+    }
+    touched.sort();
+    touched.dedup();
+    let undo_accounts: Vec<(String, Option<Account>)> = touched
+        .into_iter()
+        .map(|address| (address.clone(), state.accounts.get(address).cloned()))
+        .collect();
+    for (position, tx) in canonical.iter().enumerate() {
+        if state.tx_index.contains_key(&tx.id) {
+            eprintln!("Skipping duplicate tx {} in block {}", tx.id, block.index);
+            continue;
+        }
+        if let Err(err) = apply_transaction(config, state, tx) {
             eprintln!("Skipping invalid tx {} in block {}: {}", tx.id, block.index, err);
             continue;
         }
+        state.tx_index.insert(
+            tx.id.clone(),
+            TxLocation {
+                height: block.index,
+                position,
+            },
+        );
+        index_transaction(&mut state.address_index, block.index, position, tx);
     }
     // Reward producer with uptime-based reward and reputation bump
-    let uptime_seconds = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(state.started_at))
-        .unwrap_or_default()
-        .as_secs();
-    let reward = reward_per_block(config, uptime_seconds);
+    let reward = block_reward(config, state, block);
     let producer = state
         .accounts
         .entry(block.producer.clone())
@@ -803,49 +2797,157 @@ fn apply_block(config: &Config, state: &mut StateData, block: &Block) -> Result<
             stake: 0,
             nonce: 0,
             reputation: 0,
+            locks: Vec::new(),
         });
     producer.balance += reward as i128;
     producer.reputation += 1;
     state.produced_rewards += reward;
+    state
+        .address_index
+        .entry(block.producer.clone())
+        .or_default()
+        .push(AddressEntry {
+            height: block.index,
+            position: None,
+            direction: "in".into(),
+            reward: Some(reward as i128),
+        });
+    state.undo_log.push_back(BlockUndo {
+        height: block.index,
+        accounts: undo_accounts,
+        reward,
+    });
+    if let Some(root) = &block.state_root
+        && *root != account_state_root(&state.accounts)
+    {
+        let undo = state.undo_log.pop_back().expect("pushed above");
+        revert_block(state, block, undo);
+        return Err("state root mismatch".to_string());
+    }
+    while state.undo_log.len() as u64 > MAX_REORG_DEPTH {
+        state.undo_log.pop_front();
+    }
     Ok(())
 }
 
-fn normalize_memo(memo: &Option<String>) -> Option<String> {
-    memo.as_ref().map(|m| m.chars().take(256).collect())
+/// Undoes main-chain blocks above `height` from the undo log, returning
+/// their applied transactions to the mempool. Fails without changing
+/// anything when the log does not reach back that far.
+fn rollback_to(state: &mut StateData, height: u64) -> Result<(), String> {
+    if !can_roll_back_to(state, height) {
+        return Err(format!("rollback to {} exceeds undo history", height));
+    }
+    while state.blocks.last().is_some_and(|b| b.index > height) {
+        let block = state.blocks.pop().expect("checked above");
+        let undo = state.undo_log.pop_back().expect("checked above");
+        for tx in revert_block(state, &block, undo) {
+            if !state.mempool.iter().any(|t| t.id == tx.id) {
+                state.mempool.push(tx);
+            }
+        }
+        state.block_weights.remove(&block.hash);
+    }
+    state.best_tip_hash = state.blocks.last().map(|b| b.hash.clone());
+    Ok(())
 }
 
-fn find_matching_transaction(payment: &Payment, state: &StateData) -> Option<(String, Option<u64>)> {
-    let match_fn = |tx: &Transaction| -> bool {
-        tx.to == payment.to
-            && tx.amount >= payment.amount
-            && match &payment.memo {
-                Some(memo) => tx
-                    .memo
-                    .as_ref()
-                    .map(|m| m == memo)
-                    .unwrap_or(false),
-                None => true,
+/// Whether the undo log reaches back from the tip to `height`.
+fn can_roll_back_to(state: &StateData, height: u64) -> bool {
+    let tip = state.blocks.last().map(|b| b.index).unwrap_or(0);
+    state
+        .undo_log
+        .iter()
+        .rev()
+        .map(|u| u.height)
+        .take_while(|h| *h > height)
+        .eq((height + 1..=tip).rev())
+}
+
+/// Restores the accounts and indexes `block` changed, from its undo record,
+/// and returns the transactions it had applied.
+fn revert_block(state: &mut StateData, block: &Block, undo: BlockUndo) -> Vec<Transaction> {
+    for (address, previous) in undo.accounts {
+        if let Some(entries) = state.address_index.get_mut(&address) {
+            entries.retain(|e| e.height != block.index);
+            if entries.is_empty() {
+                state.address_index.remove(&address);
             }
-    };
-    for block in &state.blocks {
-        if let Some(tx) = block.transactions.iter().find(|t| match_fn(t)) {
-            return Some((tx.id.clone(), Some(block.index)));
         }
+        match previous {
+            Some(account) => state.accounts.insert(address, account),
+            None => state.accounts.remove(&address),
+        };
     }
-    for tx in &state.mempool {
-        if match_fn(tx) {
-            return Some((tx.id.clone(), None));
+    state.produced_rewards -= undo.reward;
+    let mut applied = Vec::new();
+    for tx in &block.transactions {
+        if state.tx_index.get(&tx.id).is_some_and(|loc| loc.height == block.index) {
+            state.tx_index.remove(&tx.id);
+            applied.push(tx.clone());
         }
     }
-    None
+    applied
 }
 
-fn update_payment_status(payment: &mut Payment, state: &StateData) {
-    let now = OffsetDateTime::now_utc();
-    let expired = parse_rfc3339(&payment.expires_at)
-        .map(|dt| dt < now)
-        .unwrap_or(false);
-    let match_tx = find_matching_transaction(payment, state);
+/// Longest memo (and payment link label) in chars; the SDK uses the same
+/// limit.
+const MAX_MEMO_CHARS: usize = 256;
+
+fn normalize_memo(memo: &Option<String>) -> Option<String> {
+    memo.as_ref().map(|m| m.chars().take(MAX_MEMO_CHARS).collect())
+}
+
+fn find_matching_transaction(
+    config: &Config,
+    payment: &Payment,
+    state: &StateData,
+) -> Option<(String, Option<u64>)> {
+    let leg_matches = |to: &str, amount: u64, memo: Option<&String>| -> bool {
+        canonical_address(config, to) == payment.to
+            && amount >= payment.amount
+            && match &payment.memo {
+                Some(expected) => memo.map(|m| m == expected).unwrap_or(false),
+                None => true,
+            }
+    };
+    // A batch pays the invoice if any single output does; an output without
+    // its own memo inherits the transaction memo.
+    let match_fn = |tx: &Transaction| -> bool {
+        if tx.action == "batch_transfer" {
+            tx.outputs
+                .iter()
+                .any(|o| leg_matches(&o.to, o.amount, o.memo.as_ref().or(tx.memo.as_ref())))
+        } else {
+            leg_matches(&tx.to, tx.amount, tx.memo.as_ref())
+        }
+    };
+    // Only the destination's applied transactions, via its address index.
+    let entries = state.address_index.get(&payment.to).map(Vec::as_slice).unwrap_or(&[]);
+    for entry in entries {
+        let tx = entry.position.and_then(|position| {
+            state.blocks.get(entry.height as usize)?.transactions.get(position)
+        });
+        if let Some(tx) = tx.filter(|t| match_fn(t)) {
+            return Some((tx.id.clone(), Some(entry.height)));
+        }
+    }
+    for tx in &state.mempool {
+        if match_fn(tx) {
+            return Some((tx.id.clone(), None));
+        }
+    }
+    None
+}
+
+fn update_payment_status(config: &Config, payment: &mut Payment, state: &StateData) {
+    if payment.status == "cancelled" {
+        return;
+    }
+    let now = OffsetDateTime::now_utc();
+    let expired = parse_rfc3339(&payment.expires_at)
+        .map(|dt| dt < now)
+        .unwrap_or(false);
+    let match_tx = find_matching_transaction(config, payment, state);
     match (match_tx, expired) {
         (Some((tx_id, Some(block_idx))), _) => {
             payment.status = "paid".into();
@@ -864,12 +2966,17 @@ fn update_payment_status(payment: &mut Payment, state: &StateData) {
     }
 }
 
+/// Fork-choice weight of a block: one plus its producer's stake.
+fn block_weight(accounts: &HashMap<String, Account>, producer: &str) -> f64 {
+    1.0 + accounts.get(producer).map(|a| a.stake as f64).unwrap_or(0.0)
+}
+
 fn build_chain_from_tip(store: &HashMap<String, Block>, tip_hash: &str) -> Option<Vec<Block>> {
     let mut chain = Vec::new();
     let mut current_hash = tip_hash.to_string();
     while let Some(block) = store.get(&current_hash) {
         chain.push(block.clone());
-        if block.previous_hash == "genesis" || block.index == 0 {
+        if block.index == 0 {
             break;
         }
         current_hash = block.previous_hash.clone();
@@ -878,11 +2985,7 @@ fn build_chain_from_tip(store: &HashMap<String, Block>, tip_hash: &str) -> Optio
     Some(chain)
 }
 
-fn rebuild_state_from_chain(
-    config: &Config,
-    chain: &[Block],
-    base_accounts: &HashMap<String, Account>,
-) -> Result<StateData, String> {
+fn rebuild_state_from_chain(config: &Config, chain: &[Block]) -> Result<StateData, String> {
     if chain.is_empty() {
         return Err("empty chain".into());
     }
@@ -890,7 +2993,6 @@ fn rebuild_state_from_chain(
         return Err("missing genesis".into());
     }
     let mut state = initial_state(config);
-    state.accounts = base_accounts.clone();
     state.blocks.clear();
     state.block_store.clear();
     state.produced_blocks = 0;
@@ -909,15 +3011,8 @@ fn rebuild_state_from_chain(
         if block.previous_hash != prev.hash {
             return Err("chain linkage invalid".into());
         }
-        if let Err(err) = apply_block(config, &mut state, block) {
-            return Err(err);
-        }
-        let stake = state
-            .accounts
-            .get(&block.producer)
-            .map(|a| a.stake as f64)
-            .unwrap_or(0.0);
-        cumulative += 1.0 + stake;
+        apply_block(config, &mut state, block)?;
+        cumulative += block_weight(&state.accounts, &block.producer);
         state.block_weights.insert(block.hash.clone(), cumulative);
         state.blocks.push(block.clone());
         state.block_store.insert(block.hash.clone(), block.clone());
@@ -946,6 +3041,10 @@ async fn rate_limit_middleware(
     next.run(request).await
 }
 
+/// Network-level checks shared by every `/p2p/*` route: the optional shared
+/// `BULEN_P2P_TOKEN` and protocol major version. Peer identity comes from the
+/// handshake session (`verify_peer_session`).
+#[allow(clippy::result_large_err)]
 fn verify_p2p_headers(config: &Config, headers: &HeaderMap) -> Result<(), Response> {
     if let Some(expected) = &config.p2p_token {
         let token = headers
@@ -956,9 +3055,6 @@ fn verify_p2p_headers(config: &Config, headers: &HeaderMap) -> Result<(), Respon
             return Err((StatusCode::FORBIDDEN, "Forbidden").into_response());
         }
     }
-    if headers.get("x-bulen-node-id").is_none() {
-        return Err((StatusCode::BAD_REQUEST, "Missing node id").into_response());
-    }
     if let Some(remote) = headers
         .get("x-bulen-protocol-version")
         .and_then(|v| v.to_str().ok())
@@ -972,6 +3068,521 @@ fn verify_p2p_headers(config: &Config, headers: &HeaderMap) -> Result<(), Respon
     Ok(())
 }
 
+/// Handshakes older or newer than this (by the signer's clock) are rejected.
+const HANDSHAKE_MAX_SKEW_SECS: u64 = 120;
+/// Most node ids with a pinned identity key; the least recently seen is
+/// dropped to make room.
+const MAX_KNOWN_PEER_KEYS: usize = 4_096;
+const PEER_KEY_TTL_SECS: u64 = 30 * 24 * 3_600;
+const PEER_IDENTITY_SAVE_SECS: u64 = 60;
+/// Most live inbound sessions; the one closest to expiry is dropped first.
+const MAX_INBOUND_SESSIONS: usize = 4_096;
+/// Upper bound on peers returned by, or accepted from, one `/p2p/peers` call.
+const MAX_SHARED_PEERS: usize = 64;
+/// Upper bound on blocks returned by one `/p2p/blocks` call.
+const MAX_RANGE_BLOCKS: u64 = 1_000;
+/// Range requests kept in flight per peer during sync.
+const SYNC_PIPELINE_DEPTH: usize = 2;
+/// Upper bound on headers returned by one `/p2p/headers` call.
+const MAX_RANGE_HEADERS: u64 = 2_000;
+/// Deepest rollback a sync-time reorg may perform; undo data is kept for this
+/// many blocks.
+const MAX_REORG_DEPTH: u64 = 500;
+
+fn genesis_hash(state: &StateData) -> String {
+    state.blocks.first().map(|b| b.hash.clone()).unwrap_or_default()
+}
+
+/// Message both sides sign during the handshake. The responder signs the
+/// initiator's nonce, so each side proves possession of its identity key for
+/// this exchange.
+fn handshake_payload(
+    node_id: &str,
+    chain_id: &str,
+    genesis_hash: &str,
+    nonce: &str,
+    timestamp: u64,
+) -> String {
+    format!(
+        "bulen-p2p-handshake|{}|{}|{}|{}|{}",
+        node_id, chain_id, genesis_hash, nonce, timestamp
+    )
+}
+
+fn sign_with_identity(identity: &NodeIdentity, message: &str) -> String {
+    use p256::ecdsa::signature::Signer;
+    let signature: Signature = identity.key.sign(message.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(signature.to_der().as_bytes())
+}
+
+/// Pins `public_key` to `node_id` on first contact; later handshakes for the
+/// same node id must use the same key.
+fn check_peer_identity(
+    config: &Config,
+    sessions: &mut P2pSessions,
+    node_id: &str,
+    public_key: &str,
+    now: u64,
+) -> Result<(), String> {
+    match sessions.known_keys.get_mut(node_id) {
+        Some(known) if known.public_key.trim() == public_key.trim() => {
+            known.last_seen = now;
+            return Ok(());
+        }
+        Some(_) => return Err("node id is bound to a different identity key".into()),
+        None => {}
+    }
+    sessions
+        .known_keys
+        .retain(|_, known| known.last_seen + PEER_KEY_TTL_SECS > now);
+    if sessions.known_keys.len() >= MAX_KNOWN_PEER_KEYS
+        && let Some(oldest) = sessions
+            .known_keys
+            .iter()
+            .min_by_key(|(_, known)| known.last_seen)
+            .map(|(id, _)| id.clone())
+    {
+        sessions.known_keys.remove(&oldest);
+    }
+    sessions.known_keys.insert(
+        node_id.to_string(),
+        KnownPeerKey {
+            public_key: public_key.to_string(),
+            last_seen: now,
+        },
+    );
+    sessions.identities_dirty = true;
+    save_peer_identities(config, sessions, now);
+    Ok(())
+}
+
+/// Authenticates a `/p2p/*` request by its handshake session
+/// (`x-bulen-peer-id` + `x-bulen-peer-session`). Unknown or expired sessions
+/// get 401 so the caller knows to handshake again.
+#[allow(clippy::result_large_err)]
+fn verify_peer_session(app: &AppState, headers: &HeaderMap) -> Result<PeerSession, Response> {
+    verify_p2p_headers(&app.config, headers)?;
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    };
+    let (peer_id, token) = (header("x-bulen-peer-id"), header("x-bulen-peer-session"));
+    let mut sessions = app.p2p_sessions.lock().unwrap();
+    let now = now_unix();
+    if peer_banned(&sessions.penalties, &peer_id, now) {
+        return Err((StatusCode::FORBIDDEN, "Peer is banned").into_response());
+    }
+    sessions.inbound.retain(|_, s| s.expires_at > now);
+    match sessions.inbound.get(&token) {
+        Some(session) if peer_banned(&sessions.penalties, &session.remote_ip, now) => {
+            Err((StatusCode::FORBIDDEN, "Peer is banned").into_response())
+        }
+        Some(session) if !peer_id.is_empty() && session.node_id == peer_id => {
+            let session = session.clone();
+            drop(sessions);
+            Ok(session)
+        }
+        _ => Err((StatusCode::UNAUTHORIZED, "Invalid or expired peer session").into_response()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HandshakeInput {
+    node_id: String,
+    chain_id: String,
+    genesis_hash: String,
+    nonce: String,
+    timestamp: u64,
+    public_key: String,
+    signature: String,
+    /// Where the initiator accepts connections; added to the address book.
+    #[serde(default)]
+    listen_url: Option<String>,
+    /// Transports the initiator supports ("ws", "http").
+    #[serde(default)]
+    transports: Vec<String>,
+}
+
+async fn p2p_handshake(
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<HandshakeInput>,
+) -> Response {
+    if let Err(resp) = verify_p2p_headers(&app.config, &headers) {
+        return resp;
+    }
+    if input.node_id.is_empty() || input.nonce.len() < 16 {
+        return (StatusCode::BAD_REQUEST, "Missing handshake parameters").into_response();
+    }
+    if input.chain_id != app.config.chain_id {
+        return (StatusCode::BAD_REQUEST, "chain id mismatch").into_response();
+    }
+    let genesis = genesis_hash(&app.state.read().unwrap());
+    if input.genesis_hash != genesis {
+        return (StatusCode::BAD_REQUEST, "genesis hash mismatch").into_response();
+    }
+    let now = now_unix();
+    if now.abs_diff(input.timestamp) > HANDSHAKE_MAX_SKEW_SECS {
+        return (StatusCode::BAD_REQUEST, "handshake timestamp out of range").into_response();
+    }
+    let payload = handshake_payload(
+        &input.node_id,
+        &input.chain_id,
+        &input.genesis_hash,
+        &input.nonce,
+        input.timestamp,
+    );
+    if verify_p256_signature(&input.public_key, payload.as_bytes(), &input.signature).is_err() {
+        return (StatusCode::FORBIDDEN, "Invalid handshake signature").into_response();
+    }
+
+    let token = format!("psess_{}", Uuid::new_v4().as_simple());
+    let expires_at = now + app.config.p2p_session_ttl_secs;
+    {
+        let mut sessions = app.p2p_sessions.lock().unwrap();
+        if peer_banned(&sessions.penalties, &input.node_id, now)
+            || peer_banned(&sessions.penalties, &addr.ip().to_string(), now)
+        {
+            return (StatusCode::FORBIDDEN, "Peer is banned").into_response();
+        }
+        sessions.seen_nonces.retain(|_, expiry| *expiry > now);
+        if sessions.seen_nonces.contains_key(&input.nonce) {
+            return (StatusCode::FORBIDDEN, "Handshake replayed").into_response();
+        }
+        sessions
+            .seen_nonces
+            .insert(input.nonce.clone(), now + 2 * HANDSHAKE_MAX_SKEW_SECS);
+        if let Err(err) = check_peer_identity(
+            &app.config,
+            &mut sessions,
+            &input.node_id,
+            &input.public_key,
+            now,
+        ) {
+            return (StatusCode::FORBIDDEN, err).into_response();
+        }
+        sessions.inbound.retain(|_, s| s.expires_at > now);
+        if sessions.inbound.len() >= MAX_INBOUND_SESSIONS
+            && let Some(oldest) = sessions
+                .inbound
+                .iter()
+                .min_by_key(|(_, s)| s.expires_at)
+                .map(|(token, _)| token.clone())
+        {
+            sessions.inbound.remove(&oldest);
+        }
+        sessions.inbound.insert(
+            token.clone(),
+            PeerSession {
+                node_id: input.node_id.clone(),
+                expires_at,
+                remote_ip: addr.ip().to_string(),
+            },
+        );
+    }
+    if let Some(url) = input.listen_url.as_deref() {
+        let mut book = app.peer_stats.lock().unwrap();
+        add_known_peer(&app.config, &mut book, url, "handshake");
+        if let Some(stat) = normalize_peer_url(url).and_then(|url| book.get_mut(&url))
+            && stat.node_id.is_none()
+        {
+            stat.node_id = Some(input.node_id.clone());
+        }
+    }
+    let signature = sign_with_identity(
+        &app.identity,
+        &handshake_payload(&app.config.node_id, &app.config.chain_id, &genesis, &input.nonce, now),
+    );
+    Json(serde_json::json!({
+        "ok": true,
+        "nodeId": app.config.node_id,
+        "chainId": app.config.chain_id,
+        "genesisHash": genesis,
+        "protocolVersion": app.config.protocol_version,
+        "publicKey": app.identity.public_key,
+        "timestamp": now,
+        "signature": signature,
+        "sessionToken": token,
+        "expiresAt": expires_at,
+        "transports": if input.transports.iter().any(|t| t == "ws") {
+            offered_transports(&app.config)
+        } else {
+            vec!["http"]
+        },
+    }))
+    .into_response()
+}
+
+fn offered_transports(config: &Config) -> Vec<&'static str> {
+    if config.p2p_websocket {
+        vec!["ws", "http"]
+    } else {
+        vec!["http"]
+    }
+}
+
+/// Returns a live session with `peer`, performing the handshake (and
+/// verifying the peer's counter-signature, chain and genesis) when needed.
+async fn peer_session(app: &AppState, peer: &str) -> Result<OutboundSession, String> {
+    let now = now_unix();
+    if let Some(session) = app.p2p_sessions.lock().unwrap().outbound.get(peer)
+        && session.expires_at > now + 5
+    {
+        return Ok(session.clone());
+    }
+    let genesis = genesis_hash(&app.state.read().unwrap());
+    let nonce = Uuid::new_v4().as_simple().to_string();
+    let payload = handshake_payload(&app.config.node_id, &app.config.chain_id, &genesis, &nonce, now);
+    let mut req = app
+        .client
+        .post(format!("{}/p2p/handshake", peer))
+        .header("x-bulen-protocol-version", &app.config.protocol_version)
+        .timeout(Duration::from_secs(5))
+        .json(&serde_json::json!({
+            "nodeId": app.config.node_id,
+            "chainId": app.config.chain_id,
+            "genesisHash": genesis,
+            "nonce": nonce,
+            "timestamp": now,
+            "publicKey": app.identity.public_key,
+            "signature": sign_with_identity(&app.identity, &payload),
+            // Light nodes take no gossip, so they stay out of address books.
+            "listenUrl": app.config.public_url.as_ref().filter(|_| !app.config.light_client),
+            "transports": offered_transports(&app.config),
+        }));
+    if let Some(t) = app.config.p2p_token.as_ref() {
+        req = req.header("x-bulen-p2p-token", t);
+    }
+    let resp = req.send().await.map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("handshake rejected: {}", resp.status()));
+    }
+    let body: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+    let field = |name: &str| body.get(name).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    if field("chainId") != app.config.chain_id {
+        return Err("peer is on a different chain".into());
+    }
+    if field("genesisHash") != genesis {
+        return Err("peer has a different genesis".into());
+    }
+    let peer_node_id = field("nodeId");
+    if peer_node_id == app.config.node_id {
+        return Err("peer is this node".into());
+    }
+    let peer_key = field("publicKey");
+    let timestamp = body.get("timestamp").and_then(|v| v.as_u64()).unwrap_or(0);
+    let expected = handshake_payload(&peer_node_id, &app.config.chain_id, &genesis, &nonce, timestamp);
+    verify_p256_signature(&peer_key, expected.as_bytes(), &field("signature"))
+        .map_err(|_| "invalid peer handshake signature".to_string())?;
+
+    let mut sessions = app.p2p_sessions.lock().unwrap();
+    check_peer_identity(&app.config, &mut sessions, &peer_node_id, &peer_key, now_unix())?;
+    let transports = body.get("transports").and_then(|v| v.as_array());
+    let session = OutboundSession {
+        node_id: peer_node_id,
+        websocket: transports.is_some_and(|t| t.iter().any(|v| v == "ws")),
+        token: field("sessionToken"),
+        expires_at: body
+            .get("expiresAt")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(now + app.config.p2p_session_ttl_secs),
+    };
+    sessions.outbound.insert(peer.to_string(), session.clone());
+    Ok(session)
+}
+
+/// Calls an authenticated `/p2p/*` route (POST with `payload`, GET without),
+/// re-handshaking once if the peer no longer knows our session.
+async fn peer_request(
+    app: &AppState,
+    peer: &str,
+    path: &str,
+    payload: Option<&serde_json::Value>,
+) -> Result<reqwest::Response, String> {
+    for _ in 0..2 {
+        let session = peer_session(app, peer).await?;
+        let url = format!("{}{}", peer, path);
+        let mut req = match payload {
+            Some(body) => app.client.post(url).json(body),
+            None => app.client.get(url),
+        };
+        req = req
+            .header("x-bulen-protocol-version", &app.config.protocol_version)
+            .header("x-bulen-peer-id", &app.config.node_id)
+            .header("x-bulen-peer-session", &session.token)
+            .timeout(Duration::from_secs(5));
+        if let Some(t) = app.config.p2p_token.as_ref() {
+            req = req.header("x-bulen-p2p-token", t);
+        }
+        let resp = req.send().await.map_err(|e| e.to_string())?;
+        if resp.status() == StatusCode::UNAUTHORIZED {
+            app.p2p_sessions.lock().unwrap().outbound.remove(peer);
+            continue;
+        }
+        if !resp.status().is_success() {
+            return Err(format!("status {}", resp.status()));
+        }
+        return Ok(resp);
+    }
+    Err("peer rejected session".into())
+}
+
+async fn send_to_peer(
+    app: &AppState,
+    peer: &str,
+    path: &str,
+    payload: &serde_json::Value,
+) -> Result<(), String> {
+    peer_request(app, peer, path, Some(payload)).await.map(|_| ())
+}
+
+/// Peers worth sharing: static/seed peers, healthy address-book entries and
+/// this node's own public URL.
+async fn p2p_peers(State(app): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = verify_peer_session(&app, &headers) {
+        return resp;
+    }
+    let book = app.peer_stats.lock().unwrap();
+    let mut peers: Vec<String> = book
+        .iter()
+        .filter(|(_, s)| s.ok || s.source == "static" || s.source == "seed")
+        .map(|(url, _)| url.clone())
+        .collect();
+    peers.sort();
+    peers.truncate(MAX_SHARED_PEERS);
+    if let Some(own) = &app.config.public_url {
+        peers.push(own.clone());
+    }
+    Json(serde_json::json!({ "ok": true, "peers": peers })).into_response()
+}
+
+/// Block without its transactions, served by `/p2p/headers`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BlockHeader {
+    index: u64,
+    previous_hash: String,
+    hash: String,
+    timestamp: String,
+    producer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_root: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reward_claim: Option<RewardClaim>,
+}
+
+impl From<&Block> for BlockHeader {
+    fn from(block: &Block) -> Self {
+        BlockHeader {
+            index: block.index,
+            previous_hash: block.previous_hash.clone(),
+            hash: block.hash.clone(),
+            timestamp: block.timestamp.clone(),
+            producer: block.producer.clone(),
+            state_root: block.state_root.clone(),
+            reward_claim: block.reward_claim.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockRangeQuery {
+    from: u64,
+    to: Option<u64>,
+}
+
+/// Main-chain blocks `from..=to` in ascending order, cut at
+/// `MAX_RANGE_BLOCKS` or the `BULEN_P2P_RANGE_MAX_BYTES` budget (at least one
+/// block is always returned). `more` means the caller should continue after
+/// the last returned height.
+async fn p2p_blocks(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<BlockRangeQuery>,
+) -> Response {
+    if let Err(resp) = verify_peer_session(&app, &headers) {
+        return resp;
+    }
+    let state = app.state.read().unwrap();
+    let tip = state.blocks.last().map(|b| b.index).unwrap_or(0);
+    let requested_to = query.to.unwrap_or(tip).min(tip);
+    if query.to.is_some_and(|to| to < query.from) {
+        return (StatusCode::BAD_REQUEST, "invalid block range").into_response();
+    }
+    if query.from > tip {
+        return Json(serde_json::json!({ "ok": true, "blocks": [], "more": false })).into_response();
+    }
+    let to = requested_to.min(query.from + MAX_RANGE_BLOCKS - 1);
+    let mut blocks = Vec::new();
+    let mut bytes = 0usize;
+    for block in &state.blocks[query.from as usize..=to as usize] {
+        let size = serde_json::to_vec(block).map(|v| v.len()).unwrap_or(0);
+        if !blocks.is_empty() && bytes + size > app.config.p2p_range_max_bytes {
+            break;
+        }
+        bytes += size;
+        blocks.push(block);
+    }
+    let last = query.from + blocks.len() as u64 - 1;
+    Json(serde_json::json!({
+        "ok": true,
+        "from": query.from,
+        "to": last,
+        "blocks": blocks,
+        "more": last < requested_to,
+    }))
+    .into_response()
+}
+
+/// Main-chain headers `from..=to`, ascending, at most `MAX_RANGE_HEADERS`.
+async fn p2p_headers(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<BlockRangeQuery>,
+) -> Response {
+    if let Err(resp) = verify_peer_session(&app, &headers) {
+        return resp;
+    }
+    let state = app.state.read().unwrap();
+    let tip = state.blocks.last().map(|b| b.index).unwrap_or(0);
+    if query.to.is_some_and(|to| to < query.from) {
+        return (StatusCode::BAD_REQUEST, "invalid block range").into_response();
+    }
+    let to = query.to.unwrap_or(tip).min(tip).min(query.from + MAX_RANGE_HEADERS - 1);
+    let list: Vec<BlockHeader> = if query.from > tip {
+        Vec::new()
+    } else {
+        state.blocks[query.from as usize..=to as usize].iter().map(BlockHeader::from).collect()
+    };
+    Json(serde_json::json!({ "ok": true, "headers": list })).into_response()
+}
+
+/// Asks `peer` for its peer list and merges it into the address book.
+async fn exchange_peers(app: &AppState, peer: &str) -> Result<usize, String> {
+    let body: serde_json::Value = peer_request(app, peer, "/p2p/peers", None)
+        .await?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut book = app.peer_stats.lock().unwrap();
+    let added = body
+        .get("peers")
+        .and_then(|v| v.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|v| v.as_str())
+                .take(MAX_SHARED_PEERS)
+                .filter(|url| add_known_peer(&app.config, &mut book, url, "exchange"))
+                .count()
+        })
+        .unwrap_or(0);
+    Ok(added)
+}
+
 #[derive(Deserialize)]
 struct BlockListQuery {
     limit: Option<usize>,
@@ -998,7 +3609,7 @@ async fn list_blocks_paged(
     let limit = params.limit.unwrap_or(20).min(100);
     let offset = params.offset.unwrap_or(0);
     let mut sorted = state.blocks.clone();
-    sorted.sort_by(|a, b| b.index.cmp(&a.index));
+    sorted.sort_by_key(|b| std::cmp::Reverse(b.index));
     let page = sorted
         .into_iter()
         .skip(offset)
@@ -1021,14 +3632,30 @@ struct TxInput {
     fee: u64,
     #[serde(default)]
     nonce: Option<u64>,
-    #[serde(default)]
+    #[serde(default, alias = "publicKey")]
     public_key: Option<String>,
     #[serde(default)]
     signature: Option<String>,
+    #[serde(default, alias = "keyType")]
+    key_type: Option<String>,
     #[serde(default)]
     memo: Option<String>,
     #[serde(default)]
     action: Option<String>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default, alias = "chainId")]
+    chain_id: Option<String>,
+    #[serde(default, alias = "expiresAtHeight")]
+    expires_at_height: Option<u64>,
+    #[serde(default)]
+    outputs: Vec<TransferOutput>,
+    #[serde(default)]
+    multisig: Option<MultisigPolicy>,
+    #[serde(default)]
+    signatures: Vec<PartialSignature>,
+    #[serde(default)]
+    lock: Option<LockSchedule>,
 }
 
 #[axum::debug_handler]
@@ -1051,8 +3678,8 @@ async fn post_transaction(
         if payload.memo.is_some() && memo != payload.memo {
             return (StatusCode::BAD_REQUEST, "memo too long (max 256 chars)").into_response();
         }
-        let tx = Transaction {
-            id: format!("tx-{}", Uuid::new_v4()),
+        let mut tx = Transaction {
+            id: String::new(),
             from: payload.from,
             to: payload.to.unwrap_or_else(|| "".into()),
             amount: payload.amount,
@@ -1061,13 +3688,37 @@ async fn post_transaction(
             timestamp: now_iso(),
             public_key: payload.public_key,
             signature: payload.signature,
+            key_type: payload.key_type,
             memo,
             action: payload.action.unwrap_or_else(|| "transfer".into()),
+            version: payload.version.unwrap_or(TX_VERSION),
+            chain_id: payload.chain_id.or_else(|| Some(app.config.chain_id.clone())),
+            expires_at_height: payload.expires_at_height,
+            outputs: payload.outputs,
+            multisig: payload.multisig,
+            signatures: payload.signatures,
+            lock: payload.lock,
         };
-        if let Err(err) = verify_signature(&app.config, &state, &tx) {
+        if tx.version == LEGACY_TX_VERSION {
+            tx.chain_id = None;
+            tx.expires_at_height = None;
+        }
+        tx.id = compute_transaction_id(&tx);
+        if is_known_transaction(&state, &tx.id) {
+            return (StatusCode::CONFLICT, "duplicate transaction").into_response();
+        }
+        if let Err(err) = admit_signing_format(&app.config, &tx)
+            .and_then(|_| admit_action(&app.config, &tx, false))
+        {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        if let Err(err) = validate_destinations(&app.config, &tx) {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        if let Err(err) = verify_signature(&app.config, &tx) {
             return (StatusCode::BAD_REQUEST, err).into_response();
         }
-        if let Err(err) = validate_transaction(&state, &tx) {
+        if let Err(err) = validate_transaction(&app.config, &state, &tx) {
             return (StatusCode::BAD_REQUEST, err).into_response();
         }
         tx
@@ -1075,50 +3726,186 @@ async fn post_transaction(
 
     {
         let mut state = app.state.write().unwrap();
+        if is_known_transaction(&state, &tx.id) {
+            return (StatusCode::CONFLICT, "duplicate transaction").into_response();
+        }
         state.mempool.push(tx.clone());
     }
 
-    broadcast_transaction(app.clone(), tx.clone()).await;
+    broadcast_transaction(&app, &tx);
     Json(tx).into_response()
 }
 
+async fn get_transaction(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+) -> Response {
+    let state = app.state.read().unwrap();
+    if let Some(location) = state.tx_index.get(&id)
+        && let Some(block) = state.blocks.get(location.height as usize)
+        && let Some(tx) = block.transactions.get(location.position)
+    {
+        let tip_height = state.blocks.last().map(|b| b.index).unwrap_or(0);
+        let confirmations = tip_height.saturating_sub(block.index) + 1;
+        let finalized = tip_height.saturating_sub(block.index) >= app.config.finality_min_depth;
+        return Json(serde_json::json!({
+            "status": "confirmed",
+            "transaction": tx,
+            "blockHeight": block.index,
+            "blockHash": block.hash,
+            "confirmations": confirmations,
+            "finality": if finalized { "final" } else { "unfinalized" },
+            "receipt": {
+                "feePaid": tx.fee,
+                "resultingNonce": tx.nonce,
+            },
+        }))
+        .into_response();
+    }
+    if let Some(tx) = state.mempool.iter().find(|t| t.id == id) {
+        return Json(serde_json::json!({
+            "status": "pending",
+            "transaction": tx,
+            "blockHeight": null,
+            "blockHash": null,
+            "confirmations": 0,
+            "finality": "pending",
+            "receipt": null,
+        }))
+        .into_response();
+    }
+    (StatusCode::NOT_FOUND, "transaction not found").into_response()
+}
+
 #[derive(Deserialize)]
 struct FaucetInput {
     address: String,
     amount: Option<u64>,
 }
 
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct RewardEstimateInput {
+    stake: Option<f64>,
+    uptime_hours_per_day: Option<f64>,
+    days: Option<f64>,
+    device_class: Option<String>,
+}
+
+/// Uptime rewards for `stake` online `uptimeHoursPerDay` hours a day over
+/// `days`, at this node's loyalty and efficiency boosts. Inputs are clamped
+/// like the JS node's `computeRewardProjection`: 0..24 hours (default 24),
+/// 1..30 days (default 7), stake weight `1 + stake / 10000` up to 3.
+fn reward_projection(app: &AppState, input: &RewardEstimateInput) -> serde_json::Value {
+    let stake = input.stake.unwrap_or(0.0).max(0.0);
+    let stake_weight = (1.0 + stake / 10_000.0).min(3.0);
+    let hours = input.uptime_hours_per_day.unwrap_or(24.0).clamp(0.0, 24.0);
+    let days = input.days.unwrap_or(7.0).clamp(1.0, 30.0);
+    let device_class = input
+        .device_class
+        .as_deref()
+        .filter(|c| !c.is_empty())
+        .unwrap_or(&app.config.device_class);
+    let reward = uptime_reward(app, reward_uptime_seconds(app), device_class);
+    let hourly = reward.hourly * stake_weight;
+    let daily = hourly * hours;
+    serde_json::json!({
+        "hourly": hourly,
+        "daily": daily,
+        "weekly": daily * days.min(7.0),
+        "periodTotal": daily * days,
+        "perBlock": hourly_reward_per_block(&app.config, hourly),
+        "stakeWeight": stake_weight,
+        "uptimeHoursPerDay": hours,
+        "days": days,
+        "deviceClass": device_class,
+        "loyaltyBoost": reward.loyalty_boost,
+        "deviceBoost": reward.device_boost,
+        "efficiencyBoost": reward.efficiency_boost,
+    })
+}
+
+async fn rewards_estimate(
+    State(app): State<AppState>,
+    Json(input): Json<RewardEstimateInput>,
+) -> Response {
+    Json(serde_json::json!({
+        "ok": true,
+        "projection": reward_projection(&app, &input),
+    }))
+    .into_response()
+}
+
 async fn faucet(
     State(app): State<AppState>,
     Json(input): Json<FaucetInput>,
 ) -> Response {
     // Rate limit handled by middleware.
-    if !app.config.enable_faucet {
+    if !app.config.enable_faucet || !is_faucet_chain(&app.config.chain_id) {
         return (StatusCode::FORBIDDEN, "Faucet disabled").into_response();
     }
     if input.address.is_empty() {
         return (StatusCode::BAD_REQUEST, "missing address").into_response();
     }
+    if let Err(err) = validate_address(&app.config, &input.address) {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
     let amount = input.amount.unwrap_or(1000);
     if amount == 0 {
         return (StatusCode::BAD_REQUEST, "invalid amount").into_response();
     }
-    let mut state = app.state.write().unwrap();
-    let entry = state.accounts.entry(input.address.clone()).or_insert(Account {
-        balance: 0,
-        stake: 0,
-        nonce: 0,
-        reputation: 0,
-    });
-    entry.balance += amount as i128;
-    let new_balance = entry.balance;
-    save_state(&app.config, &state);
-    Json(serde_json::json!({
-        "ok": true,
-        "address": input.address,
-        "newBalance": new_balance
-    }))
-    .into_response()
+    let address = canonical_address(&app.config, &input.address);
+    let tx = {
+        let mut state = app.state.write().unwrap();
+        let pending = state.mempool.iter().filter(|t| t.action == "faucet").count() as u64;
+        let nonce = state.accounts.get(FAUCET_ACCOUNT).map(|a| a.nonce).unwrap_or(0);
+        let mut tx = Transaction {
+            id: String::new(),
+            from: FAUCET_ACCOUNT.into(),
+            to: address.clone(),
+            amount,
+            fee: 0,
+            nonce: nonce + pending + 1,
+            timestamp: now_iso(),
+            action: "faucet".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(app.config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        state.mempool.push(tx.clone());
+        tx
+    };
+    broadcast_transaction(&app, &tx);
+    // The credit exists once a block (ours or a peer's) includes it.
+    let deadline = Instant::now() + Duration::from_millis(3 * app.config.block_interval_ms);
+    while Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+        let state = app.state.read().unwrap();
+        if state.tx_index.contains_key(&tx.id) {
+            let balance = state.accounts.get(&address).map(|a| a.balance).unwrap_or(0);
+            return Json(serde_json::json!({
+                "ok": true,
+                "address": input.address,
+                "txId": tx.id,
+                "newBalance": balance
+            }))
+            .into_response();
+        }
+    }
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "ok": true, "address": input.address, "txId": tx.id })),
+    )
+        .into_response()
 }
 
 async fn get_account(
@@ -1126,14 +3913,122 @@ async fn get_account(
     State(app): State<AppState>,
 ) -> Response {
     // Rate limit handled by middleware.
+    let address = canonical_address(&app.config, &address);
+    if app.config.light_client {
+        return light_account(&app, &address).await;
+    }
     let state = app.state.read().unwrap();
-    let account = state.accounts.get(&address).cloned().unwrap_or(Account {
-        balance: 0,
-        stake: 0,
-        nonce: 0,
-        reputation: 0,
-    });
-    Json(account).into_response()
+    let account = state.accounts.get(&address).cloned().unwrap_or_default();
+    Json(account_body(&account, chain_clock(&state))).into_response()
+}
+
+/// Account JSON with lock schedules evaluated at `clock` (height, time).
+fn account_body(account: &Account, clock: (u64, u64)) -> serde_json::Value {
+    let locked = locked_balance(account, clock);
+    let schedules: Vec<serde_json::Value> = account
+        .locks
+        .iter()
+        .map(|l| {
+            serde_json::json!({
+                "txId": l.tx_id,
+                "amount": l.amount,
+                "unlocked": unlocked_amount(l, clock),
+                "schedule": l.schedule,
+            })
+        })
+        .collect();
+    let mut body = serde_json::to_value(account).unwrap_or_default();
+    body["locks"] = serde_json::json!(schedules);
+    body["locked"] = serde_json::json!(locked);
+    body["spendable"] = serde_json::json!(account.balance - locked);
+    body
+}
+
+#[derive(Deserialize)]
+struct AccountHistoryQuery {
+    limit: Option<usize>,
+    cursor: Option<usize>,
+    direction: Option<String>,
+    action: Option<String>,
+}
+
+/// Newest-first history from the address index. `cursor` is the `nextCursor`
+/// of the previous page.
+async fn get_account_transactions(
+    Path(address): Path<String>,
+    Query(params): Query<AccountHistoryQuery>,
+    State(app): State<AppState>,
+) -> Response {
+    if let Some(direction) = params.direction.as_deref()
+        && direction != "in"
+        && direction != "out"
+    {
+        return (StatusCode::BAD_REQUEST, "direction must be in or out").into_response();
+    }
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let state = app.state.read().unwrap();
+    let entries = state
+        .address_index
+        .get(&canonical_address(&app.config, &address))
+        .map(|v| v.as_slice())
+        .unwrap_or(&[]);
+    let end = params.cursor.unwrap_or(entries.len()).min(entries.len());
+    let mut items = Vec::new();
+    let mut next_cursor = None;
+    for i in (0..end).rev() {
+        let entry = &entries[i];
+        if let Some(direction) = params.direction.as_deref()
+            && entry.direction != direction
+            && entry.direction != "self"
+        {
+            continue;
+        }
+        let Some(block) = state.blocks.get(entry.height as usize) else {
+            continue;
+        };
+        let item = match entry.position {
+            Some(position) => {
+                let Some(tx) = block.transactions.get(position) else {
+                    continue;
+                };
+                if params.action.as_deref().is_some_and(|a| a != tx.action) {
+                    continue;
+                }
+                serde_json::json!({
+                    "type": "transaction",
+                    "direction": entry.direction,
+                    "blockHeight": block.index,
+                    "blockHash": block.hash,
+                    "timestamp": block.timestamp,
+                    "transaction": tx,
+                })
+            }
+            None => {
+                if params.action.as_deref().is_some_and(|a| a != "reward") {
+                    continue;
+                }
+                serde_json::json!({
+                    "type": "reward",
+                    "direction": entry.direction,
+                    "blockHeight": block.index,
+                    "blockHash": block.hash,
+                    "timestamp": block.timestamp,
+                    "amount": entry.reward.unwrap_or(0),
+                })
+            }
+        };
+        if items.len() == limit {
+            next_cursor = Some(i + 1);
+            break;
+        }
+        items.push(item);
+    }
+    Json(serde_json::json!({
+        "address": address,
+        "items": items,
+        "nextCursor": next_cursor,
+    }))
+    .into_response()
 }
 
 #[derive(Deserialize)]
@@ -1142,12 +4037,68 @@ struct PaymentInput {
     amount: u64,
     memo: Option<String>,
     expires_in_seconds: Option<u64>,
+    #[serde(alias = "webhookUrl")]
+    webhook_url: Option<String>,
+    #[serde(alias = "merchantOrderId")]
+    merchant_order_id: Option<String>,
+    #[serde(alias = "customerReference")]
+    customer_reference: Option<String>,
+}
+
+/// Longest merchant order id or customer reference accepted.
+const MAX_PAYMENT_REFERENCE_CHARS: usize = 128;
+
+/// DNS resolver for webhook deliveries that drops internal addresses, so a
+/// public-looking hostname cannot point a webhook into the node's network.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Client for webhook deliveries. It never follows redirects and, unless
+/// private webhooks are allowed, only connects to public addresses.
+fn webhook_client(config: &Config) -> Client {
+    let mut builder = Client::builder().redirect(reqwest::redirect::Policy::none());
+    if !config.allow_private_webhooks {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    builder.build().expect("webhook client")
+}
+
+fn validate_webhook_url(config: &Config, url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| "Invalid webhook URL".to_string())?;
+    match parsed.scheme() {
+        "https" => {}
+        "http" if config.allow_insecure_webhooks => {}
+        _ => return Err("Webhook URL must be https://".into()),
+    }
+    if !config.allow_private_webhooks && !is_public_host(url) {
+        return Err("Webhook URL must point to a public host".into());
+    }
+    if config.require_webhook_secret && config.webhook_secret.is_none() {
+        return Err("Webhook secret required when using webhookUrl".into());
+    }
+    Ok(())
 }
 
-fn create_payment(input: PaymentInput) -> Result<Payment, String> {
+fn create_payment(config: &Config, input: PaymentInput) -> Result<Payment, String> {
     if input.to.is_empty() {
         return Err("Missing destination address".into());
     }
+    validate_address(config, &input.to)?;
     if input.amount == 0 {
         return Err("Invalid amount".into());
     }
@@ -1155,6 +4106,17 @@ fn create_payment(input: PaymentInput) -> Result<Payment, String> {
     if input.memo.is_some() && memo != input.memo {
         return Err("Memo too long (max 256 chars)".into());
     }
+    let merchant_order_id = input.merchant_order_id.filter(|v| !v.is_empty());
+    let customer_reference = input.customer_reference.filter(|v| !v.is_empty());
+    for reference in [&merchant_order_id, &customer_reference].into_iter().flatten() {
+        if reference.chars().count() > MAX_PAYMENT_REFERENCE_CHARS {
+            return Err("Payment reference too long (max 128 chars)".into());
+        }
+    }
+    let webhook_url = input.webhook_url.filter(|url| !url.is_empty());
+    if let Some(url) = &webhook_url {
+        validate_webhook_url(config, url)?;
+    }
     let now = SystemTime::now();
     let expires = input.expires_in_seconds.unwrap_or(900).max(60);
     let expires_at = now
@@ -1168,10 +4130,10 @@ fn create_payment(input: PaymentInput) -> Result<Payment, String> {
                 .ok()
                 .and_then(|dt| dt.format(&time::format_description::well_known::Rfc3339).ok())
         })
-        .unwrap_or_else(|| now_iso());
+        .unwrap_or_else(now_iso);
     let payment = Payment {
         id: format!("pay_{}", Uuid::new_v4().as_simple()),
-        to: input.to,
+        to: canonical_address(config, &input.to),
         amount: input.amount,
         memo,
         created_at: now_iso(),
@@ -1179,82 +4141,403 @@ fn create_payment(input: PaymentInput) -> Result<Payment, String> {
         status: "pending".into(),
         transaction_id: None,
         block_index: None,
+        webhook_url,
+        merchant_order_id,
+        customer_reference,
     };
     Ok(payment)
 }
 
+/// Seconds between payment refreshes, which catch expiries and mempool
+/// matches; blocks refresh payments as they are applied.
+const PAYMENT_REFRESH_SECS: u64 = 5;
+
+/// Re-evaluates every payment against the chain and mempool. Runs after
+/// block application and from `spawn_payment_refresher`, never on reads.
 fn update_payments(app: &AppState) {
     let state = app.state.read().unwrap();
     let mut payments = app.payments.lock().unwrap();
-    let mut changed = false;
-    for p in payments.iter_mut() {
-        let before = p.status.clone();
-        update_payment_status(p, &state);
-        if p.status != before {
-            changed = true;
-        }
-    }
-    if changed {
+    let changed = payments.refresh(&app.config, &state);
+    if !changed.is_empty() {
         save_payments(&app.config, &payments);
     }
+    drop(payments);
+    drop(state);
+    for payment in changed {
+        queue_payment_webhook(app, &payment);
+    }
+}
+
+/// Finished deliveries kept for `/api/payments/:id/webhooks`.
+const MAX_WEBHOOK_DELIVERIES: usize = 1_000;
+
+fn queue_payment_webhook(app: &AppState, payment: &Payment) {
+    let Some(url) = payment.webhook_url.clone() else {
+        return;
+    };
+    let id = format!("whk_{}", Uuid::new_v4().as_simple());
+    let body = serde_json::json!({
+        "id": id,
+        "event": "payment.updated",
+        "payment": payment,
+    });
+    let mut outbox = app.webhooks.lock().unwrap();
+    outbox.push(WebhookDelivery {
+        id,
+        payment_id: payment.id.clone(),
+        url,
+        body: body.to_string(),
+        state: "pending".into(),
+        created_at: now_unix(),
+        next_attempt_at_ms: 0,
+        attempts: vec![],
+    });
+    while outbox.len() > MAX_WEBHOOK_DELIVERIES {
+        let Some(oldest) = outbox.iter().position(|d| d.state != "pending") else {
+            break;
+        };
+        outbox.remove(oldest);
+    }
+    save_webhooks(&app.config, &outbox);
+    drop(outbox);
+    app.webhook_wake.notify_one();
+}
+
+/// Hex HMAC-SHA256 of `{timestamp}.{body}`; receivers should also reject
+/// stale `x-bulen-timestamp` values to stop replays.
+fn webhook_signature(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Webhook requests in flight at once; a slow receiver holds up only its slot.
+const WEBHOOK_CONCURRENCY: usize = 8;
+
+/// Posts every due outbox entry once. Anything but a 2xx is retried after
+/// `webhook_retry_base_ms`, doubling per attempt, until
+/// `webhook_max_attempts` marks it failed.
+async fn deliver_due_webhooks(app: &AppState) {
+    let now_ms = now_unix_ms();
+    let due: Vec<WebhookDelivery> = app
+        .webhooks
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|d| d.state == "pending" && d.next_attempt_at_ms <= now_ms)
+        .cloned()
+        .collect();
+    futures_util::stream::iter(due)
+        .for_each_concurrent(WEBHOOK_CONCURRENCY, |delivery| deliver_webhook(app, delivery))
+        .await;
+}
+
+/// Makes one attempt at `delivery` and records the outcome in the outbox.
+async fn deliver_webhook(app: &AppState, delivery: WebhookDelivery) {
+    let timestamp = now_unix();
+    let mut request = app
+        .webhook_client
+        .post(&delivery.url)
+        .timeout(Duration::from_secs(10))
+        .header("content-type", "application/json")
+        .header("x-bulen-event", "payment.updated")
+        .header("x-bulen-delivery", &delivery.id)
+        .header("x-bulen-timestamp", timestamp.to_string());
+    if let Some(secret) = &app.config.webhook_secret {
+        let signature = webhook_signature(secret, timestamp, &delivery.body);
+        request = request.header("x-bulen-signature", signature);
+    }
+    let attempt = match request.body(delivery.body.clone()).send().await {
+        Ok(resp) => WebhookAttempt {
+            at: timestamp,
+            status_code: Some(resp.status().as_u16()),
+            error: (!resp.status().is_success()).then(|| format!("HTTP {}", resp.status())),
+        },
+        Err(err) => WebhookAttempt {
+            at: timestamp,
+            status_code: None,
+            error: Some(err.to_string()),
+        },
+    };
+    let mut outbox = app.webhooks.lock().unwrap();
+    let Some(entry) = outbox.iter_mut().find(|d| d.id == delivery.id) else {
+        return;
+    };
+    let delivered = attempt.error.is_none();
+    entry.attempts.push(attempt);
+    let attempts = entry.attempts.len();
+    if delivered {
+        entry.state = "delivered".into();
+    } else if attempts >= app.config.webhook_max_attempts {
+        entry.state = "failed".into();
+    } else {
+        let backoff = app.config.webhook_retry_base_ms << (attempts - 1).min(16);
+        entry.next_attempt_at_ms = now_unix_ms() + backoff;
+    }
+    save_webhooks(&app.config, &outbox);
+}
+
+fn spawn_payment_refresher(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut refresh = tokio::time::interval(Duration::from_secs(PAYMENT_REFRESH_SECS));
+        loop {
+            refresh.tick().await;
+            update_payments(&app);
+        }
+    })
+}
+
+fn spawn_webhook_worker(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let idle = Duration::from_millis(app.config.webhook_retry_base_ms.clamp(50, 1_000));
+        loop {
+            deliver_due_webhooks(&app).await;
+            tokio::select! {
+                _ = tokio::time::sleep(idle) => {}
+                _ = app.webhook_wake.notified() => {}
+            }
+        }
+    })
 }
 
 async fn post_payment(State(app): State<AppState>, Json(input): Json<PaymentInput>) -> Response {
-    let payment = match create_payment(input) {
+    let payment = match create_payment(&app.config, input) {
         Ok(p) => p,
         Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
     };
     {
         let mut payments = app.payments.lock().unwrap();
-        payments.push(payment.clone());
+        payments.insert(payment.clone());
         save_payments(&app.config, &payments);
     }
     Json(payment).into_response()
 }
 
+async fn get_payment_webhooks(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+) -> Response {
+    if app.payments.lock().unwrap().get(&id).is_none() {
+        return (StatusCode::NOT_FOUND, "Payment not found").into_response();
+    }
+    let deliveries: Vec<WebhookDelivery> = app
+        .webhooks
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|d| d.payment_id == id)
+        .cloned()
+        .collect();
+    Json(serde_json::json!({ "paymentId": id, "deliveries": deliveries })).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PaymentLinkInput {
+    address: Option<String>,
+    amount: Option<u64>,
+    memo: Option<String>,
+    label: Option<String>,
+    expires_in_seconds: Option<u64>,
+}
+
+/// `bulen:` URI in the same shape as the SDK's `build_payment_link`. Memo and
+/// label are limited to `MAX_MEMO_CHARS`; expiry is an absolute unix time.
+fn payment_link(config: &Config, input: &PaymentLinkInput, now: u64) -> Result<String, String> {
+    let address = input.address.as_deref().unwrap_or("");
+    if address.is_empty() {
+        return Err("Missing address".into());
+    }
+    validate_address(config, address)?;
+    let amount = match input.amount {
+        Some(amount) if amount > 0 => amount,
+        _ => return Err("Invalid amount".into()),
+    };
+    let mut link = format!("bulen:{}?amount={}", address, amount);
+    for (key, value) in [("memo", &input.memo), ("label", &input.label)] {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            if value.chars().count() > MAX_MEMO_CHARS {
+                return Err(format!("{} too long (max {} chars)", key, MAX_MEMO_CHARS));
+            }
+            link.push_str(&format!("&{}={}", key, urlencoding::encode(value)));
+        }
+    }
+    if let Some(secs) = input.expires_in_seconds {
+        if secs == 0 {
+            return Err("Invalid expiry".into());
+        }
+        link.push_str(&format!("&expires={}", now.saturating_add(secs)));
+    }
+    Ok(link)
+}
+
+/// QR code for `text` as an SVG data URL, or `None` if it doesn't fit.
+fn qr_data_url(text: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(text.as_bytes()).ok()?;
+    let svg = code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Some(format!(
+        "data:image/svg+xml;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(svg)
+    ))
+}
+
+async fn post_payment_link(
+    State(app): State<AppState>,
+    Json(input): Json<PaymentLinkInput>,
+) -> Response {
+    let link = match payment_link(&app.config, &input, now_unix()) {
+        Ok(link) => link,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    Json(serde_json::json!({
+        "ok": true,
+        "qrDataUrl": qr_data_url(&link),
+        "link": link,
+    }))
+    .into_response()
+}
+
 async fn get_payment(
     Path(id): Path<String>,
     State(app): State<AppState>,
 ) -> Response {
-    update_payments(&app);
     let payments = app.payments.lock().unwrap();
-    if let Some(p) = payments.iter().find(|p| p.id == id) {
+    if let Some(p) = payments.get(&id) {
         return Json(p).into_response();
     }
     (StatusCode::NOT_FOUND, "Payment not found").into_response()
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PaymentListQuery {
+    status: Option<String>,
+    to: Option<String>,
+    merchant_order_id: Option<String>,
+    customer_reference: Option<String>,
+    /// Inclusive RFC 3339 bounds on `created_at`.
+    created_from: Option<String>,
+    created_to: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+fn parse_created_bound(value: Option<&str>, default: i64) -> Result<i64, String> {
+    match value {
+        None => Ok(default),
+        Some(raw) => parse_rfc3339(raw)
+            .map(|dt| (dt.unix_timestamp_nanos() / 1_000_000) as i64)
+            .ok_or_else(|| format!("Invalid timestamp {} (RFC 3339 expected)", raw)),
+    }
+}
+
+/// Newest-first payments matching all filters. `cursor` is the `nextCursor`
+/// of the previous page.
+async fn list_payments(
+    Query(mut query): Query<PaymentListQuery>,
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = verify_merchant(&app.config, addr, &headers) {
+        return resp;
+    }
+    query.to = query.to.map(|to| canonical_address(&app.config, &to));
+    let created = match (
+        parse_created_bound(query.created_from.as_deref(), i64::MIN),
+        parse_created_bound(query.created_to.as_deref(), i64::MAX),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let after = match query.cursor.as_deref().filter(|c| !c.is_empty()) {
+        None => None,
+        Some(cursor) => match cursor
+            .split_once(':')
+            .and_then(|(ms, id)| Some((ms.parse::<i64>().ok()?, id.to_string())))
+        {
+            Some(key) => Some(key),
+            None => return (StatusCode::BAD_REQUEST, "Invalid cursor").into_response(),
+        },
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let payments = app.payments.lock().unwrap();
+    let (page, next) = payments.list(&query, created, after, limit);
+    Json(serde_json::json!({
+        "payments": page,
+        "nextCursor": next.map(|(ms, id)| format!("{}:{}", ms, id)),
+    }))
+    .into_response()
+}
+
+/// Cancels a payment that has not been paid; later matching transfers no
+/// longer settle it.
+async fn cancel_payment(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = verify_merchant(&app.config, addr, &headers) {
+        return resp;
+    }
+    let mut payments = app.payments.lock().unwrap();
+    let Some(mut payment) = payments.get(&id).cloned() else {
+        return (StatusCode::NOT_FOUND, "Payment not found").into_response();
+    };
+    if payment.status != "pending" {
+        let msg = format!("Payment is {} and cannot be cancelled", payment.status);
+        return (StatusCode::CONFLICT, msg).into_response();
+    }
+    payment.status = "cancelled".into();
+    payments.insert(payment.clone());
+    save_payments(&app.config, &payments);
+    drop(payments);
+    queue_payment_webhook(&app, &payment);
+    Json(payment).into_response()
+}
+
 async fn status(
     State(app): State<AppState>,
 ) -> impl IntoResponse {
     let state = app.state.read().unwrap();
     let latest = state.blocks.last();
-    let height = latest.map(|b| b.index).unwrap_or(0);
-    let latest_hash = latest.map(|b| b.hash.clone());
+    let (height, latest_hash) = if app.config.light_client {
+        let headers = app.light_headers.lock().unwrap();
+        let tip = headers.last();
+        (tip.map(|h| h.index).unwrap_or(0), tip.map(|h| h.hash.clone()))
+    } else {
+        (latest.map(|b| b.index).unwrap_or(0), latest.map(|b| b.hash.clone()))
+    };
     let accounts_total = state.accounts.len();
     let mempool_size = state.mempool.len();
-    let uptime_seconds = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(state.started_at))
-        .unwrap_or_default()
-        .as_secs();
+    let uptime_seconds = reward_uptime_seconds(&app);
     let total_stake: i128 = state.accounts.values().map(|a| a.stake).sum();
-    let loyalty_boost = compute_loyalty_boost(&app.config, uptime_seconds);
-    let device_boost = compute_device_boost(&app.config);
-    let hourly = app.config.base_uptime_reward_per_hour
-        * app.config.reward_weight
-        * loyalty_boost
-        * device_boost;
-    let total = (uptime_seconds as f64 / 3600.0) * hourly;
+    let reward = uptime_reward(&app, uptime_seconds, &app.config.device_class);
+    let total = (uptime_seconds as f64 / 3600.0) * reward.hourly;
     let payments = app.payments.lock().unwrap();
-    let payments_pending = payments.iter().filter(|p| p.status == "pending").count();
+    let payments_pending = payments.count_status("pending");
 
     Json(serde_json::json!({
         "chainId": app.config.chain_id,
         "nodeId": app.config.node_id,
         "nodeRole": app.config.node_role,
         "nodeProfile": app.config.node_profile,
+        "mode": if app.config.light_client { "light" } else { "full" },
         "height": height,
         "latestHash": latest_hash,
+        "finalizedHeight": height.saturating_sub(app.config.finality_min_depth),
         "mempoolSize": mempool_size,
         "accountsCount": accounts_total,
         "totalStake": total_stake,
@@ -1265,12 +4548,18 @@ async fn status(
             "startedAt": system_time_to_iso(state.started_at),
             "uptimeSeconds": uptime_seconds,
             "producedBlocks": state.produced_blocks,
-            "uptimeRewardEstimateHourly": hourly,
+            "uptimeRewardEstimateHourly": reward.hourly,
             "uptimeRewardEstimateTotal": total,
-            "loyaltyBoost": loyalty_boost,
-            "deviceBoost": device_boost,
+            "loyaltyBoost": reward.loyalty_boost,
+            "deviceBoost": reward.device_boost,
+            "efficiencyBoost": reward.efficiency_boost,
             "producedRewards": state.produced_rewards,
         },
+        "rewardProjection": reward_projection(&app, &RewardEstimateInput {
+            stake: Some(1000.0),
+            ..RewardEstimateInput::default()
+        }),
+        "power": power_summary(&app),
         "payments": {
             "total": payments.len(),
             "pending": payments_pending,
@@ -1279,6 +4568,226 @@ async fn status(
     .into_response()
 }
 
+/// One JSON entry per address-book peer, best score first; `extend` adds
+/// view-specific fields.
+fn peer_summaries(
+    app: &AppState,
+    extend: impl Fn(&PeerStat, Option<&PeerPenalty>, &mut serde_json::Value),
+) -> Vec<serde_json::Value> {
+    let penalties = app.p2p_sessions.lock().unwrap().penalties.clone();
+    let active = current_active_peers(app);
+    let book = app.peer_stats.lock().unwrap();
+    let mut peers: Vec<(i64, serde_json::Value)> = book
+        .iter()
+        .map(|(url, stat)| {
+            let penalty = stat.node_id.as_ref().and_then(|id| penalties.get(id));
+            let score = peer_score(stat, penalty);
+            let mut entry = serde_json::json!({
+                "url": url,
+                "nodeId": stat.node_id,
+                "source": stat.source,
+                "ok": stat.ok,
+                "active": active.contains(url),
+                "score": score,
+                "height": stat.height,
+                "latestHash": stat.latest_hash,
+                "lastSeen": stat.last_seen,
+                "failures": stat.failures,
+            });
+            extend(stat, penalty, &mut entry);
+            (score, entry)
+        })
+        .collect();
+    peers.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1["url"].as_str().cmp(&b.1["url"].as_str())));
+    peers.into_iter().map(|(_, entry)| entry).collect()
+}
+
+async fn list_peers(State(app): State<AppState>) -> Response {
+    let height = app.state.read().unwrap().blocks.last().map(|b| b.index).unwrap_or(0);
+    let peers = peer_summaries(&app, |_, _, _| {});
+    Json(serde_json::json!({ "ok": true, "height": height, "peers": peers })).into_response()
+}
+
+/// Gate for `/api/admin/*`: the `BULEN_ADMIN_TOKEN` header when configured,
+/// otherwise loopback callers only.
+#[allow(clippy::result_large_err)]
+fn verify_admin(config: &Config, addr: SocketAddr, headers: &HeaderMap) -> Result<(), Response> {
+    verify_operator(config.admin_token.as_ref(), "x-bulen-admin-token", addr, headers)
+}
+
+/// Gate for listing and cancelling payments: `BULEN_MERCHANT_TOKEN` or
+/// whatever `verify_admin` accepts.
+#[allow(clippy::result_large_err)]
+fn verify_merchant(config: &Config, addr: SocketAddr, headers: &HeaderMap) -> Result<(), Response> {
+    verify_operator(config.merchant_token.as_ref(), "x-bulen-merchant-token", addr, headers)
+        .or_else(|_| verify_admin(config, addr, headers))
+}
+
+/// Gate for `/api/device/*`, like `verify_admin` with `BULEN_DEVICE_TOKEN`.
+#[allow(clippy::result_large_err)]
+fn verify_device(config: &Config, addr: SocketAddr, headers: &HeaderMap) -> Result<(), Response> {
+    verify_operator(config.device_token.as_ref(), "x-bulen-device-token", addr, headers)
+}
+
+#[allow(clippy::result_large_err)]
+fn verify_operator(
+    token: Option<&String>,
+    header: &str,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<(), Response> {
+    let allowed = match token {
+        Some(expected) => headers.get(header).and_then(|v| v.to_str().ok()) == Some(expected),
+        None => addr.ip().is_loopback(),
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err((StatusCode::FORBIDDEN, "Forbidden").into_response())
+    }
+}
+
+/// Records a battery level and starts or ends sleep; charging never sleeps.
+fn apply_battery_level(config: &Config, power: &mut PowerState, level: f64, now: u64) {
+    power.battery_level = Some(level);
+    let sleep = level < config.low_battery_threshold && !power.charging;
+    match power.sleeping_since {
+        None if sleep => power.sleeping_since = Some(now),
+        Some(since) if !sleep => {
+            power.slept_seconds += now.saturating_sub(since);
+            power.sleeping_since = None;
+        }
+        _ => {}
+    }
+}
+
+fn power_summary(app: &AppState) -> serde_json::Value {
+    let power = app.power.lock().unwrap();
+    serde_json::json!({
+        "batteryLevel": power.battery_level,
+        "charging": power.charging,
+        "metered": power.metered,
+        "powerWatts": power.power_watts,
+        "sleeping": power.sleeping_since.is_some(),
+        "sleptSeconds": slept_seconds(&power, now_unix()),
+        "lowBatteryThreshold": app.config.low_battery_threshold,
+        "producing": !app.config.light_client && power.sleeping_since.is_none(),
+        "syncIntervalMs": power_sync_interval(&app.config, &power).as_millis() as u64,
+    })
+}
+
+#[derive(Deserialize)]
+struct BatteryInput {
+    level: f64,
+    charging: Option<bool>,
+    metered: Option<bool>,
+}
+
+/// Battery and link report from the device; omitted flags keep their value.
+async fn device_battery(
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<BatteryInput>,
+) -> Response {
+    if let Err(resp) = verify_device(&app.config, addr, &headers) {
+        return resp;
+    }
+    if !(0.0..=1.0).contains(&input.level) {
+        return (StatusCode::BAD_REQUEST, "Invalid level (0..1)").into_response();
+    }
+    let sleeping = {
+        let mut power = app.power.lock().unwrap();
+        power.charging = input.charging.unwrap_or(power.charging);
+        power.metered = input.metered.unwrap_or(power.metered);
+        apply_battery_level(&app.config, &mut power, input.level, now_unix());
+        save_power_state(&app.config, &power);
+        power.sleeping_since.is_some()
+    };
+    app.sync_wake.notify_one();
+    Json(serde_json::json!({
+        "ok": true,
+        "sleeping": sleeping,
+        "threshold": app.config.low_battery_threshold,
+        "power": power_summary(&app),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnergyInput {
+    power_watts: Option<f64>,
+    battery_level: Option<f64>,
+}
+
+async fn device_energy(
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(input): Json<EnergyInput>,
+) -> Response {
+    if let Err(resp) = verify_device(&app.config, addr, &headers) {
+        return resp;
+    }
+    if input.power_watts.is_some_and(|w| !(0.0..=500.0).contains(&w)) {
+        return (StatusCode::BAD_REQUEST, "Invalid powerWatts (0..500)").into_response();
+    }
+    if input.battery_level.is_some_and(|l| !(0.0..=1.0).contains(&l)) {
+        return (StatusCode::BAD_REQUEST, "Invalid batteryLevel (0..1)").into_response();
+    }
+    let mut power = app.power.lock().unwrap();
+    if let Some(level) = input.battery_level {
+        apply_battery_level(&app.config, &mut power, level, now_unix());
+    }
+    if let Some(watts) = input.power_watts.filter(|w| *w > 0.0) {
+        power.power_watts = Some(watts);
+    }
+    save_power_state(&app.config, &power);
+    Json(serde_json::json!({
+        "ok": true,
+        "powerWatts": power.power_watts,
+        "efficiencyBoost": compute_efficiency_boost(power.power_watts),
+        "batteryLevel": power.battery_level,
+    }))
+    .into_response()
+}
+
+/// Address book with scores and backoff, plus every penalty record and ban.
+async fn admin_peers(
+    State(app): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = verify_admin(&app.config, addr, &headers) {
+        return resp;
+    }
+    let now = now_unix();
+    let penalties = app.p2p_sessions.lock().unwrap().penalties.clone();
+    let peers = peer_summaries(&app, |stat, penalty, entry| {
+        entry["successes"] = serde_json::json!(stat.successes);
+        entry["consecutiveFailures"] = serde_json::json!(stat.consecutive_failures);
+        entry["avgLatencyMs"] = serde_json::json!(stat.avg_latency_ms);
+        entry["backoffUntil"] =
+            serde_json::json!((stat.next_attempt_at > now).then_some(stat.next_attempt_at));
+        entry["penaltyPoints"] = serde_json::json!(penalty.map(|p| p.points).unwrap_or(0));
+    });
+    let bans: Vec<serde_json::Value> = penalties
+        .iter()
+        .map(|(key, penalty)| {
+            let mut entry = serde_json::to_value(penalty).unwrap_or_default();
+            entry["peer"] = serde_json::json!(key);
+            entry["banned"] = serde_json::json!(penalty.banned_until > now);
+            entry
+        })
+        .collect();
+    Json(serde_json::json!({ "ok": true, "peers": peers, "penalties": bans })).into_response()
+}
+
+fn prometheus_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
 async fn metrics(
     State(app): State<AppState>,
 ) -> impl IntoResponse {
@@ -1286,16 +4795,9 @@ async fn metrics(
     let latest_height = state.blocks.last().map(|b| b.index).unwrap_or(0);
     let accounts_total = state.accounts.len();
     let mempool_size = state.mempool.len();
-    let uptime_seconds = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(state.started_at))
-        .unwrap_or_default()
-        .as_secs();
-    let loyalty_boost = compute_loyalty_boost(&app.config, uptime_seconds);
-    let device_boost = compute_device_boost(&app.config);
-    let hourly = app.config.base_uptime_reward_per_hour
-        * app.config.reward_weight
-        * loyalty_boost
-        * device_boost;
+    let uptime_seconds = reward_uptime_seconds(&app);
+    let reward = uptime_reward(&app, uptime_seconds, &app.config.device_class);
+    let hourly = reward.hourly;
     let total = (uptime_seconds as f64 / 3600.0) * hourly;
     let payments = app.payments.lock().unwrap();
     let labels = format!(
@@ -1319,11 +4821,16 @@ async fn metrics(
     out.push_str(&format!(
         "bulen_peers_count{} {}\n",
         labels,
-        app.config.peers.len()
+        current_active_peers(&app).len()
     ));
     out.push_str(&format!(
-        "bulen_reward_weight{} {}\n",
-        labels, app.config.reward_weight
+        "bulen_known_peers{} {}\n",
+        labels,
+        app.peer_stats.lock().unwrap().len()
+    ));
+    out.push_str(&format!(
+        "bulen_reward_weight{} {}\n",
+        labels, app.config.reward_weight
     ));
     out.push_str(&format!(
         "bulen_reward_estimate_hourly{} {}\n",
@@ -1335,12 +4842,25 @@ async fn metrics(
     ));
     out.push_str(&format!(
         "bulen_loyalty_boost{} {}\n",
-        labels, loyalty_boost
+        labels, reward.loyalty_boost
     ));
     out.push_str(&format!(
         "bulen_device_boost{} {}\n",
-        labels, device_boost
+        labels, reward.device_boost
+    ));
+    out.push_str(&format!(
+        "bulen_efficiency_boost{} {}\n",
+        labels, reward.efficiency_boost
     ));
+    {
+        let power = app.power.lock().unwrap();
+        out.push_str(&format!(
+            "bulen_power_sleeping{} {}\n",
+            labels,
+            u8::from(power.sleeping_since.is_some())
+        ));
+        out.push_str(&format!("bulen_power_metered{} {}\n", labels, u8::from(power.metered)));
+    }
     out.push_str(&format!(
         "bulen_rewards_produced{} {}\n",
         labels, state.produced_rewards
@@ -1352,8 +4872,64 @@ async fn metrics(
     out.push_str(&format!(
         "bulen_payments_pending{} {}\n",
         labels,
-        payments.iter().filter(|p| p.status == "pending").count()
+        payments.count_status("pending")
+    ));
+    let gossip = &app.gossip.counters;
+    for (name, counter) in [
+        ("sent", &gossip.sent),
+        ("relayed", &gossip.relayed),
+        ("duplicate", &gossip.duplicates),
+        ("dropped", &gossip.dropped),
+        ("retried", &gossip.retries),
+        ("compact_block", &gossip.compact_blocks),
+        ("compact_missing_tx", &gossip.compact_missing),
+    ] {
+        out.push_str(&format!(
+            "bulen_gossip_{}_total{} {}\n",
+            name,
+            labels,
+            counter.load(Ordering::Relaxed)
+        ));
+    }
+    out.push_str(&format!(
+        "bulen_peer_links{} {}\n",
+        labels,
+        app.peer_links.lock().unwrap().links.len()
+    ));
+    out.push_str(&format!(
+        "bulen_gossip_queued{} {}\n",
+        labels,
+        app.gossip.queues.lock().unwrap().values().map(|q| q.len()).sum::<usize>()
     ));
+    // Per-peer series for active peers and peers this node has contacted;
+    // addresses only heard of through exchange would read as down. Lag is our
+    // height minus the peer's (negative when the peer is ahead).
+    let active = current_active_peers(&app);
+    let book = app.peer_stats.lock().unwrap();
+    let mut peer_urls: Vec<&String> = book
+        .iter()
+        .filter(|(url, stat)| active.contains(url) || stat.successes > 0 || stat.failures > 0)
+        .map(|(url, _)| url)
+        .collect();
+    peer_urls.sort();
+    for url in peer_urls {
+        let stat = &book[url];
+        let peer_labels = format!(
+            "{{chain_id=\"{}\",node_id=\"{}\",peer=\"{}\",peer_node_id=\"{}\"}}",
+            app.config.chain_id,
+            app.config.node_id,
+            prometheus_label(url),
+            prometheus_label(stat.node_id.as_deref().unwrap_or(""))
+        );
+        out.push_str(&format!("bulen_peer_up{} {}\n", peer_labels, stat.ok as u8));
+        out.push_str(&format!(
+            "bulen_peer_height_lag{} {}\n",
+            peer_labels,
+            latest_height as i64 - stat.height as i64
+        ));
+        out.push_str(&format!("bulen_peer_failures_total{} {}\n", peer_labels, stat.failures));
+        out.push_str(&format!("bulen_peer_last_seen_seconds{} {}\n", peer_labels, stat.last_seen));
+    }
     Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "text/plain; version=0.0.4")
@@ -1375,6 +4951,7 @@ async fn info(State(app): State<AppState>) -> impl IntoResponse {
         "chainId": app.config.chain_id,
         "nodeId": app.config.node_id,
         "requireSignatures": app.config.require_signatures,
+        "addressPrefix": app.config.address_prefix,
         "enableFaucet": app.config.enable_faucet,
         "protocolMajor": protocol_major(&app.config.protocol_version),
         "height": state.blocks.last().map(|b| b.index).unwrap_or(0),
@@ -1396,15 +4973,26 @@ fn prune_wallet_store(store: &mut WalletStore) {
 #[derive(Deserialize)]
 struct WalletChallengeInput {
     address: String,
+    /// May be omitted for secp256k1 wallets identified by an Ethereum
+    /// address; the key is then recovered from the `personal_sign` signature.
+    #[serde(default, alias = "publicKey")]
     public_key: String,
+    #[serde(alias = "walletType")]
     wallet_type: Option<String>,
+    #[serde(default, alias = "keyType")]
+    key_type: Option<String>,
 }
 
 async fn wallets_challenge(
     State(app): State<AppState>,
     Json(input): Json<WalletChallengeInput>,
 ) -> Response {
-    if input.address.is_empty() || input.public_key.is_empty() {
+    let key_type = input.key_type.clone().unwrap_or_else(default_key_type);
+    if !KEY_TYPES.contains(&key_type.as_str()) {
+        return (StatusCode::BAD_REQUEST, "Unsupported keyType").into_response();
+    }
+    let recoverable = key_type == "secp256k1" && input.address.starts_with("0x");
+    if input.address.is_empty() || (input.public_key.is_empty() && !recoverable) {
         return (StatusCode::BAD_REQUEST, "Missing address or publicKey").into_response();
     }
     let mut store = app.wallet_store.lock().unwrap();
@@ -1425,6 +5013,7 @@ async fn wallets_challenge(
         address: input.address,
         public_key: input.public_key,
         wallet_type: input.wallet_type.unwrap_or_else(|| "unknown".into()),
+        key_type,
         nonce,
         message: message.clone(),
         created_at: now_iso(),
@@ -1443,24 +5032,31 @@ async fn wallets_challenge(
 
 #[derive(Deserialize)]
 struct WalletVerifyInput {
+    #[serde(alias = "challengeId")]
     challenge_id: String,
     signature: String,
 }
 
-fn verify_wallet_signature(challenge: &WalletChallenge, signature_b64: &str) -> bool {
-    let signature_bytes = match base64::engine::general_purpose::STANDARD.decode(signature_b64) {
-        Ok(b) => b,
-        Err(_) => return false,
-    };
-    let signature = match Signature::from_der(&signature_bytes) {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
-    let key = match VerifyingKey::from_public_key_pem(&challenge.public_key) {
-        Ok(k) => k,
-        Err(_) => return false,
-    };
-    key.verify(challenge.message.as_bytes(), &signature).is_ok()
+/// Checks the challenge signature. Ethereum-addressed secp256k1 wallets are
+/// verified by recovering the signer from `personal_sign` and comparing the
+/// derived address; the recovered key is returned so the session records it.
+fn verify_wallet_signature(challenge: &WalletChallenge, signature: &str) -> Result<String, String> {
+    let message = challenge.message.as_bytes();
+    if challenge.key_type == "secp256k1" && challenge.address.starts_with("0x") {
+        let key = recover_personal_sign(message, signature)?;
+        if !eth_address(&key).eq_ignore_ascii_case(&challenge.address) {
+            return Err("signer does not match address".into());
+        }
+        let recovered = hex::encode(key.to_encoded_point(true).as_bytes());
+        if !challenge.public_key.is_empty()
+            && parse_secp256k1_key(&challenge.public_key)? != key
+        {
+            return Err("signer does not match publicKey".into());
+        }
+        return Ok(recovered);
+    }
+    verify_scheme_signature(&challenge.key_type, &challenge.public_key, message, signature)?;
+    Ok(challenge.public_key.clone())
 }
 
 async fn wallets_verify(
@@ -1476,16 +5072,18 @@ async fn wallets_verify(
     if challenge.status == "verified" {
         return (StatusCode::BAD_REQUEST, "Challenge already used").into_response();
     }
-    if !verify_wallet_signature(challenge, &input.signature) {
-        return (StatusCode::BAD_REQUEST, "Invalid signature").into_response();
-    }
+    let public_key = match verify_wallet_signature(challenge, &input.signature) {
+        Ok(key) => key,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid signature").into_response(),
+    };
     challenge.status = "verified".into();
     let now = OffsetDateTime::now_utc();
     let session = WalletSession {
         id: format!("sess_{}", Uuid::new_v4().as_simple()),
         address: challenge.address.clone(),
-        public_key: challenge.public_key.clone(),
+        public_key,
         wallet_type: challenge.wallet_type.clone(),
+        key_type: challenge.key_type.clone(),
         created_at: now_iso(),
         expires_at: (now + Duration::from_secs(24 * 3600))
             .format(&time::format_description::well_known::Rfc3339)
@@ -1496,6 +5094,8 @@ async fn wallets_verify(
     Json(serde_json::json!({
         "sessionId": session.id,
         "address": session.address,
+        // On-chain account controlled by the verified key.
+        "accountAddress": derive_address(&app.config, &session.key_type, &session.public_key).ok(),
         "expiresAt": session.expires_at
     }))
     .into_response()
@@ -1518,22 +5118,243 @@ async fn wallets_info(State(app): State<AppState>) -> impl IntoResponse {
         "chainId": app.config.chain_id,
         "chainName": "BulenCoin Devnet",
         "rpcUrl": format!("http://localhost:{}/api", app.config.http_port),
+        "keyTypes": KEY_TYPES,
         "connectors": [
-            {"type": "metamask"},
-            {"type": "walletconnect"},
-            {"type": "ledger"}
+            {"type": "metamask", "keyType": "secp256k1"},
+            {"type": "walletconnect", "keyType": "secp256k1"},
+            {"type": "ledger", "keyType": "secp256k1"}
         ]
     }))
 }
 
+#[derive(Deserialize)]
+struct MultisigProposalInput {
+    multisig: MultisigPolicy,
+    #[serde(default)]
+    to: Option<String>,
+    amount: u64,
+    #[serde(default)]
+    fee: u64,
+    #[serde(default)]
+    nonce: Option<u64>,
+    #[serde(default)]
+    memo: Option<String>,
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default, alias = "expiresAtHeight")]
+    expires_at_height: Option<u64>,
+    #[serde(default)]
+    outputs: Vec<TransferOutput>,
+    #[serde(default)]
+    lock: Option<LockSchedule>,
+    /// A co-signer's signature over the proposal payload; opening a proposal
+    /// requires one, so only key holders can create them.
+    #[serde(default)]
+    signature: Option<PartialSignature>,
+}
+
+/// Open proposals kept in total and per multisig account.
+const MAX_MULTISIG_PROPOSALS: usize = 2_048;
+const MAX_PROPOSALS_PER_MULTISIG: usize = 32;
+
+fn prune_multisig_proposals(proposals: &mut Vec<MultisigProposal>) {
+    let now = OffsetDateTime::now_utc();
+    proposals.retain(|p| parse_rfc3339(&p.expires_at).map(|dt| dt > now).unwrap_or(false));
+}
+
+fn multisig_proposal_view(proposal: &MultisigProposal) -> serde_json::Value {
+    let threshold = proposal
+        .transaction
+        .multisig
+        .as_ref()
+        .map(|p| p.threshold)
+        .unwrap_or(0);
+    serde_json::json!({
+        "proposal": proposal,
+        "signaturesCollected": proposal.transaction.signatures.len(),
+        "threshold": threshold,
+    })
+}
+
+/// Starts collecting co-signatures for a multisig transaction, opened with
+/// the first co-signer's signature over the canonical payload. The others
+/// sign `payload` and attach their signature with `POST .../signatures`.
+async fn post_multisig_proposal(
+    State(app): State<AppState>,
+    Json(input): Json<MultisigProposalInput>,
+) -> Response {
+    if let Err(err) = validate_multisig_policy(&input.multisig) {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    let memo = normalize_memo(&input.memo);
+    if input.memo.is_some() && memo != input.memo {
+        return (StatusCode::BAD_REQUEST, "memo too long (max 256 chars)").into_response();
+    }
+    let Some(first_signature) = input.signature else {
+        return (StatusCode::BAD_REQUEST, "a co-signer signature is required").into_response();
+    };
+    let from = derive_multisig_address(&app.config, &input.multisig);
+    let tx = {
+        let state = app.state.read().unwrap();
+        let current_nonce = state
+            .accounts
+            .get(&canonical_address(&app.config, &from))
+            .map(|a| a.nonce)
+            .unwrap_or(0);
+        let mut tx = Transaction {
+            id: String::new(),
+            from,
+            to: input.to.unwrap_or_default(),
+            amount: input.amount,
+            fee: input.fee,
+            nonce: input.nonce.unwrap_or(current_nonce + 1),
+            timestamp: now_iso(),
+            action: input.action.unwrap_or_else(|| "transfer".into()),
+            memo,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(app.config.chain_id.clone()),
+            expires_at_height: input.expires_at_height,
+            outputs: input.outputs,
+            multisig: Some(input.multisig),
+            signatures: vec![],
+            lock: input.lock,
+        };
+        tx.id = compute_transaction_id(&tx);
+        if let Err(err) = validate_destinations(&app.config, &tx) {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        if let Err(err) = validate_transaction(&app.config, &state, &tx) {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        let policy = tx.multisig.as_ref().expect("set above");
+        if let Err(err) = verify_partial_signature(policy, &tx, &first_signature) {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        tx.signatures.push(first_signature);
+        tx
+    };
+    let mut proposals = app.multisig_proposals.lock().unwrap();
+    prune_multisig_proposals(&mut proposals);
+    if let Some(existing) = proposals.iter().find(|p| p.id == tx.id) {
+        return Json(multisig_proposal_view(existing)).into_response();
+    }
+    let open_for_account = proposals.iter().filter(|p| p.transaction.from == tx.from).count();
+    if proposals.len() >= MAX_MULTISIG_PROPOSALS
+        || open_for_account >= MAX_PROPOSALS_PER_MULTISIG
+    {
+        return (StatusCode::TOO_MANY_REQUESTS, "too many open multisig proposals")
+            .into_response();
+    }
+    let threshold = tx.multisig.as_ref().map(|p| p.threshold).unwrap_or(0);
+    let status = if tx.signatures.len() >= threshold { "ready" } else { "collecting" };
+    let proposal = MultisigProposal {
+        id: tx.id.clone(),
+        payload: canonical_payload(&tx),
+        transaction: tx,
+        created_at: now_iso(),
+        expires_at: (OffsetDateTime::now_utc() + Duration::from_secs(24 * 3600))
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_else(|_| now_iso()),
+        status: status.into(),
+    };
+    proposals.push(proposal.clone());
+    save_multisig_proposals(&app.config, &proposals);
+    Json(multisig_proposal_view(&proposal)).into_response()
+}
+
+async fn get_multisig_proposal(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+) -> Response {
+    let mut proposals = app.multisig_proposals.lock().unwrap();
+    prune_multisig_proposals(&mut proposals);
+    match proposals.iter().find(|p| p.id == id) {
+        Some(p) => Json(multisig_proposal_view(p)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Proposal not found or expired").into_response(),
+    }
+}
+
+async fn post_multisig_signature(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+    Json(input): Json<PartialSignature>,
+) -> Response {
+    let mut proposals = app.multisig_proposals.lock().unwrap();
+    prune_multisig_proposals(&mut proposals);
+    let Some(proposal) = proposals.iter_mut().find(|p| p.id == id) else {
+        return (StatusCode::NOT_FOUND, "Proposal not found or expired").into_response();
+    };
+    if proposal.status == "broadcast" {
+        return (StatusCode::CONFLICT, "Proposal already broadcast").into_response();
+    }
+    let Some(policy) = proposal.transaction.multisig.clone() else {
+        return (StatusCode::BAD_REQUEST, "not a multisig transaction").into_response();
+    };
+    if let Err(err) = verify_partial_signature(&policy, &proposal.transaction, &input) {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    let signatures = &mut proposal.transaction.signatures;
+    signatures.retain(|s| s.public_key.trim() != input.public_key.trim());
+    signatures.push(input);
+    if signatures.len() >= policy.threshold {
+        proposal.status = "ready".into();
+    }
+    let view = multisig_proposal_view(proposal);
+    save_multisig_proposals(&app.config, &proposals);
+    Json(view).into_response()
+}
+
+async fn broadcast_multisig_proposal(
+    Path(id): Path<String>,
+    State(app): State<AppState>,
+) -> Response {
+    let tx = {
+        let mut proposals = app.multisig_proposals.lock().unwrap();
+        prune_multisig_proposals(&mut proposals);
+        let Some(proposal) = proposals.iter().find(|p| p.id == id) else {
+            return (StatusCode::NOT_FOUND, "Proposal not found or expired").into_response();
+        };
+        proposal.transaction.clone()
+    };
+    let Some(policy) = tx.multisig.as_ref() else {
+        return (StatusCode::BAD_REQUEST, "not a multisig transaction").into_response();
+    };
+    if let Err(err) = verify_multisig(&app.config, policy, &tx) {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    {
+        let mut state = app.state.write().unwrap();
+        if is_known_transaction(&state, &tx.id) {
+            return (StatusCode::CONFLICT, "duplicate transaction").into_response();
+        }
+        if let Err(err) = validate_transaction(&app.config, &state, &tx) {
+            return (StatusCode::BAD_REQUEST, err).into_response();
+        }
+        state.mempool.push(tx.clone());
+    }
+    {
+        let mut proposals = app.multisig_proposals.lock().unwrap();
+        if let Some(proposal) = proposals.iter_mut().find(|p| p.id == id) {
+            proposal.status = "broadcast".into();
+        }
+        save_multisig_proposals(&app.config, &proposals);
+    }
+    broadcast_transaction(&app, &tx);
+    Json(tx).into_response()
+}
+
 async fn p2p_tx(
     State(app): State<AppState>,
     headers: HeaderMap,
     Json(payload): Json<serde_json::Value>,
 ) -> Response {
-    if let Err(resp) = verify_p2p_headers(&app.config, &headers) {
-        return resp;
-    }
+    let session = match verify_peer_session(&app, &headers) {
+        Ok(session) => session,
+        Err(resp) => return resp,
+    };
     let tx: Transaction = match serde_json::from_value(
         payload
             .get("transaction")
@@ -1543,12 +5364,57 @@ async fn p2p_tx(
         Ok(tx) => tx,
         Err(_) => return (StatusCode::BAD_REQUEST, "bad tx payload").into_response(),
     };
+    match accept_peer_tx(&app, &session.node_id, tx) {
+        Ok(body) => Json(body).into_response(),
+        Err(reject) => reject.into_response(),
+    }
+}
+
+/// Admits a transaction received from peer `node_id` over HTTP or a
+/// WebSocket link and relays it on when it is new.
+fn accept_peer_tx(
+    app: &AppState,
+    node_id: &str,
+    tx: Transaction,
+) -> Result<serde_json::Value, (StatusCode, String)> {
+    if app.config.light_client {
+        return Ok(serde_json::json!({ "ok": true, "ignored": true }));
+    }
+    let reject = |err: &str| (StatusCode::BAD_REQUEST, err.to_string());
+    if tx.id != compute_transaction_id(&tx) {
+        penalize_peer(app, node_id, false, "transaction id does not match payload");
+        return Err(reject("transaction id does not match payload"));
+    }
+    if gossip_already_seen(app, &tx.id, node_id) {
+        app.gossip.counters.duplicates.fetch_add(1, Ordering::Relaxed);
+        return Ok(serde_json::json!({ "ok": true, "ignored": true }));
+    }
     let mut state = app.state.write().unwrap();
-    if state.mempool.iter().any(|t| t.id == tx.id) {
-        return Json(serde_json::json!({ "ok": true, "ignored": true })).into_response();
+    if is_known_transaction(&state, &tx.id) {
+        drop(state);
+        gossip_mark_seen(app, &tx.id, Some(node_id));
+        return Ok(serde_json::json!({ "ok": true, "ignored": true }));
+    }
+    if let Err(err) =
+        admit_signing_format(&app.config, &tx).and_then(|_| admit_action(&app.config, &tx, true))
+    {
+        return Err(reject(&err));
+    }
+    if let Err(err) = verify_signature(&app.config, &tx) {
+        drop(state);
+        penalize_peer(app, node_id, false, &err);
+        return Err(reject(&err));
     }
-    state.mempool.push(tx);
-    Json(serde_json::json!({ "ok": true })).into_response()
+    // Envelope failures (nonce, fee, expiry) can be honest races; not penalized.
+    if let Err(err) = validate_envelope(&app.config, &state, &tx) {
+        return Err(reject(&err));
+    }
+    state.mempool.push(tx.clone());
+    drop(state);
+    gossip_mark_seen(app, &tx.id, Some(node_id));
+    app.gossip.counters.relayed.fetch_add(1, Ordering::Relaxed);
+    broadcast_transaction(app, &tx);
+    Ok(serde_json::json!({ "ok": true }))
 }
 
 async fn p2p_block(
@@ -1556,11 +5422,10 @@ async fn p2p_block(
     headers: HeaderMap,
     Json(payload): Json<serde_json::Value>,
 ) -> Response {
-    if !headers.is_empty() {
-        if let Err(resp) = verify_p2p_headers(&app.config, &headers) {
-            return resp;
-        }
-    }
+    let session = match verify_peer_session(&app, &headers) {
+        Ok(session) => session,
+        Err(resp) => return resp,
+    };
     let block: Block = match serde_json::from_value(
         payload
             .get("block")
@@ -1570,556 +5435,4742 @@ async fn p2p_block(
         Ok(block) => block,
         Err(_) => return (StatusCode::BAD_REQUEST, "bad block payload").into_response(),
     };
+    match accept_peer_block(&app, &session.node_id, block) {
+        Ok(body) => Json(body).into_response(),
+        Err(reject) => reject.into_response(),
+    }
+}
 
+/// Stores a block received from peer `node_id`, switches to it when it
+/// extends the best chain and relays it on in that case.
+fn accept_peer_block(
+    app: &AppState,
+    node_id: &str,
+    block: Block,
+) -> Result<serde_json::Value, (StatusCode, String)> {
+    if app.config.light_client {
+        return Ok(serde_json::json!({ "ok": true, "ignored": true }));
+    }
     let mut state = app.state.write().unwrap();
     let mut incoming = block.clone();
     incoming.hash = String::new();
     let expected_hash = compute_block_hash(&incoming);
     if block.hash != expected_hash {
-        return (StatusCode::BAD_REQUEST, "invalid block hash").into_response();
+        drop(state);
+        penalize_peer(app, node_id, true, "invalid block hash");
+        return Err((StatusCode::BAD_REQUEST, "invalid block hash".into()));
+    }
+    if gossip_already_seen(app, &block.hash, node_id) {
+        app.gossip.counters.duplicates.fetch_add(1, Ordering::Relaxed);
+        return Ok(serde_json::json!({ "ok": true, "ignored": true }));
     }
     state.block_store.insert(block.hash.clone(), block.clone());
     let best_tip = block.hash.clone();
     if let Some(chain) = build_chain_from_tip(&state.block_store, &best_tip) {
         let current_height = state.blocks.last().map(|b| b.index).unwrap_or(0);
-        if let Some(last) = chain.last() {
-            if last.index >= current_height {
-                let current_accounts = state.accounts.clone();
-                match rebuild_state_from_chain(&app.config, &chain, &current_accounts) {
-                    Ok(mut rebuilt) => {
-                        // Preserve mempool entries not yet included
-                        let included: HashSet<String> = chain
-                            .iter()
-                            .flat_map(|b| b.transactions.iter().map(|t| t.id.clone()))
-                            .collect();
-                        rebuilt.mempool = state
-                            .mempool
-                            .iter()
-                            .filter(|t| !included.contains(&t.id))
-                            .cloned()
-                            .collect();
-                        *state = rebuilt;
-                        save_state(&app.config, &state);
-                        drop(state);
-                        update_payments(&app);
-                        return Json(serde_json::json!({ "ok": true, "reorg": true })).into_response();
-                    }
-                    Err(err) => {
-                        return (StatusCode::BAD_REQUEST, err).into_response();
-                    }
+        if let Some(last) = chain.last()
+            && last.index >= current_height
+        {
+            match adopt_chain(&app.config, &mut state, &chain) {
+                Ok(dropped) => {
+                    save_state(&app.config, &state);
+                    drop(state);
+                    gossip_mark_seen(app, &block.hash, Some(node_id));
+                    update_payments(app);
+                    app.gossip.counters.relayed.fetch_add(1, Ordering::Relaxed);
+                    broadcast_block(app, &block);
+                    return Ok(serde_json::json!({ "ok": true, "reorg": dropped > 0 }));
+                }
+                Err(err) => {
+                    state.block_store.remove(&block.hash);
+                    drop(state);
+                    penalize_peer(app, node_id, true, &err);
+                    return Err((StatusCode::BAD_REQUEST, err));
                 }
             }
         }
     }
-    Json(serde_json::json!({ "ok": true, "ignored": false })).into_response()
+    drop(state);
+    gossip_mark_seen(app, &block.hash, Some(node_id));
+    Ok(serde_json::json!({ "ok": true, "ignored": false }))
 }
 
-async fn broadcast_transaction(app: AppState, tx: Transaction) {
-    if app.config.peers.is_empty() {
-        return;
+/// Makes `chain` (from genesis) our main chain: applies it on top of the
+/// common ancestor, or replays it from genesis when the ancestor is beyond
+/// the undo history. Returns how many of our blocks were dropped.
+fn adopt_chain(config: &Config, state: &mut StateData, chain: &[Block]) -> Result<u64, String> {
+    if chain.first().is_none_or(|b| b.index != 0) {
+        return Err("missing genesis".into());
     }
-    let payload = serde_json::json!({
-        "transaction": tx,
-    });
-    for peer in app.config.peers.clone() {
-        let client = app.client.clone();
-        let payload = payload.clone();
-        let token = app.config.p2p_token.clone();
-        let proto = app.config.protocol_version.clone();
-        let node_id = app.config.node_id.clone();
-        tokio::spawn(async move {
-            let mut req = client
-                .post(format!("{}/p2p/tx", peer))
-                .header("x-bulen-protocol-version", proto)
-                .header("x-bulen-node-id", node_id);
-            if let Some(t) = token.as_ref() {
-                req = req.header("x-bulen-p2p-token", t);
-            }
-            let _ = req.json(&payload).send().await;
-        });
+    let shared = chain.iter().zip(&state.blocks).take_while(|(a, b)| a.hash == b.hash).count();
+    let ancestor = shared.max(1) as u64 - 1;
+    if can_roll_back_to(state, ancestor) {
+        return switch_to_branch(config, state, ancestor, &chain[ancestor as usize + 1..]);
     }
+    let tip = state.blocks.last().map(|b| b.index).unwrap_or(0);
+    let mut rebuilt = rebuild_state_from_chain(config, chain)?;
+    let included: HashSet<&String> = chain
+        .iter()
+        .flat_map(|b| b.transactions.iter().map(|t| &t.id))
+        .collect();
+    rebuilt.mempool = state
+        .mempool
+        .iter()
+        .filter(|t| !included.contains(&t.id))
+        .cloned()
+        .collect();
+    *state = rebuilt;
+    Ok(tip - ancestor)
 }
 
-async fn broadcast_block(app: AppState, block: Block) {
-    if app.config.peers.is_empty() {
-        return;
-    }
-    let payload = serde_json::json!({
-        "block": block,
-    });
-    for peer in app.config.peers.clone() {
-        let client = app.client.clone();
-        let payload = payload.clone();
-        let token = app.config.p2p_token.clone();
-        let proto = app.config.protocol_version.clone();
-        let node_id = app.config.node_id.clone();
-        tokio::spawn(async move {
-            let mut req = client
-                .post(format!("{}/p2p/block", peer))
-                .header("x-bulen-protocol-version", proto)
-                .header("x-bulen-node-id", node_id);
-            if let Some(t) = token.as_ref() {
-                req = req.header("x-bulen-p2p-token", t);
-            }
-            let _ = req.json(&payload).send().await;
-        });
+/// Upper bound on entries in the gossip seen cache.
+const GOSSIP_SEEN_CAPACITY: usize = 20_000;
+/// Delivery attempts per queued gossip message before it is dropped.
+const GOSSIP_MAX_ATTEMPTS: u32 = 3;
+
+/// Whether message `id` was already accepted here; if so, records that
+/// `peer` has it too. Nothing is recorded for new messages, so an invalid
+/// copy cannot shadow a valid one arriving later.
+fn gossip_already_seen(app: &AppState, id: &str, peer: &str) -> bool {
+    let now = now_unix();
+    let mut seen = app.gossip.seen.lock().unwrap();
+    match seen.entries.get_mut(id).filter(|e| e.expires_at > now) {
+        Some(entry) => {
+            entry.peers.insert(peer.to_string());
+            true
+        }
+        None => false,
     }
 }
 
-async fn fetch_block_from_peer(
-    app: &AppState,
-    peer: &str,
-    height: u64,
-) -> Result<Block, String> {
-    let mut req = app
-        .client
-        .get(format!("{}/api/blocks/{}", peer, height))
-        .header("x-bulen-protocol-version", &app.config.protocol_version)
-        .header("x-bulen-node-id", &app.config.node_id);
-    if let Some(t) = app.config.p2p_token.as_ref() {
-        req = req.header("x-bulen-p2p-token", t);
+/// Records that `peer` has message `id`; returns whether the message was new
+/// to this node. Called once a message has been accepted.
+fn gossip_mark_seen(app: &AppState, id: &str, peer: Option<&str>) -> bool {
+    let now = now_unix();
+    let mut seen = app.gossip.seen.lock().unwrap();
+    if let Some(entry) = seen.entries.get_mut(id).filter(|e| e.expires_at > now) {
+        entry.peers.extend(peer.map(str::to_string));
+        return false;
     }
-    req = req.timeout(Duration::from_secs(5));
-    let resp = req.send().await.map_err(|_| "fetch failed".to_string())?;
-    if !resp.status().is_success() {
-        return Err(format!("status {}", resp.status()));
+    // Drop expired entries, then the oldest ones while at capacity. Queue
+    // entries whose id was re-inserted later no longer match and are skipped.
+    while let Some((expires_at, _)) = seen.expiry.front() {
+        if *expires_at > now && seen.entries.len() < GOSSIP_SEEN_CAPACITY {
+            break;
+        }
+        let (expires_at, key) = seen.expiry.pop_front().expect("front checked");
+        if seen.entries.get(&key).is_some_and(|e| e.expires_at == expires_at) {
+            seen.entries.remove(&key);
+        }
     }
-    resp.json::<Block>()
-        .await
-        .map_err(|_| "decode block failed".into())
+    let expires_at = now + app.config.gossip_seen_ttl_secs;
+    seen.entries.insert(
+        id.to_string(),
+        SeenEntry {
+            expires_at,
+            peers: peer.map(str::to_string).into_iter().collect(),
+        },
+    );
+    seen.expiry.push_back((expires_at, id.to_string()));
+    true
 }
 
-async fn fetch_status_from_peer(
-    app: &AppState,
-    peer: &str,
-) -> Result<(u64, Option<String>, Option<String>), String> {
-    let mut req = app
-        .client
-        .get(format!("{}/api/status", peer))
-        .header("x-bulen-protocol-version", &app.config.protocol_version)
-        .header("x-bulen-node-id", &app.config.node_id);
-    if let Some(t) = app.config.p2p_token.as_ref() {
-        req = req.header("x-bulen-p2p-token", t);
-    }
-    req = req.timeout(Duration::from_secs(5));
-    let resp = req.send().await.map_err(|e| e.to_string())?;
-    if !resp.status().is_success() {
-        return Err(format!("status {}", resp.status()));
-    }
-    let json: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
-    Ok((
-        json.get("height").and_then(|v| v.as_u64()).unwrap_or(0),
-        json.get("latestHash")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-        json.get("nodeId")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-    ))
-}
+/// Queues a message for up to `BULEN_GOSSIP_FANOUT` active peers not known to
+/// have it. Peers are ordered by a hash of message id and URL so successive
+/// messages spread over different peers; for blocks, the remaining peers get
+/// a header announcement instead. A full queue drops its oldest entry.
+fn gossip_publish(app: &AppState, id: &str, message: PeerMessage) -> usize {
+    gossip_mark_seen(app, id, None);
+    let active = current_active_peers(app);
+    let node_ids: HashMap<String, Option<String>> = {
+        let book = app.peer_stats.lock().unwrap();
+        active
+            .iter()
+            .map(|url| (url.clone(), book.get(url).and_then(|s| s.node_id.clone())))
+            .collect()
+    };
+    let known = app
+        .gossip
+        .seen
+        .lock()
+        .unwrap()
+        .entries
+        .get(id)
+        .map(|e| e.peers.clone())
+        .unwrap_or_default();
+    let mut targets: Vec<String> = active
+        .into_iter()
+        .filter(|url| {
+            !known.contains(url) && !node_ids[url].as_ref().is_some_and(|n| known.contains(n))
+        })
+        .collect();
+    targets.sort_by_cached_key(|url| format!("{:x}", Sha256::digest(format!("{}|{}", id, url))));
+    let rest = targets.split_off(targets.len().min(app.config.gossip_fanout));
+    let announcement = match &message {
+        PeerMessage::Block { block } => Some(PeerMessage::Header {
+            header: BlockHeader::from(block),
+        }),
+        _ => None,
+    };
 
-async fn sync_with_peer(app: AppState, peer: String) {
-    let (height, latest_hash, node_id) = match fetch_status_from_peer(&app, &peer).await {
-        Ok(res) => res,
-        Err(_) => {
-            let mut stats = app.peer_stats.lock().unwrap();
-            record_peer_stat(&mut stats, &peer, 0, false, None, None);
-            return;
+    let mut queues = app.gossip.queues.lock().unwrap();
+    let mut enqueue = |url: &String, message: PeerMessage| {
+        let queue = queues.entry(url.clone()).or_default();
+        if queue.len() >= app.config.gossip_queue_size {
+            queue.pop_front();
+            app.gossip.counters.dropped.fetch_add(1, Ordering::Relaxed);
         }
+        queue.push_back(GossipItem {
+            id: id.to_string(),
+            message,
+            attempts: 0,
+        });
     };
-    {
-        let mut stats = app.peer_stats.lock().unwrap();
-        record_peer_stat(&mut stats, &peer, height, true, latest_hash.clone(), node_id);
+    for url in &targets {
+        enqueue(url, message.clone());
     }
-    let local_height = {
-        let state = app.state.read().unwrap();
-        state.blocks.last().map(|b| b.index).unwrap_or(0)
-    };
-    if height <= local_height {
-        return;
+    if let Some(announcement) = announcement {
+        for url in &rest {
+            enqueue(url, announcement.clone());
+        }
     }
-    for h in (local_height + 1)..=height {
-        match fetch_block_from_peer(&app, &peer, h).await {
-            Ok(block) => {
-                let mut state = app.state.write().unwrap();
-                if let Err(err) = apply_block(&app.config, &mut state, &block) {
-                    eprintln!("failed to sync block {} from {}: {}", h, peer, err);
-                    break;
+    drop(queues);
+    app.gossip.wake.notify_one();
+    targets.len()
+}
+
+fn broadcast_transaction(app: &AppState, tx: &Transaction) {
+    gossip_publish(app, &tx.id, PeerMessage::Tx { transaction: Box::new(tx.clone()) });
+}
+
+fn broadcast_block(app: &AppState, block: &Block) {
+    gossip_publish(app, &block.hash, PeerMessage::Block { block: block.clone() });
+}
+
+/// Sends a peer's queued messages in order. A failed send is retried on a
+/// later pass (up to `GOSSIP_MAX_ATTEMPTS`) and stops this pass so a slow or
+/// unreachable peer does not hold the sender.
+async fn drain_gossip_queue(app: &AppState, peer: &str) {
+    let counters = &app.gossip.counters;
+    loop {
+        let next = app.gossip.queues.lock().unwrap().get_mut(peer).and_then(|q| q.pop_front());
+        let Some(mut item) = next else {
+            return;
+        };
+        match deliver_to_peer(app, peer, &item.message).await {
+            Ok(()) => {
+                counters.sent.fetch_add(1, Ordering::Relaxed);
+                if !matches!(item.message, PeerMessage::Header { .. }) {
+                    gossip_mark_seen(app, &item.id, Some(peer));
                 }
-                let included: HashSet<String> =
-                    block.transactions.iter().map(|t| t.id.clone()).collect();
-                state.mempool.retain(|t| !included.contains(&t.id));
-                state.blocks.push(block);
-                save_state(&app.config, &state);
             }
             Err(err) => {
-                eprintln!("failed to fetch block {} from {}: {}", h, peer, err);
-                break;
+                item.attempts += 1;
+                if item.attempts >= GOSSIP_MAX_ATTEMPTS {
+                    eprintln!("dropping gossip {} for {}: {}", item.id, peer, err);
+                    counters.dropped.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    counters.retries.fetch_add(1, Ordering::Relaxed);
+                    let mut queues = app.gossip.queues.lock().unwrap();
+                    queues.entry(peer.to_string()).or_default().push_front(item);
+                }
+                return;
             }
         }
     }
 }
 
-fn spawn_peer_sync(app: AppState) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        loop {
-            let peers = app.config.peers.clone();
-            for peer in peers {
-                let app_clone = app.clone();
-                sync_with_peer(app_clone, peer).await;
-            }
-            sleep(Duration::from_millis(app.config.peer_sync_interval_ms)).await;
+/// Seconds between pings on an idle link; a link silent for three intervals
+/// is closed.
+const PEER_LINK_PING_SECS: u64 = 30;
+/// How long a peer whose WebSocket dial failed is reached over HTTP only.
+const PEER_LINK_RETRY_SECS: u64 = 60;
+/// Header announcements wake the sync loop at most this often.
+const HEADER_WAKE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Sends `message` over the peer's WebSocket link, dialing one when the
+/// handshake negotiated it, and falls back to the `/p2p/tx` and `/p2p/block`
+/// HTTP routes. Header announcements are only sent over links, and blocks go
+/// out compact there when `BULEN_COMPACT_BLOCKS` is on.
+async fn deliver_to_peer(app: &AppState, peer: &str, message: &PeerMessage) -> Result<(), String> {
+    let text = match message {
+        PeerMessage::Block { block } if app.config.compact_blocks => {
+            serde_json::to_string(&compact_block(block))
         }
-    })
+        _ => serde_json::to_string(message),
+    }
+    .map_err(|e| e.to_string())?;
+    let node_id = app.peer_stats.lock().unwrap().get(peer).and_then(|s| s.node_id.clone());
+    let existing = {
+        let links = app.peer_links.lock().unwrap();
+        links
+            .links
+            .get(peer)
+            .or_else(|| node_id.as_ref().and_then(|id| links.links.get(id)))
+            .cloned()
+    };
+    let link = match existing {
+        Some(link) => Some(link),
+        None => connect_peer_link(app, peer).await.ok(),
+    };
+    if let Some(link) = link
+        && link.send(text).await.is_ok()
+    {
+        return Ok(());
+    }
+    match message {
+        PeerMessage::Tx { transaction } => {
+            let payload = serde_json::json!({ "transaction": transaction });
+            send_to_peer(app, peer, "/p2p/tx", &payload).await
+        }
+        PeerMessage::Block { block } => {
+            send_to_peer(app, peer, "/p2p/block", &serde_json::json!({ "block": block })).await
+        }
+        _ => Ok(()),
+    }
 }
 
-fn spawn_block_producer(app: AppState) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_millis(app.config.block_interval_ms)).await;
-            let maybe_block = {
-                let mut guard = app.state.write().unwrap();
-                if guard.mempool.is_empty() {
-                    None
-                } else {
-                    let txs = guard.mempool.drain(..).collect::<Vec<_>>();
-                    let previous_hash = guard
-                        .blocks
-                        .last()
-                        .map(|b| b.hash.clone())
-                        .unwrap_or_else(|| "genesis".into());
-                    let mut block = Block {
-                        index: guard.blocks.len() as u64,
-                        previous_hash,
-                        hash: String::new(),
-                        timestamp: now_iso(),
-                        producer: app.config.node_id.clone(),
-                        transactions: txs,
-                    };
-                    if let Err(err) = apply_block(&app.config, &mut guard, &block) {
-                        eprintln!("failed to apply produced block: {}", err);
-                        None
-                    } else {
-                        block.hash = compute_block_hash(&block);
-                        guard.block_store.insert(block.hash.clone(), block.clone());
-                        guard.blocks.push(block.clone());
-                        guard.produced_blocks += 1;
-                        save_state(&app.config, &guard);
-                        Some(block)
-                    }
-                }
-            };
+/// Opens a WebSocket link to `peer` authenticated with our handshake session.
+async fn connect_peer_link(app: &AppState, peer: &str) -> Result<mpsc::Sender<String>, String> {
+    use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 
-            if let Some(block) = maybe_block {
-                broadcast_block(app.clone(), block).await;
-                update_payments(&app);
+    let now = now_unix();
+    if !app.config.p2p_websocket
+        || app.peer_links.lock().unwrap().retry_at.get(peer).is_some_and(|t| *t > now)
+    {
+        return Err("websocket unavailable".into());
+    }
+    let session = peer_session(app, peer).await?;
+    if !session.websocket {
+        return Err("peer does not offer websocket".into());
+    }
+    let url = format!("{}/p2p/ws", peer.replacen("http", "ws", 1));
+    let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+    let headers = request.headers_mut();
+    let mut set = |name: &'static str, value: &str| {
+        if let Ok(value) = value.parse() {
+            headers.insert(name, value);
+        }
+    };
+    set("x-bulen-protocol-version", &app.config.protocol_version);
+    set("x-bulen-peer-id", &app.config.node_id);
+    set("x-bulen-peer-session", &session.token);
+    if let Some(t) = app.config.p2p_token.as_ref() {
+        set("x-bulen-p2p-token", t);
+    }
+    let socket = match tokio_tungstenite::connect_async(request).await {
+        Ok((socket, _)) => socket,
+        Err(err) => {
+            app.peer_links
+                .lock()
+                .unwrap()
+                .retry_at
+                .insert(peer.to_string(), now + PEER_LINK_RETRY_SECS);
+            return Err(err.to_string());
+        }
+    };
+    let queue_size = app.config.gossip_queue_size;
+    let (out_tx, in_rx) = spawn_link_io(socket, queue_size, Message::Text, |m| match m {
+        Message::Text(text) => ControlFlow::Continue(Some(text)),
+        Message::Close(_) => ControlFlow::Break(()),
+        _ => ControlFlow::Continue(None),
+    });
+    app.peer_links.lock().unwrap().links.insert(peer.to_string(), out_tx.clone());
+    let link = run_peer_link(
+        app.clone(),
+        peer.to_string(),
+        session.node_id,
+        session.expires_at,
+        in_rx,
+        out_tx.clone(),
+    );
+    tokio::spawn(link);
+    Ok(out_tx)
+}
+
+/// Spawns the writer and reader tasks of a WebSocket link: strings sent on
+/// the returned sender go out as text frames, and text frames received come
+/// out of the returned receiver. `read` sorts incoming frames; `Break` ends
+/// the link.
+fn spawn_link_io<S, M, E>(
+    socket: S,
+    queue_size: usize,
+    text: fn(String) -> M,
+    read: fn(M) -> ControlFlow<(), Option<String>>,
+) -> (mpsc::Sender<String>, mpsc::Receiver<String>)
+where
+    S: futures_util::Stream<Item = Result<M, E>> + futures_util::Sink<M> + Send + 'static,
+    M: Send + 'static,
+    E: Send + 'static,
+{
+    let (mut sink, mut stream) = socket.split();
+    let (out_tx, mut out_rx) = mpsc::channel::<String>(queue_size);
+    let (in_tx, in_rx) = mpsc::channel::<String>(queue_size);
+    tokio::spawn(async move {
+        while let Some(frame) = out_rx.recv().await {
+            if sink.send(text(frame)).await.is_err() {
+                break;
             }
         }
-    })
+        let _ = sink.close().await;
+    });
+    tokio::spawn(async move {
+        while let Some(Ok(frame)) = stream.next().await {
+            let frame = match read(frame) {
+                ControlFlow::Continue(Some(frame)) => frame,
+                ControlFlow::Continue(None) => continue,
+                ControlFlow::Break(()) => break,
+            };
+            if in_tx.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+    (out_tx, in_rx)
 }
 
-fn build_router(app_state: AppState) -> Router {
-    let cors = if app_state.config.cors_origins.is_empty() {
-        CorsLayer::new().allow_origin(Any).allow_methods(Any)
-    } else {
-        let origins = app_state
-            .config
-            .cors_origins
-            .iter()
-            .filter_map(|o| o.parse().ok())
-            .collect::<Vec<_>>();
-        CorsLayer::new().allow_origin(origins).allow_methods(Any)
+/// Accepts a WebSocket link from a peer holding a handshake session.
+async fn p2p_ws(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let session = match verify_peer_session(&app, &headers) {
+        Ok(session) => session,
+        Err(resp) => return resp,
     };
+    if !app.config.p2p_websocket {
+        return (StatusCode::NOT_FOUND, "websocket transport disabled").into_response();
+    }
+    upgrade.on_upgrade(move |socket: WebSocket| async move {
+        let queue_size = app.config.gossip_queue_size;
+        let (out_tx, in_rx) = spawn_link_io(socket, queue_size, WsMessage::Text, |m| match m {
+            WsMessage::Text(text) => ControlFlow::Continue(Some(text)),
+            WsMessage::Close(_) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(None),
+        });
+        let key = session.node_id.clone();
+        app.peer_links.lock().unwrap().links.insert(key.clone(), out_tx.clone());
+        run_peer_link(app, key, session.node_id, session.expires_at, in_rx, out_tx).await;
+    })
+}
 
-    Router::new()
-        .route("/healthz", get(health))
-        .route("/api/health", get(health))
-        .route("/api/info", get(info))
-        .route("/api/status", get(status))
-        .route("/api/accounts/:address", get(get_account))
-        .route("/api/transactions", post(post_transaction))
-        .route("/api/blocks", get(list_blocks_paged))
-        .route("/api/blocks/:height", get(get_block))
-        .route("/api/faucet", post(faucet))
-        .route("/api/payments", post(post_payment))
-        .route("/api/payments/:id", get(get_payment))
-        .route("/api/wallets/info", get(wallets_info))
-        .route("/api/wallets/challenge", post(wallets_challenge))
-        .route("/api/wallets/verify", post(wallets_verify))
-        .route("/api/wallets/session/:id", get(wallets_session))
-        .route("/p2p/tx", post(p2p_tx))
-        .route("/p2p/block", post(p2p_block))
-        .route("/metrics", get(metrics))
-        .layer(RequestBodyLimitLayer::new(
-            app_state.config.max_body_bytes.try_into().unwrap_or(131072usize),
-        ))
-        .layer(axum::middleware::from_fn_with_state(
-            app_state.clone(),
-            rate_limit_middleware,
-        ))
-        .with_state(app_state)
-        .layer(cors)
+/// Processes frames from one link until it closes, goes silent, the peer
+/// is banned or the handshake session behind it expires at
+/// `session_expires_at`, then unregisters it.
+async fn run_peer_link(
+    app: AppState,
+    key: String,
+    node_id: String,
+    session_expires_at: u64,
+    mut incoming: mpsc::Receiver<String>,
+    outgoing: mpsc::Sender<String>,
+) {
+    let mut ping = tokio::time::interval(Duration::from_secs(PEER_LINK_PING_SECS));
+    let mut last_heard = Instant::now();
+    let ttl = session_expires_at.saturating_sub(now_unix());
+    let expiry = tokio::time::sleep(Duration::from_secs(ttl));
+    tokio::pin!(expiry);
+    // Links we dialed are keyed by URL. Only those vouch for our uptime:
+    // anyone can open a session to us.
+    let dialed = key != node_id;
+    loop {
+        tokio::select! {
+            _ = &mut expiry => break,
+            frame = incoming.recv() => {
+                let Some(text) = frame else { break };
+                last_heard = Instant::now();
+                if dialed {
+                    attest_liveness(&app);
+                }
+                handle_peer_message(&app, &node_id, &text, &outgoing).await;
+                let sessions = app.p2p_sessions.lock().unwrap();
+                if peer_banned(&sessions.penalties, &node_id, now_unix()) {
+                    break;
+                }
+            }
+            _ = ping.tick() => {
+                if last_heard.elapsed() > Duration::from_secs(3 * PEER_LINK_PING_SECS) {
+                    break;
+                }
+                if !send_frame(&outgoing, &PeerMessage::Ping { nonce: now_unix() }).await {
+                    break;
+                }
+            }
+        }
+    }
+    let mut links = app.peer_links.lock().unwrap();
+    if links.links.get(&key).is_some_and(|l| l.same_channel(&outgoing)) {
+        links.links.remove(&key);
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let config = default_config();
-    let state = load_state(&config);
-    let payments = load_payments(&config);
-    let wallet_store = load_wallet_store(&config);
-    let app_state = AppState {
-        config: config.clone(),
-        state: Arc::new(RwLock::new(state)),
-        rate_limiter: Arc::new(RateLimiter::new(
-            Duration::from_millis(config.rate_limit_window_ms),
-            config.rate_limit_max_requests,
-        )),
-        client: Client::new(),
-        payments: Arc::new(Mutex::new(payments)),
-        wallet_store: Arc::new(Mutex::new(wallet_store)),
-        peer_stats: Arc::new(Mutex::new(HashMap::new())),
+async fn handle_peer_message(
+    app: &AppState,
+    node_id: &str,
+    text: &str,
+    outgoing: &mpsc::Sender<String>,
+) {
+    let message = match serde_json::from_str::<PeerMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            eprintln!("bad frame from {}: {}", node_id, err);
+            return;
+        }
+    };
+    let result = match message {
+        PeerMessage::Tx { transaction } => accept_peer_tx(app, node_id, *transaction).map(|_| ()),
+        PeerMessage::Block { block } => accept_peer_block(app, node_id, block).map(|_| ()),
+        PeerMessage::Header { header } => {
+            if !app.state.read().unwrap().block_store.contains_key(&header.hash) {
+                let now = Instant::now();
+                let mut links = app.peer_links.lock().unwrap();
+                if links.last_sync_wake.is_none_or(|at| now - at >= HEADER_WAKE_INTERVAL) {
+                    links.last_sync_wake = Some(now);
+                    app.sync_wake.notify_one();
+                }
+            }
+            Ok(())
+        }
+        PeerMessage::CompactBlock { header, short_ids } => {
+            receive_compact_block(app, node_id, header, short_ids, outgoing).await
+        }
+        PeerMessage::GetBlockTxs { hash, indexes } => {
+            let transactions = {
+                let state = app.state.read().unwrap();
+                let block = state.block_store.get(&hash);
+                let unique: HashSet<&usize> = indexes.iter().collect();
+                if block.is_some_and(|b| indexes.len() > b.transactions.len())
+                    || unique.len() != indexes.len()
+                {
+                    Err("transaction indexes exceed the block or repeat")
+                } else {
+                    Ok(block
+                        .map(|b| {
+                            indexes.iter().filter_map(|i| b.transactions.get(*i).cloned()).collect()
+                        })
+                        .unwrap_or_default())
+                }
+            };
+            match transactions {
+                Ok(transactions) => {
+                    send_frame(outgoing, &PeerMessage::BlockTxs { hash, transactions }).await;
+                    Ok(())
+                }
+                Err(err) => Err((StatusCode::BAD_REQUEST, err.to_string())),
+            }
+        }
+        PeerMessage::BlockTxs { hash, transactions } => {
+            let key = (hash, node_id.to_string());
+            let pending = app.peer_links.lock().unwrap().pending_blocks.remove(&key);
+            match pending {
+                Some(mut pending) if pending.requested.len() == transactions.len() => {
+                    let requested = std::mem::take(&mut pending.requested);
+                    for (i, tx) in requested.into_iter().zip(transactions) {
+                        pending.transactions[i] = Some(tx);
+                    }
+                    complete_compact_block(app, node_id, pending, outgoing).await
+                }
+                Some(_) => {
+                    // The sender no longer has the block; let range sync decide.
+                    app.sync_wake.notify_one();
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+        PeerMessage::Ping { nonce } => {
+            send_frame(outgoing, &PeerMessage::Pong { nonce }).await;
+            Ok(())
+        }
+        PeerMessage::Pong { .. } => Ok(()),
     };
+    if let Err((_, err)) = result {
+        eprintln!("rejected frame from {}: {}", node_id, err);
+    }
+}
 
-    spawn_block_producer(app_state.clone());
-    spawn_peer_sync(app_state.clone());
+/// Queues `message` on a link; false once the link is gone.
+async fn send_frame(outgoing: &mpsc::Sender<String>, message: &PeerMessage) -> bool {
+    let text = serde_json::to_string(message).unwrap_or_default();
+    outgoing.send(text).await.is_ok()
+}
 
-    let router = build_router(app_state.clone());
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.http_port));
-    println!(
-        "bulennode-rs listening on http://{} (chain={}, node={}, peers={})",
-        addr,
-        config.chain_id,
-        config.node_id,
-        config.peers.join(",")
-    );
+/// Hex characters kept from each salted transaction hash (48 bits).
+const SHORT_TX_ID_LEN: usize = 12;
+/// Compact blocks that may wait for missing transactions at once.
+const MAX_PENDING_BLOCKS: usize = 64;
+/// Seconds a pending compact block waits before range sync takes over.
+const PENDING_BLOCK_TTL_SECS: u64 = 30;
+
+/// Short id of a transaction within one block. Salting with the block hash
+/// keeps a collision from affecting more than that block.
+fn short_tx_id(block_hash: &str, tx_id: &str) -> String {
+    let mut digest = format!("{:x}", Sha256::digest(format!("{}|{}", block_hash, tx_id)));
+    digest.truncate(SHORT_TX_ID_LEN);
+    digest
+}
 
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .expect("failed to bind listener");
+fn compact_block(block: &Block) -> PeerMessage {
+    PeerMessage::CompactBlock {
+        header: BlockHeader::from(block),
+        short_ids: block
+            .transactions
+            .iter()
+            .map(|tx| short_tx_id(&block.hash, &tx.id))
+            .collect(),
+    }
+}
 
-    if let Err(err) = axum::serve(
-        listener,
-        router.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
+async fn receive_compact_block(
+    app: &AppState,
+    node_id: &str,
+    header: BlockHeader,
+    short_ids: Vec<String>,
+    outgoing: &mpsc::Sender<String>,
+) -> Result<(), (StatusCode, String)> {
+    let known = app.state.read().unwrap().block_store.contains_key(&header.hash)
+        || app.peer_links.lock().unwrap().pending_blocks.keys().any(|(h, _)| *h == header.hash);
+    if known {
+        gossip_mark_seen(app, &header.hash, Some(node_id));
+        app.gossip.counters.duplicates.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+    app.gossip.counters.compact_blocks.fetch_add(1, Ordering::Relaxed);
+    let mempool: HashMap<String, Transaction> = app
+        .state
+        .read()
+        .unwrap()
+        .mempool
+        .iter()
+        .map(|tx| (short_tx_id(&header.hash, &tx.id), tx.clone()))
+        .collect();
+    let pending = PendingBlock {
+        transactions: short_ids.iter().map(|id| mempool.get(id).cloned()).collect(),
+        header,
+        requested: vec![],
+        retried: false,
+        received_at: now_unix(),
+    };
+    complete_compact_block(app, node_id, pending, outgoing).await
+}
+
+/// Accepts the block once every transaction is known. Otherwise parks it and
+/// asks the sender for the missing ones, or for all of them when mempool
+/// matches produced the wrong block hash.
+async fn complete_compact_block(
+    app: &AppState,
+    node_id: &str,
+    mut pending: PendingBlock,
+    outgoing: &mpsc::Sender<String>,
+) -> Result<(), (StatusCode, String)> {
+    let mut missing: Vec<usize> = pending
+        .transactions
+        .iter()
+        .enumerate()
+        .filter(|(_, tx)| tx.is_none())
+        .map(|(i, _)| i)
+        .collect();
+    if missing.is_empty() {
+        let header = &pending.header;
+        let block = Block {
+            index: header.index,
+            previous_hash: header.previous_hash.clone(),
+            hash: header.hash.clone(),
+            timestamp: header.timestamp.clone(),
+            producer: header.producer.clone(),
+            transactions: pending.transactions.iter().flatten().cloned().collect(),
+            state_root: header.state_root.clone(),
+            reward_claim: header.reward_claim.clone(),
+        };
+        if pending.retried || compute_block_hash(&block) == block.hash {
+            return accept_peer_block(app, node_id, block).map(|_| ());
+        }
+        missing = (0..pending.transactions.len()).collect();
+        pending.retried = true;
+    }
+    app.gossip
+        .counters
+        .compact_missing
+        .fetch_add(missing.len() as u64, Ordering::Relaxed);
+    let hash = pending.header.hash.clone();
+    pending.requested = missing.clone();
     {
-        eprintln!("server error: {}", err);
+        let mut links = app.peer_links.lock().unwrap();
+        let now = now_unix();
+        links
+            .pending_blocks
+            .retain(|_, p| p.received_at + PENDING_BLOCK_TTL_SECS > now);
+        if links.pending_blocks.len() >= MAX_PENDING_BLOCKS {
+            drop(links);
+            app.sync_wake.notify_one();
+            return Ok(());
+        }
+        links.pending_blocks.insert((hash.clone(), node_id.to_string()), pending);
     }
+    send_frame(outgoing, &PeerMessage::GetBlockTxs { hash, indexes: missing }).await;
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::time::Duration;
+fn spawn_gossip_sender(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let _ = tokio::time::timeout(Duration::from_secs(1), app.gossip.wake.notified()).await;
+            let pending: Vec<String> = {
+                let queues = app.gossip.queues.lock().unwrap();
+                let mut draining = app.gossip.draining.lock().unwrap();
+                queues
+                    .iter()
+                    .filter(|(peer, queue)| !queue.is_empty() && draining.insert((*peer).clone()))
+                    .map(|(peer, _)| peer.clone())
+                    .collect()
+            };
+            for peer in pending {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    drain_gossip_queue(&app, &peer).await;
+                    app.gossip.draining.lock().unwrap().remove(&peer);
+                });
+            }
+        }
+    })
+}
 
-    async fn start_test_server() -> (SocketAddr, JoinHandle<()>) {
-        let mut config = default_config();
-        config.http_port = 0;
-        config.data_dir = std::env::temp_dir().join(format!("bulen-rs-test-{}", Uuid::new_v4()));
-        config.block_interval_ms = 200;
-        config.enable_faucet = true;
-        config.require_signatures = false;
-        let state = load_state(&config);
-        let app_state = AppState {
-        config: config.clone(),
-        state: Arc::new(RwLock::new(state)),
-        rate_limiter: Arc::new(RateLimiter::new(
-            Duration::from_millis(config.rate_limit_window_ms),
-            config.rate_limit_max_requests,
-        )),
-        client: Client::new(),
-        payments: Arc::new(Mutex::new(load_payments(&config))),
-        wallet_store: Arc::new(Mutex::new(load_wallet_store(&config))),
-        peer_stats: Arc::new(Mutex::new(HashMap::new())),
-    };
+async fn fetch_status_from_peer(
+    app: &AppState,
+    peer: &str,
+) -> Result<(u64, Option<String>, Option<String>), String> {
+    let mut req = app
+        .client
+        .get(format!("{}/api/status", peer))
+        .header("x-bulen-protocol-version", &app.config.protocol_version)
+        .header("x-bulen-node-id", &app.config.node_id);
+    if let Some(t) = app.config.p2p_token.as_ref() {
+        req = req.header("x-bulen-p2p-token", t);
+    }
+    req = req.timeout(Duration::from_secs(5));
+    let resp = req.send().await.map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("status {}", resp.status()));
+    }
+    let json: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+    Ok((
+        json.get("height").and_then(|v| v.as_u64()).unwrap_or(0),
+        json.get("latestHash")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        json.get("nodeId")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+    ))
+}
 
-        let router = build_router(app_state.clone());
-        spawn_block_producer(app_state);
+/// A polled peer that can serve main-chain blocks up to `height`.
+struct SyncSource {
+    url: String,
+    height: u64,
+    latest_hash: Option<String>,
+    /// Node id (or URL) used when penalizing the peer.
+    key: String,
+}
 
-        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
-            .await
-            .expect("bind test listener");
-        let addr = listener.local_addr().unwrap();
-        let handle = tokio::spawn(async move {
-            let _ = axum::serve(
+/// Fetches the peer's status and updates its address-book entry.
+async fn poll_peer(app: &AppState, peer: &str) -> Option<SyncSource> {
+    let started = Instant::now();
+    let (height, latest_hash, node_id) = match fetch_status_from_peer(app, peer).await {
+        Ok(res) => res,
+        Err(_) => {
+            let mut stats = app.peer_stats.lock().unwrap();
+            record_peer_failure(&app.config, &mut stats, peer);
+            return None;
+        }
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let key = node_id.clone().unwrap_or_else(|| peer.to_string());
+    let mut stats = app.peer_stats.lock().unwrap();
+    if node_id.as_deref() == Some(app.config.node_id.as_str()) {
+        // Our own address, learned through peer exchange.
+        stats.remove(peer);
+        return None;
+    }
+    record_peer_success(&mut stats, peer, height, latest_hash.clone(), node_id, latency_ms);
+    drop(stats);
+    attest_liveness(app);
+    Some(SyncSource {
+        url: peer.to_string(),
+        height,
+        latest_hash,
+        key,
+    })
+}
+
+async fn fetch_block_range(
+    app: &AppState,
+    peer: &str,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Block>, String> {
+    #[derive(Deserialize)]
+    struct RangeResponse {
+        blocks: Vec<Block>,
+    }
+    let path = format!("/p2p/blocks?from={}&to={}", from, to);
+    let resp = peer_request(app, peer, &path, None).await?;
+    resp.json::<RangeResponse>()
+        .await
+        .map(|r| r.blocks)
+        .map_err(|_| "decode block range failed".into())
+}
+
+async fn fetch_headers(
+    app: &AppState,
+    peer: &str,
+    from: u64,
+    to: u64,
+) -> Result<Vec<BlockHeader>, String> {
+    #[derive(Deserialize)]
+    struct HeadersResponse {
+        headers: Vec<BlockHeader>,
+    }
+    let mut headers = Vec::new();
+    let mut next = from;
+    while next <= to {
+        let path = format!("/p2p/headers?from={}&to={}", next, to);
+        let resp = peer_request(app, peer, &path, None).await?;
+        let page = resp
+            .json::<HeadersResponse>()
+            .await
+            .map_err(|_| "decode headers failed".to_string())?
+            .headers;
+        if page.is_empty() {
+            break;
+        }
+        next += page.len() as u64;
+        headers.extend(page);
+    }
+    Ok(headers)
+}
+
+/// Heights are contiguous from `from` and each header links to the previous one.
+fn validate_header_chain(from: u64, headers: &[BlockHeader]) -> Result<(), String> {
+    for (i, header) in headers.iter().enumerate() {
+        if header.index != from + i as u64 {
+            return Err("non-contiguous block range".into());
+        }
+        if i > 0 && header.previous_hash != headers[i - 1].hash {
+            return Err(format!("block {} does not link to its parent", header.index));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a fetched range before it touches state: heights are contiguous
+/// from `from`, every hash recomputes and each block links to the previous one.
+fn validate_block_batch(from: u64, blocks: &[Block]) -> Result<(), String> {
+    let headers: Vec<BlockHeader> = blocks.iter().map(BlockHeader::from).collect();
+    validate_header_chain(from, &headers)?;
+    match blocks.iter().find(|b| b.hash != compute_block_hash(b)) {
+        Some(block) => Err(format!("invalid hash for block {}", block.index)),
+        None => Ok(()),
+    }
+}
+
+/// Whether the peer's chain contains our tip or, for a peer that is not
+/// ahead of us, whether its tip is on our chain.
+async fn extends_local_chain(app: &AppState, source: &SyncSource) -> Result<bool, String> {
+    let (local_height, local_hash, at_peer_height) = {
+        let state = app.state.read().unwrap();
+        let tip = state.blocks.last().expect("genesis exists");
+        (
+            tip.index,
+            tip.hash.clone(),
+            state.blocks.get(source.height as usize).map(|b| b.hash.clone()),
+        )
+    };
+    if source.height <= local_height {
+        return Ok(source
+            .latest_hash
+            .as_ref()
+            .is_none_or(|hash| at_peer_height.as_ref() == Some(hash)));
+    }
+    let headers = fetch_headers(app, &source.url, local_height, local_height).await?;
+    Ok(headers.first().is_some_and(|h| h.hash == local_hash))
+}
+
+/// Headers-first fork resolution: finds the last common block with the peer
+/// within `MAX_REORG_DEPTH`, compares the cumulative weight of both branches
+/// above it and, when the peer's is heavier, downloads and validates that
+/// branch, rolls our blocks back and applies it. Returns whether we switched.
+async fn reconcile_fork(app: &AppState, source: &SyncSource) -> Result<bool, String> {
+    let local_height = app.state.read().unwrap().blocks.last().map(|b| b.index).unwrap_or(0);
+    let top = local_height.min(source.height);
+    let window = fetch_headers(app, &source.url, top.saturating_sub(MAX_REORG_DEPTH), top).await?;
+    let from = window.first().map(|h| h.index).unwrap_or(0);
+    if let Err(err) = validate_header_chain(from, &window) {
+        penalize_peer(app, &source.key, true, &err);
+        return Err(err);
+    }
+    let ancestor = {
+        let state = app.state.read().unwrap();
+        window
+            .iter()
+            .rev()
+            .find(|h| state.blocks.get(h.index as usize).is_some_and(|b| b.hash == h.hash))
+            .cloned()
+    }
+    .ok_or("no common ancestor within reorg depth")?;
+    if ancestor.index == top && source.height <= local_height {
+        return Ok(false);
+    }
+
+    let branch_end = source.height.min(ancestor.index + 2 * MAX_REORG_DEPTH);
+    let branch = fetch_headers(app, &source.url, ancestor.index + 1, branch_end).await?;
+    if let Err(err) = validate_header_chain(ancestor.index + 1, &branch).and_then(|_| {
+        match branch.first() {
+            Some(first) if first.previous_hash == ancestor.hash => Ok(()),
+            _ => Err("branch does not start at the common ancestor".to_string()),
+        }
+    }) {
+        penalize_peer(app, &source.key, true, &err);
+        return Err(err);
+    }
+    let (ours, theirs) = {
+        let state = app.state.read().unwrap();
+        let weight = |producer: &str| block_weight(&state.accounts, producer);
+        let ours: f64 = state.blocks[ancestor.index as usize + 1..]
+            .iter()
+            .map(|b| weight(&b.producer))
+            .sum();
+        let theirs: f64 = branch.iter().map(|h| weight(&h.producer)).sum();
+        (ours, theirs)
+    };
+    if theirs <= ours {
+        return Ok(false);
+    }
+
+    let mut blocks = Vec::new();
+    while blocks.len() < branch.len() {
+        let next = ancestor.index + 1 + blocks.len() as u64;
+        let batch = fetch_block_range(app, &source.url, next, branch_end).await?;
+        if batch.is_empty() {
+            return Err("peer stopped serving its branch".into());
+        }
+        blocks.extend(batch);
+    }
+    blocks.truncate(branch.len());
+    let matches_headers = blocks.iter().zip(&branch).all(|(b, h)| b.hash == h.hash);
+    if let Err(err) = validate_block_batch(ancestor.index + 1, &blocks).and_then(|_| {
+        matches_headers
+            .then_some(())
+            .ok_or_else(|| "blocks do not match advertised headers".to_string())
+    }) {
+        penalize_peer(app, &source.key, true, &err);
+        return Err(err);
+    }
+
+    let mut state = app.state.write().unwrap();
+    if state.blocks.get(ancestor.index as usize).map(|b| &b.hash) != Some(&ancestor.hash) {
+        return Err("local chain changed during fork resolution".into());
+    }
+    let switched = switch_to_branch(&app.config, &mut state, ancestor.index, &blocks);
+    save_state(&app.config, &state);
+    drop(state);
+    let rolled_back = match switched {
+        Ok(rolled_back) => rolled_back,
+        Err(err) => {
+            eprintln!("kept our branch; the branch from {} failed: {}", source.url, err);
+            penalize_peer(app, &source.key, true, &err);
+            return Err(err);
+        }
+    };
+    eprintln!(
+        "reorg: rolled back {} blocks to {} and switched to the branch from {}",
+        rolled_back, ancestor.index, source.url
+    );
+    update_payments(app);
+    Ok(true)
+}
+
+/// Replaces our blocks above `ancestor` with `blocks`. If one of them fails
+/// to apply, the branch is undone and our own blocks are applied again, so
+/// the chain is never left part-way between the two. Returns how many of our
+/// blocks were replaced.
+fn switch_to_branch(
+    config: &Config,
+    state: &mut StateData,
+    ancestor: u64,
+    blocks: &[Block],
+) -> Result<u64, String> {
+    let ours = state.blocks[ancestor as usize + 1..].to_vec();
+    rollback_to(state, ancestor)?;
+    let mut failure = None;
+    for block in blocks {
+        if let Err(err) = apply_block(config, state, block) {
+            failure = Some(format!("block {}: {}", block.index, err));
+            break;
+        }
+        state.block_store.insert(block.hash.clone(), block.clone());
+        state.blocks.push(block.clone());
+    }
+    if let Some(err) = failure {
+        rollback_to(state, ancestor)?;
+        for block in &ours {
+            apply_block(config, state, block)?;
+            state.blocks.push(block.clone());
+        }
+        state.best_tip_hash = state.blocks.last().map(|b| b.hash.clone());
+        let tx_index = &state.tx_index;
+        state.mempool.retain(|t| !tx_index.contains_key(&t.id));
+        return Err(err);
+    }
+    let included: HashSet<&String> = blocks
+        .iter()
+        .flat_map(|b| b.transactions.iter().map(|t| &t.id))
+        .collect();
+    state.mempool.retain(|t| !included.contains(&t.id));
+    state.best_tip_hash = state.blocks.last().map(|b| b.hash.clone());
+    Ok(ours.len() as u64)
+}
+
+/// Resolves forks first (see `reconcile_fork`), then downloads missing
+/// blocks from the peers that extend our chain in `BULEN_SYNC_BATCH_BLOCKS`
+/// ranges spread round-robin over every source that has them, with up to
+/// `SYNC_PIPELINE_DEPTH` requests in flight per source. Ranges are validated
+/// and applied in height order; a short range (byte budget) ends the round
+/// and the next round resumes after it.
+async fn sync_from_sources(app: &AppState, sources: &[SyncSource]) {
+    let mut aligned: Vec<&SyncSource> = Vec::new();
+    for source in sources {
+        match extends_local_chain(app, source).await {
+            Ok(true) => aligned.push(source),
+            // After a switch the other peers are re-checked next round.
+            Ok(false) => match reconcile_fork(app, source).await {
+                Ok(true) => return,
+                Ok(false) => {}
+                Err(err) => eprintln!("fork resolution with {} failed: {}", source.url, err),
+            },
+            Err(err) => eprintln!("chain check with {} failed: {}", source.url, err),
+        }
+    }
+    let sources = aligned;
+    let target = sources.iter().map(|s| s.height).max().unwrap_or(0);
+    loop {
+        let local_height = app.state.read().unwrap().blocks.last().map(|b| b.index).unwrap_or(0);
+        if target <= local_height {
+            return;
+        }
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut start = local_height + 1;
+        let mut slot = 0usize;
+        while start <= target && tasks.len() < sources.len() * SYNC_PIPELINE_DEPTH {
+            let holders: Vec<&&SyncSource> = sources.iter().filter(|s| s.height >= start).collect();
+            let source = holders[slot % holders.len()];
+            slot += 1;
+            let end = (start + app.config.sync_batch_blocks - 1).min(source.height);
+            let (app, url, key) = (app.clone(), source.url.clone(), source.key.clone());
+            tasks.spawn(async move {
+                let result = fetch_block_range(&app, &url, start, end).await;
+                (start, end, url, key, result)
+            });
+            start = end + 1;
+        }
+        let mut ranges = BTreeMap::new();
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((start, end, url, key, result)) = joined {
+                ranges.insert(start, (end, url, key, result));
+            }
+        }
+
+        let mut next = local_height + 1;
+        for (start, (end, url, key, result)) in ranges {
+            if start != next {
+                break;
+            }
+            let blocks = match result {
+                Ok(blocks) if !blocks.is_empty() => blocks,
+                Ok(_) => break,
+                Err(err) => {
+                    eprintln!("failed to fetch blocks {}..={} from {}: {}", start, end, url, err);
+                    break;
+                }
+            };
+            if let Err(err) = validate_block_batch(start, &blocks) {
+                eprintln!("rejecting blocks {}..={} from {}: {}", start, end, url, err);
+                penalize_peer(app, &key, true, &err);
+                break;
+            }
+            let mut state = app.state.write().unwrap();
+            let mut applied = 0u64;
+            for block in &blocks {
+                if let Err(err) = apply_block(&app.config, &mut state, block) {
+                    eprintln!("failed to sync block {} from {}: {}", block.index, url, err);
+                    break;
+                }
+                state.block_store.insert(block.hash.clone(), block.clone());
+                state.blocks.push(block.clone());
+                applied += 1;
+            }
+            let included: HashSet<String> = blocks
+                .iter()
+                .flat_map(|b| b.transactions.iter().map(|t| t.id.clone()))
+                .collect();
+            state.mempool.retain(|t| !included.contains(&t.id));
+            save_state(&app.config, &state);
+            next = start + applied;
+            if applied < blocks.len() as u64 || next <= end {
+                break;
+            }
+        }
+        if next == local_height + 1 {
+            return;
+        }
+        update_payments(app);
+    }
+}
+
+/// Polls every active peer in parallel.
+async fn poll_active_peers(app: &AppState) -> Vec<SyncSource> {
+    let mut polls = tokio::task::JoinSet::new();
+    for peer in current_active_peers(app) {
+        let app = app.clone();
+        polls.spawn(async move { poll_peer(&app, &peer).await });
+    }
+    let mut sources = Vec::new();
+    while let Some(joined) = polls.join_next().await {
+        if let Ok(Some(source)) = joined {
+            sources.push(source);
+        }
+    }
+    sources
+}
+
+/// Leaf of the account tree; the prefixes keep leaves and inner nodes apart.
+fn state_leaf_hash(address: &str, account: &Account) -> String {
+    let account = serde_json::to_string(account).unwrap_or_default();
+    format!("{:x}", Sha256::digest(format!("leaf|{}|{}", address, account)))
+}
+
+fn state_node_hash(left: &str, right: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("node|{}|{}", left, right)))
+}
+
+/// Accounts sorted by address and the Merkle tree over them, leaves first.
+/// An odd node at the end of a level moves up unchanged.
+fn state_tree(accounts: &HashMap<String, Account>) -> (Vec<&String>, Vec<Vec<String>>) {
+    let mut addresses: Vec<&String> = accounts.keys().collect();
+    addresses.sort();
+    let leaves = addresses.iter().map(|a| state_leaf_hash(a, &accounts[*a])).collect();
+    let mut levels: Vec<Vec<String>> = vec![leaves];
+    while let Some(level) = levels.last().filter(|l| l.len() > 1) {
+        let next = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => state_node_hash(left, right),
+                _ => pair[0].clone(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    (addresses, levels)
+}
+
+fn state_tree_root(levels: &[Vec<String>]) -> String {
+    levels
+        .last()
+        .and_then(|level| level.first())
+        .cloned()
+        .unwrap_or_else(|| format!("{:x}", Sha256::digest("empty")))
+}
+
+/// Commitment to every account, stored in `Block::state_root`.
+fn account_state_root(accounts: &HashMap<String, Account>) -> String {
+    state_tree_root(&state_tree(accounts).1)
+}
+
+/// Merkle path for one account leaf of a block's `state_root`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StateLeafProof {
+    address: String,
+    account: Account,
+    index: usize,
+    siblings: Vec<String>,
+}
+
+/// Proof served by `/p2p/accounts/:address/proof`: the account's leaf, or for
+/// an unknown address the neighbouring leaves it would sort between.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountStateProof {
+    height: u64,
+    block_hash: String,
+    state_root: String,
+    leaf_count: usize,
+    leaves: Vec<StateLeafProof>,
+}
+
+/// Proof against the tip, or `None` when local state no longer matches the
+/// tip's `state_root` (the faucet credits outside blocks).
+fn account_state_proof(state: &StateData, address: &str) -> Option<AccountStateProof> {
+    let tip = state.blocks.last()?;
+    let (addresses, levels) = state_tree(&state.accounts);
+    let state_root = state_tree_root(&levels);
+    if tip.state_root.as_deref() != Some(state_root.as_str()) {
+        return None;
+    }
+    let indexes = match addresses.binary_search_by(|a| a.as_str().cmp(address)) {
+        Ok(i) => vec![i],
+        Err(i) => [i.checked_sub(1), (i < addresses.len()).then_some(i)]
+            .into_iter()
+            .flatten()
+            .collect(),
+    };
+    let leaves = indexes
+        .into_iter()
+        .map(|index| {
+            let mut siblings = Vec::new();
+            let mut i = index;
+            for level in &levels[..levels.len() - 1] {
+                if let Some(sibling) = level.get(i ^ 1) {
+                    siblings.push(sibling.clone());
+                }
+                i /= 2;
+            }
+            StateLeafProof {
+                address: addresses[index].clone(),
+                account: state.accounts[addresses[index]].clone(),
+                index,
+                siblings,
+            }
+        })
+        .collect();
+    Some(AccountStateProof {
+        height: tip.index,
+        block_hash: tip.hash.clone(),
+        state_root,
+        leaf_count: addresses.len(),
+        leaves,
+    })
+}
+
+/// Root recomputed from a leaf and its path, `None` if the path has the
+/// wrong shape for `leaf_count`.
+fn state_path_root(leaf: &StateLeafProof, leaf_count: usize) -> Option<String> {
+    if leaf.index >= leaf_count {
+        return None;
+    }
+    let mut hash = state_leaf_hash(&leaf.address, &leaf.account);
+    let mut siblings = leaf.siblings.iter();
+    let (mut i, mut len) = (leaf.index, leaf_count);
+    while len > 1 {
+        if i % 2 == 1 {
+            hash = state_node_hash(siblings.next()?, &hash);
+        } else if i + 1 < len {
+            hash = state_node_hash(&hash, siblings.next()?);
+        }
+        i /= 2;
+        len = len.div_ceil(2);
+    }
+    siblings.next().is_none().then_some(hash)
+}
+
+/// Checks `proof` against its `state_root`; returns the proven account, or
+/// `None` when the proof shows the address has no account.
+fn verify_account_proof(
+    address: &str,
+    proof: &AccountStateProof,
+) -> Result<Option<Account>, String> {
+    for leaf in &proof.leaves {
+        if state_path_root(leaf, proof.leaf_count).as_deref() != Some(proof.state_root.as_str()) {
+            return Err(format!("invalid state proof for leaf {}", leaf.index));
+        }
+    }
+    let count = proof.leaf_count;
+    match proof.leaves.as_slice() {
+        [leaf] if leaf.address == address => Ok(Some(leaf.account.clone())),
+        [] if count == 0 && proof.state_root == state_tree_root(&[]) => Ok(None),
+        [leaf] if leaf.index == 0 && address < leaf.address.as_str() => Ok(None),
+        [leaf] if leaf.index + 1 == count && address > leaf.address.as_str() => Ok(None),
+        [a, b] if b.index == a.index + 1
+            && a.address.as_str() < address
+            && address < b.address.as_str() =>
+        {
+            Ok(None)
+        }
+        _ => Err("state proof does not cover the address".into()),
+    }
+}
+
+async fn p2p_account_proof(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Path(address): Path<String>,
+) -> Response {
+    if let Err(resp) = verify_peer_session(&app, &headers) {
+        return resp;
+    }
+    if app.config.light_client {
+        return (StatusCode::NOT_FOUND, "light nodes hold no account state").into_response();
+    }
+    let address = canonical_address(&app.config, &address);
+    match account_state_proof(&app.state.read().unwrap(), &address) {
+        Some(proof) => Json(serde_json::json!({ "ok": true, "proof": proof })).into_response(),
+        None => {
+            let msg = "local state does not match the tip state_root";
+            (StatusCode::CONFLICT, msg).into_response()
+        }
+    }
+}
+
+async fn fetch_account_proof(
+    app: &AppState,
+    peer: &str,
+    address: &str,
+) -> Result<AccountStateProof, String> {
+    #[derive(Deserialize)]
+    struct ProofResponse {
+        proof: AccountStateProof,
+    }
+    let path = format!("/p2p/accounts/{}/proof", address);
+    peer_request(app, peer, &path, None)
+        .await?
+        .json::<ProofResponse>()
+        .await
+        .map(|r| r.proof)
+        .map_err(|_| "decode account proof failed".to_string())
+}
+
+/// Light-mode `/api/accounts/:address`: asks peers for a state proof and
+/// answers from the first one that checks against a followed header,
+/// syncing headers first when the proof is for a newer block.
+async fn light_account(app: &AppState, address: &str) -> Response {
+    if let Err(err) = validate_address(&app.config, address) {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    let mut synced = false;
+    for peer in current_active_peers(app) {
+        let proof = match fetch_account_proof(app, &peer, address).await {
+            Ok(proof) => proof,
+            Err(err) => {
+                eprintln!("account proof from {} failed: {}", peer, err);
+                continue;
+            }
+        };
+        let tip = app.light_headers.lock().unwrap().len() as u64 - 1;
+        if proof.height > tip && !synced {
+            let sources = poll_active_peers(app).await;
+            light_sync(app, &sources).await;
+            synced = true;
+        }
+        let header = app.light_headers.lock().unwrap().get(proof.height as usize).cloned();
+        // A proof off our chain may just be a fork or lag, not a lie.
+        let Some(header) = header.filter(|h| {
+            h.hash == proof.block_hash && h.state_root.as_deref() == Some(proof.state_root.as_str())
+        }) else {
+            continue;
+        };
+        match verify_account_proof(address, &proof) {
+            Ok(account) => {
+                let tip = app.light_headers.lock().unwrap().len() as u64 - 1;
+                let time = parse_rfc3339(&header.timestamp)
+                    .map(|dt| dt.unix_timestamp().max(0) as u64)
+                    .unwrap_or(0);
+                let mut body = account_body(&account.unwrap_or_default(), (proof.height + 1, time));
+                body["verified"] = serde_json::json!({
+                    "height": proof.height,
+                    "blockHash": proof.block_hash,
+                    "stateRoot": proof.state_root,
+                    "finalized": tip - proof.height >= app.config.finality_min_depth,
+                    "peer": peer,
+                });
+                return Json(body).into_response();
+            }
+            Err(err) => {
+                let key = app.peer_stats.lock().unwrap().get(&peer).and_then(|s| s.node_id.clone());
+                penalize_peer(app, key.as_deref().unwrap_or(&peer), true, &err);
+            }
+        }
+    }
+    (StatusCode::BAD_GATEWAY, "no peer returned a verifiable account proof").into_response()
+}
+
+/// One light-sync pass: fetches headers above the finalized height from every
+/// source and adopts, height by height, the header that at least
+/// `BULEN_LIGHT_QUORUM` sources serve on a chain linked to ours. Finalized
+/// headers never change and a shorter tail never replaces a longer one.
+/// Returns the new tip height.
+async fn light_sync_round(app: &AppState, sources: &[SyncSource]) -> u64 {
+    let (base, parent_hash, current_tail) = {
+        let headers = app.light_headers.lock().unwrap();
+        let tip = headers.len() as u64 - 1;
+        let base = tip.saturating_sub(app.config.finality_min_depth) + 1;
+        let base_index = base as usize;
+        (base, headers[base_index - 1].hash.clone(), headers[base_index..].to_vec())
+    };
+    let mut fetches = tokio::task::JoinSet::new();
+    for source in sources.iter().filter(|s| s.height >= base) {
+        let (app, url, key) = (app.clone(), source.url.clone(), source.key.clone());
+        let to = source.height.min(base + MAX_RANGE_HEADERS - 1);
+        fetches.spawn(async move { (key, fetch_headers(&app, &url, base, to).await) });
+    }
+    let mut candidates: Vec<Vec<BlockHeader>> = Vec::new();
+    // One vote per node, however many addresses it is reachable at.
+    let mut voters = HashSet::new();
+    while let Some(joined) = fetches.join_next().await {
+        let Ok((key, Ok(headers))) = joined else { continue };
+        if !voters.insert(key.clone()) {
+            continue;
+        }
+        if let Err(err) = validate_header_chain(base, &headers) {
+            penalize_peer(app, &key, true, &err);
+            continue;
+        }
+        if headers.first().is_some_and(|h| h.previous_hash == parent_hash) {
+            candidates.push(headers);
+        }
+    }
+
+    let mut tail: Vec<BlockHeader> = Vec::new();
+    let mut agreeing: Vec<&Vec<BlockHeader>> = candidates.iter().collect();
+    loop {
+        let offset = tail.len();
+        let mut votes: Vec<(&BlockHeader, usize)> = Vec::new();
+        for header in agreeing.iter().filter_map(|list| list.get(offset)) {
+            match votes.iter_mut().find(|(candidate, _)| *candidate == header) {
+                Some(vote) => vote.1 += 1,
+                None => votes.push((header, 1)),
+            }
+        }
+        let Some((header, count)) = votes.into_iter().max_by_key(|(_, count)| *count) else {
+            break;
+        };
+        if count < app.config.light_quorum {
+            break;
+        }
+        agreeing.retain(|list| list.get(offset) == Some(header));
+        tail.push(header.clone());
+    }
+
+    let mut headers = app.light_headers.lock().unwrap();
+    let unchanged = headers.get(base as usize - 1).map(|h| &h.hash) == Some(&parent_hash);
+    if unchanged && tail.len() >= current_tail.len() && tail != current_tail {
+        headers.truncate(base as usize);
+        headers.extend(tail);
+        save_light_headers(&app.config, &headers);
+    }
+    headers.len() as u64 - 1
+}
+
+/// Repeats `light_sync_round` while the tip advances.
+async fn light_sync(app: &AppState, sources: &[SyncSource]) {
+    let mut tip = app.light_headers.lock().unwrap().len() as u64 - 1;
+    loop {
+        let next = light_sync_round(app, sources).await;
+        if next <= tip {
+            return;
+        }
+        tip = next;
+    }
+}
+
+/// `peer_sync_interval_ms`, stretched by `BULEN_POWER_SYNC_FACTOR` while
+/// asleep on low battery or on a metered link.
+fn power_sync_interval(config: &Config, power: &PowerState) -> Duration {
+    let base = config.peer_sync_interval_ms;
+    if power.sleeping_since.is_some() || power.metered {
+        Duration::from_millis(base.saturating_mul(config.power_sync_factor))
+    } else {
+        Duration::from_millis(base)
+    }
+}
+
+fn peer_sync_interval(app: &AppState) -> Duration {
+    power_sync_interval(&app.config, &app.power.lock().unwrap())
+}
+
+fn spawn_peer_sync(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let sources = poll_active_peers(&app).await;
+            if app.config.light_client {
+                light_sync(&app, &sources).await;
+            } else {
+                sync_from_sources(&app, &sources).await;
+            }
+            for peer in current_active_peers(&app) {
+                if let Err(err) = exchange_peers(&app, &peer).await {
+                    eprintln!("peer exchange with {} failed: {}", peer, err);
+                }
+            }
+            save_address_book(&app.config, &app.peer_stats.lock().unwrap());
+            save_peer_identities(&app.config, &mut app.p2p_sessions.lock().unwrap(), now_unix());
+            let interval = peer_sync_interval(&app);
+            let _ = tokio::time::timeout(interval, app.sync_wake.notified()).await;
+        }
+    })
+}
+
+fn spawn_block_producer(app: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if app.config.light_client {
+            return;
+        }
+        loop {
+            sleep(Duration::from_millis(app.config.block_interval_ms)).await;
+            if app.power.lock().unwrap().sleeping_since.is_some() {
+                continue;
+            }
+            let claim =
+                local_reward_claim(&app, reward_uptime_seconds(&app), &app.config.device_class);
+            let maybe_block = {
+                let mut guard = app.state.write().unwrap();
+                if guard.mempool.is_empty() {
+                    None
+                } else {
+                    let txs = guard.mempool.drain(..).collect::<Vec<_>>();
+                    let previous_hash = guard
+                        .blocks
+                        .last()
+                        .map(|b| b.hash.clone())
+                        .unwrap_or_else(|| "genesis".into());
+                    let mut block = Block {
+                        index: guard.blocks.len() as u64,
+                        previous_hash,
+                        hash: String::new(),
+                        timestamp: now_iso(),
+                        producer: app.config.node_id.clone(),
+                        transactions: txs,
+                        state_root: None,
+                        reward_claim: Some(claim),
+                    };
+                    if let Err(err) = apply_block_contents(&app.config, &mut guard, &block) {
+                        eprintln!("failed to apply produced block: {}", err);
+                        None
+                    } else {
+                        block.state_root = Some(account_state_root(&guard.accounts));
+                        block.hash = compute_block_hash(&block);
+                        guard.block_store.insert(block.hash.clone(), block.clone());
+                        guard.blocks.push(block.clone());
+                        guard.produced_blocks += 1;
+                        save_state(&app.config, &guard);
+                        Some(block)
+                    }
+                }
+            };
+
+            if let Some(block) = maybe_block {
+                broadcast_block(&app, &block);
+                update_payments(&app);
+            }
+        }
+    })
+}
+
+fn build_router(app_state: AppState) -> Router {
+    let cors = if app_state.config.cors_origins.is_empty() {
+        CorsLayer::new().allow_origin(Any).allow_methods(Any)
+    } else {
+        let origins = app_state
+            .config
+            .cors_origins
+            .iter()
+            .filter_map(|o| o.parse().ok())
+            .collect::<Vec<_>>();
+        CorsLayer::new().allow_origin(origins).allow_methods(Any)
+    };
+
+    Router::new()
+        .route("/healthz", get(health))
+        .route("/api/health", get(health))
+        .route("/api/info", get(info))
+        .route("/api/status", get(status))
+        .route("/api/accounts/:address", get(get_account))
+        .route("/api/accounts/:address/transactions", get(get_account_transactions))
+        .route("/api/transactions", post(post_transaction))
+        .route("/api/transactions/:id", get(get_transaction))
+        .route("/api/blocks", get(list_blocks_paged))
+        .route("/api/blocks/:height", get(get_block))
+        .route("/api/faucet", post(faucet))
+        .route("/api/rewards/estimate", post(rewards_estimate))
+        .route("/api/payments", get(list_payments).post(post_payment))
+        .route("/api/payments/:id", get(get_payment))
+        .route("/api/payments/:id/cancel", post(cancel_payment))
+        .route("/api/payments/:id/webhooks", get(get_payment_webhooks))
+        .route("/api/payment-link", post(post_payment_link))
+        .route("/api/wallets/info", get(wallets_info))
+        .route("/api/wallets/challenge", post(wallets_challenge))
+        .route("/api/wallets/verify", post(wallets_verify))
+        .route("/api/wallets/session/:id", get(wallets_session))
+        .route("/api/multisig/proposals", post(post_multisig_proposal))
+        .route("/api/multisig/proposals/:id", get(get_multisig_proposal))
+        .route("/api/multisig/proposals/:id/signatures", post(post_multisig_signature))
+        .route("/api/multisig/proposals/:id/broadcast", post(broadcast_multisig_proposal))
+        .route("/p2p/handshake", post(p2p_handshake))
+        .route("/p2p/peers", get(p2p_peers))
+        .route("/p2p/blocks", get(p2p_blocks))
+        .route("/p2p/headers", get(p2p_headers))
+        .route("/p2p/ws", get(p2p_ws))
+        .route("/p2p/accounts/:address/proof", get(p2p_account_proof))
+        .route("/p2p/tx", post(p2p_tx))
+        .route("/p2p/block", post(p2p_block))
+        .route("/api/peers", get(list_peers))
+        .route("/api/admin/peers", get(admin_peers))
+        .route("/api/device/battery", post(device_battery))
+        .route("/api/device/energy", post(device_energy))
+        .route("/metrics", get(metrics))
+        .layer(RequestBodyLimitLayer::new(
+            app_state.config.max_body_bytes.try_into().unwrap_or(131072usize),
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit_middleware,
+        ))
+        .with_state(app_state)
+        .layer(cors)
+}
+
+#[tokio::main]
+async fn main() {
+    let config = default_config();
+    let state = load_state(&config);
+    let payments = load_payments(&config);
+    let wallet_store = load_wallet_store(&config);
+    let app_state = AppState {
+        config: config.clone(),
+        state: Arc::new(RwLock::new(state)),
+        rate_limiter: Arc::new(RateLimiter::new(
+            Duration::from_millis(config.rate_limit_window_ms),
+            config.rate_limit_max_requests,
+        )),
+        client: Client::new(),
+        webhook_client: webhook_client(&config),
+        payments: Arc::new(Mutex::new(payments)),
+        wallet_store: Arc::new(Mutex::new(wallet_store)),
+        multisig_proposals: Arc::new(Mutex::new(load_multisig_proposals(&config))),
+        peer_stats: Arc::new(Mutex::new(load_address_book(&config))),
+        identity: Arc::new(load_or_create_identity(&config)),
+        p2p_sessions: Arc::new(Mutex::new(load_p2p_sessions(&config))),
+        gossip: Arc::new(Gossip::default()),
+        peer_links: Arc::new(Mutex::new(PeerLinks::default())),
+        sync_wake: Arc::new(Notify::new()),
+        light_headers: Arc::new(Mutex::new(load_light_headers(&config))),
+        power: Arc::new(Mutex::new(load_power_state(&config))),
+        uptime: Arc::new(Mutex::new(load_uptime_ledger(&config))),
+        webhooks: Arc::new(Mutex::new(load_webhooks(&config))),
+        webhook_wake: Arc::new(Notify::new()),
+    };
+
+    spawn_block_producer(app_state.clone());
+    spawn_peer_sync(app_state.clone());
+    spawn_gossip_sender(app_state.clone());
+    spawn_uptime_tracker(app_state.clone());
+    spawn_payment_refresher(app_state.clone());
+    spawn_webhook_worker(app_state.clone());
+
+    let router = build_router(app_state.clone());
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.http_port));
+    println!(
+        "bulennode-rs listening on http://{} (chain={}, node={}, peers={})",
+        addr,
+        config.chain_id,
+        config.node_id,
+        config.peers.join(",")
+    );
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .expect("failed to bind listener");
+
+    if let Err(err) = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    {
+        eprintln!("server error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+
+    fn test_config() -> Config {
+        let mut config = default_config();
+        config.http_port = 0;
+        config.data_dir = std::env::temp_dir().join(format!("bulen-rs-test-{}", Uuid::new_v4()));
+        config.block_interval_ms = 200;
+        config.enable_faucet = true;
+        config.require_signatures = false;
+        config.allow_private_peers = true;
+        config.allow_private_webhooks = true;
+        config
+    }
+
+    fn test_app_state(config: Config) -> AppState {
+        let state = load_state(&config);
+        AppState {
+            config: config.clone(),
+            state: Arc::new(RwLock::new(state)),
+            rate_limiter: Arc::new(RateLimiter::new(
+                Duration::from_millis(config.rate_limit_window_ms),
+                config.rate_limit_max_requests,
+            )),
+            client: Client::new(),
+            webhook_client: webhook_client(&config),
+            payments: Arc::new(Mutex::new(load_payments(&config))),
+            wallet_store: Arc::new(Mutex::new(load_wallet_store(&config))),
+            multisig_proposals: Arc::new(Mutex::new(load_multisig_proposals(&config))),
+            peer_stats: Arc::new(Mutex::new(load_address_book(&config))),
+            identity: Arc::new(load_or_create_identity(&config)),
+            p2p_sessions: Arc::new(Mutex::new(load_p2p_sessions(&config))),
+            gossip: Arc::new(Gossip::default()),
+            peer_links: Arc::new(Mutex::new(PeerLinks::default())),
+            sync_wake: Arc::new(Notify::new()),
+            light_headers: Arc::new(Mutex::new(load_light_headers(&config))),
+            power: Arc::new(Mutex::new(load_power_state(&config))),
+            uptime: Arc::new(Mutex::new(load_uptime_ledger(&config))),
+            webhooks: Arc::new(Mutex::new(load_webhooks(&config))),
+            webhook_wake: Arc::new(Notify::new()),
+        }
+    }
+
+    async fn start_test_server() -> (SocketAddr, JoinHandle<()>) {
+        let (addr, handle, _) = start_test_node(test_config()).await;
+        (addr, handle)
+    }
+
+    async fn start_test_node(mut config: Config) -> (SocketAddr, JoinHandle<()>, AppState) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind test listener");
+        let addr = listener.local_addr().unwrap();
+        config.public_url = Some(format!("http://{}", addr));
+        let app_state = test_app_state(config);
+        let router = build_router(app_state.clone());
+        spawn_block_producer(app_state.clone());
+        spawn_gossip_sender(app_state.clone());
+
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(
                 listener,
                 router.into_make_service_with_connect_info::<SocketAddr>(),
             )
             .await;
-        });
-        (addr, handle)
+        });
+        (addr, handle, app_state)
+    }
+
+    /// Applies and appends a block on top of `app`'s main chain.
+    fn append_test_block(app: &AppState, producer: &str, transactions: Vec<Transaction>) {
+        let mut state = app.state.write().unwrap();
+        let mut block = Block {
+            index: state.blocks.len() as u64,
+            previous_hash: state.blocks.last().unwrap().hash.clone(),
+            hash: String::new(),
+            timestamp: now_iso(),
+            producer: producer.into(),
+            transactions,
+            state_root: None,
+            reward_claim: None,
+        };
+        apply_block_contents(&app.config, &mut state, &block).unwrap();
+        block.state_root = Some(account_state_root(&state.accounts));
+        block.hash = compute_block_hash(&block);
+        state.block_store.insert(block.hash.clone(), block.clone());
+        state.blocks.push(block);
+    }
+
+    /// Checksummed devnet address for a readable test account name.
+    fn test_address(name: &str) -> String {
+        encode_address("bulendev", &Sha256::digest(name.as_bytes())[..20])
+    }
+
+    #[tokio::test]
+    async fn health_and_block_flow() {
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        let health = client.get(format!("{}/healthz", base)).send().await.unwrap();
+        assert!(health.status().is_success());
+
+        let faucet = client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": test_address("alice"), "amount": 1000}))
+            .send()
+            .await
+            .unwrap();
+        assert!(faucet.status().is_success());
+
+        let tx = client
+            .post(format!("{}/api/transactions", base))
+            .json(&serde_json::json!({
+                "from": test_address("alice"),
+                "to": test_address("bob"),
+                "amount": 10,
+                "fee": 1
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(tx.status().is_success());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let blocks: serde_json::Value = client
+            .get(format!("{}/api/blocks", base))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let list = blocks
+            .get("blocks")
+            .and_then(|b| b.as_array())
+            .cloned()
+            .unwrap_or_default();
+        assert!(!list.is_empty());
+
+        handle.abort();
+    }
+
+    fn signed_transaction(config: &Config, key: &p256::ecdsa::SigningKey, version: u32) -> Transaction {
+        use p256::ecdsa::signature::Signer;
+        use p256::pkcs8::{EncodePublicKey, LineEnding};
+        let public_key = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        let mut tx = Transaction {
+            id: String::new(),
+            from: derive_address(config, "p256", &public_key).unwrap(),
+            to: "bob".into(),
+            amount: 10,
+            fee: 1,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: Some("order-1".into()),
+            public_key: Some(public_key),
+            signature: None,
+            key_type: None,
+            version,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: Some(5),
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        if version == LEGACY_TX_VERSION {
+            tx.chain_id = None;
+            tx.expires_at_height = None;
+        }
+        let signature: Signature = key.sign(canonical_payload(&tx).as_bytes());
+        tx.signature =
+            Some(base64::engine::general_purpose::STANDARD.encode(signature.to_der().as_bytes()));
+        tx.id = compute_transaction_id(&tx);
+        tx
+    }
+
+    #[test]
+    fn signature_covers_action_memo_chain_and_expiry() {
+        let mut config = default_config();
+        config.require_signatures = true;
+        let state = initial_state(&config);
+        let key = p256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let tx = signed_transaction(&config, &key, TX_VERSION);
+        assert!(verify_signature(&config, &tx).is_ok());
+        assert!(validate_envelope(&config, &state, &tx).is_ok());
+
+        let mut flipped = tx.clone();
+        flipped.action = "stake".into();
+        assert!(verify_signature(&config, &flipped).is_err());
+
+        let mut rewritten = tx.clone();
+        rewritten.memo = Some("order-2".into());
+        assert!(verify_signature(&config, &rewritten).is_err());
+
+        let mut other_chain = config.clone();
+        other_chain.chain_id = "bulencoin-othernet".into();
+        assert!(validate_envelope(&other_chain, &state, &tx).is_err());
+
+        let mut late = state.clone();
+        late.blocks = vec![late.blocks[0].clone(); 6];
+        assert_eq!(
+            validate_envelope(&config, &late, &tx).unwrap_err(),
+            "transaction expired"
+        );
+
+        let legacy = signed_transaction(&config, &key, LEGACY_TX_VERSION);
+        assert!(verify_signature(&config, &legacy).is_ok());
+        assert!(admit_signing_format(&config, &legacy).is_err());
+        // Blocks already carrying legacy txs still replay with the gate off.
+        assert!(validate_envelope(&config, &state, &legacy).is_ok());
+        config.allow_legacy_signatures = true;
+        assert!(admit_signing_format(&config, &legacy).is_ok());
+    }
+
+    #[tokio::test]
+    async fn p2p_routes_require_handshake_session() {
+        let mut remote_config = test_config();
+        remote_config.block_interval_ms = 60_000;
+        let (addr, handle, remote) = start_test_node(remote_config).await;
+        let peer = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        let anonymous = client
+            .post(format!("{}/p2p/block", peer))
+            .json(&serde_json::json!({"block": remote.state.read().unwrap().blocks[0]}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+        let mut config = test_config();
+        config.node_id = "node-a".into();
+        let local = test_app_state(config.clone());
+        let mut tx = Transaction {
+            id: String::new(),
+            from: test_address("alice"),
+            to: test_address("bob"),
+            amount: 1,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        let payload = serde_json::json!({ "transaction": tx });
+        send_to_peer(&local, &peer, "/p2p/tx", &payload).await.unwrap();
+        assert!(remote.state.read().unwrap().mempool.iter().any(|t| t.id == tx.id));
+        assert!(remote.p2p_sessions.lock().unwrap().known_keys.contains_key("node-a"));
+
+        // Expired sessions are renewed transparently.
+        for session in remote.p2p_sessions.lock().unwrap().inbound.values_mut() {
+            session.expires_at = 0;
+        }
+        send_to_peer(&local, &peer, "/p2p/tx", &payload).await.unwrap();
+
+        // Same node id with another identity key is refused.
+        let mut impostor_config = test_config();
+        impostor_config.node_id = "node-a".into();
+        let impostor = test_app_state(impostor_config);
+        assert!(peer_session(&impostor, &peer).await.is_err());
+
+        let mut other_chain = test_config();
+        other_chain.chain_id = "bulencoin-othernet".into();
+        assert!(peer_session(&test_app_state(other_chain), &peer).await.is_err());
+
+        // A captured handshake cannot be replayed.
+        let now = now_unix();
+        let genesis = genesis_hash(&local.state.read().unwrap());
+        let nonce = Uuid::new_v4().as_simple().to_string();
+        let body = serde_json::json!({
+            "nodeId": "node-a",
+            "chainId": config.chain_id,
+            "genesisHash": genesis,
+            "nonce": nonce,
+            "timestamp": now,
+            "publicKey": local.identity.public_key,
+            "signature": sign_with_identity(
+                &local.identity,
+                &handshake_payload("node-a", &config.chain_id, &genesis, &nonce, now),
+            ),
+        });
+        let handshake = |body: serde_json::Value| {
+            client.post(format!("{}/p2p/handshake", peer)).json(&body).send()
+        };
+        assert!(handshake(body.clone()).await.unwrap().status().is_success());
+        assert_eq!(handshake(body).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn peers_are_discovered_through_a_seed() {
+        let named = |id: &str| {
+            let mut config = test_config();
+            config.node_id = id.into();
+            config
+        };
+        let (seed_addr, seed_handle, seed) = start_test_node(named("seed")).await;
+        let seed_url = format!("http://{}", seed_addr);
+        let mut b_config = named("node-b");
+        b_config.seed_nodes = vec![seed_url.clone()];
+        let (b_addr, b_handle, b) = start_test_node(b_config).await;
+        let b_url = format!("http://{}", b_addr);
+
+        // B bootstraps from the seed and advertises its own URL in the handshake.
+        assert_eq!(current_active_peers(&b), vec![seed_url.clone()]);
+        exchange_peers(&b, &seed_url).await.unwrap();
+        assert_eq!(seed.peer_stats.lock().unwrap()[&b_url].source, "handshake");
+        // Only peers the seed has reached itself are shared onwards.
+        poll_peer(&seed, &b_url).await;
+        assert!(seed.peer_stats.lock().unwrap()[&b_url].ok);
+
+        let mut c_config = named("node-c");
+        c_config.seed_nodes = vec![seed_url.clone()];
+        let c = test_app_state(c_config.clone());
+        assert_eq!(exchange_peers(&c, &seed_url).await.unwrap(), 1);
+        assert!(current_active_peers(&c).contains(&b_url));
+
+        save_address_book(&c.config, &c.peer_stats.lock().unwrap());
+        let reloaded = load_address_book(&c_config);
+        assert_eq!(reloaded[&b_url].source, "exchange");
+        assert_eq!(reloaded[&seed_url].source, "seed");
+
+        // The active set stays bounded however many peers are known.
+        let mut book = reloaded;
+        for port in 0..20 {
+            add_known_peer(&c_config, &mut book, &format!("http://10.0.0.1:{}", 5000 + port), "exchange");
+        }
+        assert!(!add_known_peer(&c_config, &mut book, "ftp://bad", "exchange"));
+        assert_eq!(active_peers(&c_config, &book, &HashMap::new()).len(), c_config.max_active_peers);
+
+        seed_handle.abort();
+        b_handle.abort();
+    }
+
+    #[test]
+    fn ed25519_and_secp256k1_signatures() {
+        use ed25519_dalek::Signer as _;
+        let mut config = default_config();
+        config.require_signatures = true;
+        let p256_key = p256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let base = signed_transaction(&config, &p256_key, TX_VERSION);
+
+        let ed_key = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let ed_public = hex::encode(ed_key.verifying_key().to_bytes());
+        let mut ed_tx = base.clone();
+        ed_tx.key_type = Some("ed25519".into());
+        ed_tx.public_key = Some(ed_public.clone());
+        ed_tx.from = derive_address(&config, "ed25519", &ed_public).unwrap();
+        let signature = ed_key.sign(canonical_payload(&ed_tx).as_bytes());
+        ed_tx.signature = Some(base64::engine::general_purpose::STANDARD.encode(signature.to_bytes()));
+        assert!(verify_signature(&config, &ed_tx).is_ok());
+        let mut wrong_scheme = ed_tx.clone();
+        wrong_scheme.key_type = None;
+        assert!(verify_signature(&config, &wrong_scheme).is_err());
+
+        let k1_key = k256::ecdsa::SigningKey::from_bytes(&[9u8; 32].into()).unwrap();
+        let k1_public = hex::encode(k1_key.verifying_key().to_encoded_point(false).as_bytes());
+        let mut k1_tx = base.clone();
+        k1_tx.key_type = Some("secp256k1".into());
+        k1_tx.public_key = Some(k1_public.clone());
+        k1_tx.from = derive_address(&config, "secp256k1", &k1_public).unwrap();
+        let signature: k256::ecdsa::Signature = k1_key.sign(canonical_payload(&k1_tx).as_bytes());
+        k1_tx.signature = Some(base64::engine::general_purpose::STANDARD.encode(signature.to_der().as_bytes()));
+        assert!(verify_signature(&config, &k1_tx).is_ok());
+
+        let personal_sign = |message: &[u8]| {
+            let (signature, recovery_id) = k1_key
+                .sign_prehash_recoverable(&eth_message_hash(message))
+                .unwrap();
+            let mut bytes = signature.to_bytes().to_vec();
+            bytes.push(recovery_id.to_byte() + 27);
+            format!("0x{}", hex::encode(bytes))
+        };
+        k1_tx.signature = Some(personal_sign(canonical_payload(&k1_tx).as_bytes()));
+        assert!(verify_signature(&config, &k1_tx).is_ok());
+
+        // Same 32 bytes under different schemes never share an address.
+        let compressed = hex::encode(k1_key.verifying_key().to_encoded_point(true).as_bytes());
+        assert_eq!(derive_address(&config, "secp256k1", &compressed), Ok(k1_tx.from.clone()));
+        assert_ne!(k1_tx.from, ed_tx.from);
+
+        let eth = eth_address(k1_key.verifying_key());
+        let challenge = WalletChallenge {
+            id: "chal_test".into(),
+            address: eth.to_uppercase().replacen("0X", "0x", 1),
+            public_key: String::new(),
+            wallet_type: "metamask".into(),
+            key_type: "secp256k1".into(),
+            nonce: "n".into(),
+            message: "Sign this message to prove wallet ownership for BulenCoin.".into(),
+            created_at: now_iso(),
+            expires_at: now_iso(),
+            status: "pending".into(),
+        };
+        let recovered = verify_wallet_signature(&challenge, &personal_sign(challenge.message.as_bytes()));
+        assert_eq!(recovered, Ok(compressed));
+        assert!(verify_wallet_signature(&challenge, &personal_sign(b"other message")).is_err());
+    }
+
+    #[test]
+    fn addresses_are_checksummed_per_chain() {
+        let mut config = default_config();
+        let address = test_address("alice");
+        assert!(address.starts_with("bulendev1"));
+        assert!(validate_address(&config, &address).is_ok());
+
+        let last = address.chars().last().unwrap();
+        let typo = format!("{}{}", &address[..address.len() - 1], if last == 'q' { 'p' } else { 'q' });
+        assert!(validate_address(&config, &typo).is_err());
+        assert!(validate_address(&config, &address.to_uppercase()).is_err());
+        assert!(validate_address(&config, "alice").is_err());
+        let mainnet = encode_address("bulen", &Sha256::digest(b"alice")[..20]);
+        assert_eq!(
+            validate_address(&config, &mainnet).unwrap_err(),
+            "address prefix must be bulendev on this chain"
+        );
+
+        // Legacy addresses keep working for `to` and for signed `from`.
+        let legacy = format!("addr_{}", hex::encode(&Sha256::digest(b"alice")[..20]));
+        assert!(validate_address(&config, &legacy).is_ok());
+        assert!(validate_address(&config, "addr_1234").is_err());
+        config.require_signatures = true;
+        let key = p256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let tx = signed_transaction(&config, &key, TX_VERSION);
+        let hash = key_address_hash("p256", tx.public_key.as_ref().unwrap()).unwrap();
+        let mut legacy_tx = tx.clone();
+        legacy_tx.from = format!("addr_{}", hex::encode(hash));
+        assert!(address_matches(&config, &tx.from, &hash, "addr_"));
+        assert!(address_matches(&config, &legacy_tx.from, &hash, "addr_"));
+
+        config.allow_legacy_addresses = false;
+        assert!(validate_address(&config, &legacy).is_err());
+        assert!(!address_matches(&config, &legacy_tx.from, &hash, "addr_"));
+        assert!(verify_signature(&config, &tx).is_ok());
+    }
+
+    #[test]
+    fn batch_transfer_applies_atomically() {
+        let config = default_config();
+        let mut state = initial_state(&config);
+        let (alice, bob) = (test_address("alice"), test_address("bob"));
+        let carol = test_address("carol");
+        state.accounts.insert(
+            "treasury".into(),
+            Account {
+                balance: 1_000,
+                stake: 0,
+                nonce: 0,
+                reputation: 0,
+                locks: Vec::new(),
+            },
+        );
+        let output = |to: &str, amount: u64, memo: Option<&str>| TransferOutput {
+            to: to.into(),
+            amount,
+            memo: memo.map(|m| m.to_string()),
+        };
+        let mut tx = Transaction {
+            id: String::new(),
+            from: "treasury".into(),
+            to: String::new(),
+            amount: 300,
+            fee: 2,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "batch_transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+            outputs: vec![
+                output(&alice, 100, Some("payroll-alice")),
+                output(&bob, 150, None),
+                output(&carol, 50, Some("inv-7")),
+            ],
+        };
+        assert!(apply_transaction(&config, &mut state, &tx)
+            .unwrap_err()
+            .contains("fee too low"));
+        tx.amount = 299;
+        tx.fee = 3;
+        assert!(apply_transaction(&config, &mut state, &tx)
+            .unwrap_err()
+            .contains("sum of outputs"));
+        tx.amount = 300;
+        tx.outputs[2].amount = 5_000;
+        tx.amount = 5_250;
+        assert_eq!(
+            apply_transaction(&config, &mut state, &tx).unwrap_err(),
+            "insufficient balance"
+        );
+        assert!(!state.accounts.contains_key(&alice));
+
+        tx.outputs[2].amount = 50;
+        tx.amount = 300;
+        apply_transaction(&config, &mut state, &tx).unwrap();
+        assert_eq!(state.accounts["treasury"].balance, 697);
+        assert_eq!(state.accounts["treasury"].nonce, 1);
+        assert_eq!(state.accounts[&bob].balance, 150);
+        assert_eq!(state.accounts[&carol].balance, 50);
+
+        tx.id = compute_transaction_id(&tx);
+        state.mempool.push(tx.clone());
+        let payment = Payment {
+            id: "pay_1".into(),
+            to: carol.clone(),
+            amount: 50,
+            memo: Some("inv-7".into()),
+            created_at: now_iso(),
+            expires_at: now_iso(),
+            status: "pending".into(),
+            transaction_id: None,
+            block_index: None,
+            webhook_url: None,
+            merchant_order_id: None,
+            customer_reference: None,
+        };
+        assert_eq!(
+            find_matching_transaction(&config, &payment, &state),
+            Some((tx.id.clone(), None))
+        );
+    }
+
+    #[tokio::test]
+    async fn multisig_proposal_collects_signatures() {
+        use p256::ecdsa::signature::Signer;
+        use p256::pkcs8::{EncodePublicKey, LineEnding};
+
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        let keys: Vec<p256::ecdsa::SigningKey> = (1u8..=3)
+            .map(|i| p256::ecdsa::SigningKey::from_bytes(&[i; 32].into()).unwrap())
+            .collect();
+        let pems: Vec<String> = keys
+            .iter()
+            .map(|k| k.verifying_key().to_public_key_pem(LineEnding::LF).unwrap())
+            .collect();
+        let policy = MultisigPolicy {
+            threshold: 2,
+            public_keys: pems.clone(),
+        };
+        let config = default_config();
+        let treasury = derive_multisig_address(&config, &policy);
+        let mut reordered = policy.clone();
+        reordered.public_keys.reverse();
+        assert_eq!(derive_multisig_address(&config, &reordered), treasury);
+        assert!(treasury.starts_with("bulendev1"));
+
+        client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": treasury, "amount": 1000}))
+            .send()
+            .await
+            .unwrap();
+        let payload = serde_json::json!({
+            "version": TX_VERSION,
+            "chainId": config.chain_id,
+            "from": treasury,
+            "to": test_address("vendor"),
+            "amount": 100,
+            "fee": 1,
+            "nonce": 1,
+            "action": "transfer",
+            "memo": null,
+            "expiresAtHeight": null,
+        })
+        .to_string();
+        let sign = |i: usize| {
+            let signature: Signature = keys[i].sign(payload.as_bytes());
+            serde_json::json!({
+                "public_key": pems[i],
+                "signature": base64::engine::general_purpose::STANDARD
+                    .encode(signature.to_der().as_bytes()),
+            })
+        };
+        let outsider = p256::ecdsa::SigningKey::from_bytes(&[9u8; 32].into()).unwrap();
+        let outsider_sig: Signature = outsider.sign(payload.as_bytes());
+        let outsider_json = serde_json::json!({
+            "public_key": outsider.verifying_key().to_public_key_pem(LineEnding::LF).unwrap(),
+            "signature": base64::engine::general_purpose::STANDARD
+                .encode(outsider_sig.to_der().as_bytes()),
+        });
+        let proposal_body = |signature: Option<serde_json::Value>| {
+            serde_json::json!({
+                "multisig": policy,
+                "to": test_address("vendor"),
+                "amount": 100,
+                "fee": 1,
+                "signature": signature,
+            })
+        };
+        for signature in [None, Some(outsider_json.clone())] {
+            let refused = client
+                .post(format!("{}/api/multisig/proposals", base))
+                .json(&proposal_body(signature))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(refused.status(), StatusCode::BAD_REQUEST);
+        }
+        let created: serde_json::Value = client
+            .post(format!("{}/api/multisig/proposals", base))
+            .json(&proposal_body(Some(sign(0))))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let id = created["proposal"]["id"].as_str().unwrap().to_string();
+        assert_eq!(created["proposal"]["payload"], payload.as_str());
+        assert_eq!(created["proposal"]["transaction"]["from"], treasury.as_str());
+        assert_eq!(created["signaturesCollected"], 1);
+
+        let rejected = client
+            .post(format!("{}/api/multisig/proposals/{}/signatures", base, id))
+            .json(&outsider_json)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+
+        let one: serde_json::Value = client
+            .post(format!("{}/api/multisig/proposals/{}/signatures", base, id))
+            .json(&sign(0))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(one["proposal"]["status"], "collecting");
+        let early = client
+            .post(format!("{}/api/multisig/proposals/{}/broadcast", base, id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(early.status(), StatusCode::BAD_REQUEST);
+
+        let two: serde_json::Value = client
+            .post(format!("{}/api/multisig/proposals/{}/signatures", base, id))
+            .json(&sign(2))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(two["proposal"]["status"], "ready");
+        let sent = client
+            .post(format!("{}/api/multisig/proposals/{}/broadcast", base, id))
+            .send()
+            .await
+            .unwrap();
+        assert!(sent.status().is_success());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let vendor: serde_json::Value = client
+            .get(format!("{}/api/accounts/{}", base, test_address("vendor")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(vendor["balance"], 100);
+
+        handle.abort();
+    }
+
+    #[test]
+    fn vesting_limits_spendable_balance() {
+        let config = default_config();
+        let mut state = initial_state(&config);
+        let (dev, shop) = (test_address("dev"), test_address("shop"));
+        state.accounts.insert(
+            "team-pool".into(),
+            Account {
+                balance: 10_000,
+                stake: 0,
+                nonce: 0,
+                reputation: 0,
+                locks: Vec::new(),
+            },
+        );
+        let tx = |from: &str, to: &str, amount: u64, nonce: u64, lock: Option<LockSchedule>| Transaction {
+            id: format!("{}-{}", from, nonce),
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee: 0,
+            nonce,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock,
+        };
+        // 1000 vesting linearly over heights 10..20 with a cliff at 15.
+        let schedule = LockSchedule {
+            unit: "height".into(),
+            start: 10,
+            end: 20,
+            cliff: Some(15),
+        };
+        apply_transaction(&config, &mut state, &tx("team-pool", &dev, 1_000, 1, Some(schedule)))
+            .unwrap();
+        state.accounts.get_mut(&dev).unwrap().balance += 5;
+        let at_height = |state: &mut StateData, height: usize| {
+            let genesis = state.blocks[0].clone();
+            state.blocks = vec![genesis; height];
+        };
+
+        at_height(&mut state, 14);
+        assert_eq!(locked_balance(&state.accounts[&dev], chain_clock(&state)), 1_000);
+        assert_eq!(
+            apply_transaction(&config, &mut state, &tx(&dev, &shop, 6, 1, None)).unwrap_err(),
+            "insufficient balance"
+        );
+        apply_transaction(&config, &mut state, &tx(&dev, &shop, 5, 1, None)).unwrap();
+
+        at_height(&mut state, 16);
+        assert_eq!(locked_balance(&state.accounts[&dev], chain_clock(&state)), 400);
+        apply_transaction(&config, &mut state, &tx(&dev, &shop, 600, 2, None)).unwrap();
+        assert!(apply_transaction(&config, &mut state, &tx(&dev, &shop, 1, 3, None)).is_err());
+
+        at_height(&mut state, 20);
+        apply_transaction(&config, &mut state, &tx(&dev, &shop, 400, 3, None)).unwrap();
+        assert!(state.accounts[&dev].locks.is_empty());
+        assert_eq!(state.accounts[&dev].balance, 0);
+
+        let bad = LockSchedule {
+            unit: "block".into(),
+            start: 1,
+            end: 2,
+            cliff: None,
+        };
+        let bad = tx("team-pool", &shop, 1, 2, Some(bad));
+        assert!(apply_transaction(&config, &mut state, &bad).is_err());
+    }
+
+    #[tokio::test]
+    async fn duplicate_transactions_are_rejected() {
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": test_address("carol"), "amount": 1000}))
+            .send()
+            .await
+            .unwrap();
+
+        let body = serde_json::json!({
+            "from": test_address("carol"),
+            "to": test_address("dave"),
+            "amount": 10,
+            "fee": 1,
+            "nonce": 1
+        });
+        let first: serde_json::Value = client
+            .post(format!("{}/api/transactions", base))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let id = first.get("id").and_then(|v| v.as_str()).unwrap().to_string();
+        assert_eq!(id.len(), 64);
+
+        let again = client
+            .post(format!("{}/api/transactions", base))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(again.status(), StatusCode::CONFLICT);
+
+        // Still rejected once the first copy has been included in a block.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let after_block = client
+            .post(format!("{}/api/transactions", base))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(after_block.status(), StatusCode::CONFLICT);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn transaction_lookup_and_receipt() {
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": test_address("erin"), "amount": 1000}))
+            .send()
+            .await
+            .unwrap();
+        let tx: serde_json::Value = client
+            .post(format!("{}/api/transactions", base))
+            .json(&serde_json::json!({
+                "from": test_address("erin"),
+                "to": test_address("frank"),
+                "amount": 25,
+                "fee": 2
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let id = tx.get("id").and_then(|v| v.as_str()).unwrap().to_string();
+
+        let pending: serde_json::Value = client
+            .get(format!("{}/api/transactions/{}", base, id))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let status = pending.get("status").and_then(|v| v.as_str()).unwrap_or("");
+        assert!(status == "pending" || status == "confirmed");
+
+        let mut confirmed = serde_json::Value::Null;
+        for _ in 0..50 {
+            confirmed = client
+                .get(format!("{}/api/transactions/{}", base, id))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if confirmed["status"] == "confirmed" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(confirmed["status"], "confirmed");
+        assert!(confirmed["blockHeight"].as_u64().unwrap() >= 1);
+        let confirmations = confirmed["confirmations"].as_u64().unwrap();
+        assert!(confirmations >= 1);
+        let finality = if confirmations > default_config().finality_min_depth {
+            "final"
+        } else {
+            "unfinalized"
+        };
+        assert_eq!(confirmed["finality"], finality);
+        assert_eq!(confirmed["receipt"]["feePaid"], 2);
+        assert_eq!(confirmed["receipt"]["resultingNonce"], 1);
+
+        let missing = client
+            .get(format!("{}/api/transactions/{}", base, "nope"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn account_history_pages_and_filters() {
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+        let (gina, hank, ivan) = (test_address("gina"), test_address("hank"), test_address("ivan"));
+
+        client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": gina, "amount": 1000}))
+            .send()
+            .await
+            .unwrap();
+        for (nonce, body) in [
+            serde_json::json!({"from": gina, "to": hank, "amount": 5, "fee": 1}),
+            serde_json::json!({"from": gina, "to": gina, "amount": 100, "fee": 1, "action": "stake"}),
+            serde_json::json!({"from": gina, "to": ivan, "amount": 7, "fee": 1}),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut body = body;
+            body["nonce"] = serde_json::json!(nonce as u64 + 1);
+            let resp = client
+                .post(format!("{}/api/transactions", base))
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+            assert!(resp.status().is_success());
+            tokio::time::sleep(Duration::from_millis(400)).await;
+        }
+
+        let get = |query: String| {
+            let client = client.clone();
+            let url = format!("{}/api/accounts/{}/transactions{}", base, gina, query);
+            async move {
+                client
+                    .get(url)
+                    .send()
+                    .await
+                    .unwrap()
+                    .json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let first = get("?limit=2".into()).await;
+        let items = first["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["transaction"]["to"], ivan);
+        assert_eq!(items[1]["transaction"]["action"], "stake");
+        let cursor = first["nextCursor"].as_u64().unwrap();
+
+        let second = get(format!("?limit=2&cursor={}", cursor)).await;
+        let items = second["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["transaction"]["to"], hank);
+        // The faucet credit is a transaction in a block too.
+        assert_eq!(items[1]["transaction"]["action"], "faucet");
+        assert!(second["nextCursor"].is_null());
+
+        let stakes = get("?action=stake".into()).await;
+        assert_eq!(stakes["items"].as_array().unwrap().len(), 1);
+
+        let incoming = client
+            .get(format!("{}/api/accounts/{}/transactions?direction=in", base, ivan))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(incoming["items"].as_array().unwrap().len(), 1);
+        assert_eq!(incoming["items"][0]["direction"], "in");
+
+        let status: serde_json::Value = client
+            .get(format!("{}/api/status", base))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let producer = status["nodeId"].as_str().unwrap();
+        let rewards = client
+            .get(format!(
+                "{}/api/accounts/{}/transactions?action=reward",
+                base, producer
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert!(!rewards["items"].as_array().unwrap().is_empty());
+        assert_eq!(rewards["items"][0]["type"], "reward");
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn stake_and_unstake_and_payments() {
+        let (addr, handle) = start_test_server().await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+
+        // Fund alice
+        client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({"address": test_address("alice"), "amount": 5000}))
+            .send()
+            .await
+            .unwrap();
+
+        // Stake
+        let stake = client
+            .post(format!("{}/api/transactions", base))
+            .json(&serde_json::json!({
+                "from": test_address("alice"),
+                "to": test_address("alice"),
+                "amount": 1000,
+                "fee": 1,
+                "action": "stake",
+                "nonce": 1
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(stake.status().is_success());
+
+        tokio::time::sleep(Duration::from_millis(800)).await;
+
+        let account: serde_json::Value = client
+            .get(format!("{}/api/accounts/{}", base, test_address("alice")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let stake_value = account.get("stake").and_then(|v| v.as_i64()).unwrap_or(0);
+        assert!(stake_value >= 1000, "stake not applied, got {}", stake_value);
+        let next_nonce = account
+            .get("nonce")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1)
+            + 1;
+
+        // Unstake
+        let unstake = client
+            .post(format!("{}/api/transactions", base))
+            .json(&serde_json::json!({
+                "from": test_address("alice"),
+                "to": test_address("alice"),
+                "amount": 500,
+                "fee": 1,
+                "action": "unstake",
+                "nonce": next_nonce
+            }))
+            .send()
+            .await
+            .unwrap();
+        if !unstake.status().is_success() {
+            let status = unstake.status();
+            let body = unstake.text().await.unwrap_or_default();
+            panic!("unstake failed: {} body={}", status, body);
+        }
+
+        // Payment creation
+        let pay = client
+            .post(format!("{}/api/payments", base))
+            .json(&serde_json::json!({
+                "to": test_address("bob"),
+                "amount": 50,
+                "memo": "test"
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let payment_id = pay.get("id").and_then(|v| v.as_str()).unwrap().to_string();
+
+        // Pay it
+        client
+            .post(format!("{}/api/transactions", base))
+            .json(&serde_json::json!({
+                "from": test_address("alice"),
+                "to": test_address("bob"),
+                "amount": 60,
+                "fee": 1,
+                "memo": "test"
+            }))
+            .send()
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+
+        let payment_status = client
+            .get(format!("{}/api/payments/{}", base, payment_id))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let status = payment_status.get("status").and_then(|v| v.as_str()).unwrap_or("");
+        assert!(status == "paid" || status == "pending_block" || status == "pending");
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn misbehaving_peers_back_off_and_get_banned() {
+        let mut remote_config = test_config();
+        remote_config.block_interval_ms = 60_000;
+        let (addr, handle, remote) = start_test_node(remote_config).await;
+        let peer = format!("http://{}", addr);
+
+        // Unreachable peers are skipped with a growing backoff.
+        let mut config = test_config();
+        config.node_id = "node-bad".into();
+        config.peers = vec!["http://127.0.0.1:9".into()];
+        let local = test_app_state(config.clone());
+        assert!(poll_peer(&local, "http://127.0.0.1:9").await.is_none());
+        let first = local.peer_stats.lock().unwrap()["http://127.0.0.1:9"].next_attempt_at;
+        assert!(first > now_unix());
+        assert!(current_active_peers(&local).is_empty());
+        record_peer_failure(&config, &mut local.peer_stats.lock().unwrap(), "http://127.0.0.1:9");
+        {
+            let book = local.peer_stats.lock().unwrap();
+            assert!(book["http://127.0.0.1:9"].next_attempt_at > first);
+            assert!(peer_score(&book["http://127.0.0.1:9"], None) < 100);
+        }
+
+        // Two blocks with a forged hash cross the ban threshold.
+        let mut block = remote.state.read().unwrap().blocks[0].clone();
+        block.index = 1;
+        block.hash = "forged".into();
+        let payload = serde_json::json!({ "block": block });
+        for _ in 0..2 {
+            assert!(send_to_peer(&local, &peer, "/p2p/block", &payload).await.is_err());
+        }
+        let err = peer_request(&local, &peer, "/p2p/peers", None).await.unwrap_err();
+        assert!(err.contains("403"));
+        local.p2p_sessions.lock().unwrap().outbound.clear();
+        assert!(peer_session(&local, &peer).await.is_err());
+
+        let admin: serde_json::Value = reqwest::get(format!("{}/api/admin/peers", peer))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let ban = &admin["penalties"][0];
+        assert_eq!(ban["peer"], "node-bad");
+        assert_eq!(ban["invalidBlocks"], 2);
+        assert_eq!(ban["banned"], true);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn peers_are_listed_with_metrics() {
+        let mut config = test_config();
+        config.max_active_peers = 1;
+        let (addr, handle, app) = start_test_node(config).await;
+        let base = format!("http://{}", addr);
+        {
+            let mut book = app.peer_stats.lock().unwrap();
+            let (hash, node_id) = (Some("abc".into()), Some("node-x".into()));
+            record_peer_success(&mut book, "http://peer-a:4100", 5, hash, node_id, 40.0);
+            record_peer_failure(&app.config, &mut book, "http://peer-b:4100");
+        }
+
+        let body: serde_json::Value = reqwest::get(format!("{}/api/peers", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let peers = body["peers"].as_array().unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0]["nodeId"], "node-x");
+        assert_eq!(peers[0]["latestHash"], "abc");
+        assert_eq!(peers[1]["failures"], 1);
+        assert!(peers[0]["score"].as_i64() > peers[1]["score"].as_i64());
+
+        // Known from exchange only, outside the active set: no series.
+        {
+            let mut book = app.peer_stats.lock().unwrap();
+            assert!(add_known_peer(&app.config, &mut book, "http://peer-c:4100", "exchange"));
+        }
+        let metrics = reqwest::get(format!("{}/metrics", base))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(metrics.contains("peer=\"http://peer-a:4100\",peer_node_id=\"node-x\"} 1"));
+        assert!(metrics.contains("bulen_peer_height_lag{") && metrics.contains("} -5\n"));
+        assert!(metrics.contains("bulen_peer_up{") && metrics.contains("peer=\"http://peer-b:4100\",peer_node_id=\"\"} 0"));
+        assert!(!metrics.contains("peer-c"));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn range_sync_pulls_batches_from_several_peers() {
+        let mut a_config = test_config();
+        a_config.block_interval_ms = 60_000;
+        a_config.p2p_range_max_bytes = 2_000;
+        let (a_addr, a_handle, a) = start_test_node(a_config).await;
+        for _ in 0..30 {
+            append_test_block(&a, "node-a", vec![]);
+        }
+        let mut b_config = test_config();
+        b_config.block_interval_ms = 60_000;
+        let (b_addr, b_handle, b) = start_test_node(b_config).await;
+        *b.state.write().unwrap() = a.state.read().unwrap().clone();
+
+        let mut config = test_config();
+        config.sync_batch_blocks = 5;
+        let local = test_app_state(config);
+        let a_url = format!("http://{}", a_addr);
+        let b_url = format!("http://{}", b_addr);
+
+        // The byte budget cuts the range short and flags that more remain.
+        let resp = peer_request(&local, &a_url, "/p2p/blocks?from=1&to=30", None).await.unwrap();
+        let body: serde_json::Value = resp.json().await.unwrap();
+        let returned = body["blocks"].as_array().unwrap().len();
+        assert!(returned > 0 && returned < 30);
+        assert_eq!(body["more"], true);
+
+        let sources = vec![
+            poll_peer(&local, &a_url).await.unwrap(),
+            poll_peer(&local, &b_url).await.unwrap(),
+        ];
+        sync_from_sources(&local, &sources).await;
+        let synced = local.state.read().unwrap();
+        assert_eq!(synced.blocks.len(), 31);
+        assert_eq!(synced.blocks.last().unwrap().hash, a.state.read().unwrap().blocks[30].hash);
+
+        let mut forged = synced.blocks[1..4].to_vec();
+        forged[1].hash = "forged".into();
+        assert!(validate_block_batch(1, &forged).is_err());
+        assert!(validate_block_batch(2, &synced.blocks[1..4]).is_err());
+        assert!(validate_block_batch(1, &synced.blocks[1..4]).is_ok());
+
+        a_handle.abort();
+        b_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn stale_fork_reorgs_onto_heavier_peer_branch() {
+        let mut a_config = test_config();
+        a_config.block_interval_ms = 60_000;
+        let (a_addr, a_handle, a) = start_test_node(a_config).await;
+        let (alice, bob) = (test_address("alice"), test_address("bob"));
+        a.state.write().unwrap().accounts.insert(
+            alice.clone(),
+            Account { balance: 100, stake: 0, nonce: 0, reputation: 0, locks: Vec::new() },
+        );
+        for _ in 0..3 {
+            append_test_block(&a, "node-a", vec![]);
+        }
+        let local = test_app_state(test_config());
+        *local.state.write().unwrap() = a.state.read().unwrap().clone();
+
+        // Local forks off at height 3 with a transfer; the peer builds a longer branch.
+        let mut tx = Transaction {
+            id: String::new(),
+            from: alice.clone(),
+            to: bob.clone(),
+            amount: 10,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(local.config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        append_test_block(&local, "local", vec![tx.clone()]);
+        append_test_block(&local, "local", vec![]);
+        for _ in 0..4 {
+            append_test_block(&a, "node-a", vec![]);
+        }
+        assert_eq!(local.state.read().unwrap().accounts[&bob].balance, 10);
+
+        let peer = format!("http://{}", a_addr);
+        let source = poll_peer(&local, &peer).await.unwrap();
+        assert!(!extends_local_chain(&local, &source).await.unwrap());
+        sync_from_sources(&local, &[source]).await;
+
+        let state = local.state.read().unwrap();
+        let remote = a.state.read().unwrap();
+        assert_eq!(state.blocks.len(), 8);
+        assert_eq!(state.blocks.last().unwrap().hash, remote.blocks.last().unwrap().hash);
+        assert_eq!(state.accounts[&alice].balance, 100);
+        assert!(!state.accounts.contains_key(&bob));
+        assert!(!state.accounts.contains_key("local"));
+        assert!(!state.tx_index.contains_key(&tx.id));
+        assert!(state.mempool.iter().any(|t| t.id == tx.id));
+
+        // Rollbacks cannot reach below the recorded undo history.
+        let mut shallow = state.clone();
+        shallow.undo_log.clear();
+        assert!(rollback_to(&mut shallow, 3).is_err());
+        assert_eq!(shallow.blocks.len(), 8);
+
+        a_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn gossip_relays_once_and_bounds_queues() {
+        let mut c_config = test_config();
+        c_config.block_interval_ms = 60_000;
+        let (c_addr, c_handle, c) = start_test_node(c_config).await;
+        let mut b_config = test_config();
+        b_config.block_interval_ms = 60_000;
+        b_config.peers = vec![format!("http://{}", c_addr)];
+        let (b_addr, b_handle, b) = start_test_node(b_config).await;
+        let b_url = format!("http://{}", b_addr);
+
+        let mut config = test_config();
+        config.peers = vec!["http://127.0.0.1:9".into()];
+        config.gossip_queue_size = 2;
+        let local = test_app_state(config);
+        let mut tx = Transaction {
+            id: String::new(),
+            from: test_address("alice"),
+            to: test_address("bob"),
+            amount: 1,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(local.config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        let payload = serde_json::json!({ "transaction": tx });
+        send_to_peer(&local, &b_url, "/p2p/tx", &payload).await.unwrap();
+        send_to_peer(&local, &b_url, "/p2p/tx", &payload).await.unwrap();
+
+        // B relays the new transaction to C exactly once.
+        for _ in 0..50 {
+            if b.gossip.counters.sent.load(Ordering::Relaxed) > 0 {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(c.state.read().unwrap().mempool.iter().any(|t| t.id == tx.id));
+        assert_eq!(b.gossip.counters.duplicates.load(Ordering::Relaxed), 1);
+        assert_eq!(b.gossip.counters.relayed.load(Ordering::Relaxed), 1);
+        assert_eq!(b.gossip.counters.sent.load(Ordering::Relaxed), 1);
+        // C does not send it back to the node it came from.
+        let message = PeerMessage::Tx {
+            transaction: Box::new(tx.clone()),
+        };
+        assert_eq!(gossip_publish(&c, &tx.id, message), 0);
+
+        // Without a sender running, the queue keeps only the newest messages.
+        for id in ["m1", "m2", "m3"] {
+            assert_eq!(gossip_publish(&local, id, PeerMessage::Ping { nonce: 0 }), 1);
+        }
+        let queued: Vec<String> = local.gossip.queues.lock().unwrap()["http://127.0.0.1:9"]
+            .iter()
+            .map(|item| item.id.clone())
+            .collect();
+        assert_eq!(queued, vec!["m2", "m3"]);
+        assert_eq!(local.gossip.counters.dropped.load(Ordering::Relaxed), 1);
+
+        b_handle.abort();
+        c_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn peers_exchange_messages_over_websocket_links() {
+        let mut c_config = test_config();
+        c_config.block_interval_ms = 60_000;
+        let (c_addr, c_handle, c) = start_test_node(c_config).await;
+        let c_url = format!("http://{}", c_addr);
+        let mut b_config = test_config();
+        b_config.block_interval_ms = 60_000;
+        b_config.peers = vec![c_url.clone()];
+        let (b_addr, b_handle, b) = start_test_node(b_config).await;
+        let b_url = format!("http://{}", b_addr);
+        let transfer = |nonce: u64| {
+            let mut tx = Transaction {
+                id: String::new(),
+                from: test_address("alice"),
+                to: test_address("bob"),
+                amount: 1,
+                fee: 0,
+                nonce,
+                timestamp: now_iso(),
+                action: "transfer".into(),
+                memo: None,
+                public_key: None,
+                signature: None,
+                key_type: None,
+                version: TX_VERSION,
+                chain_id: Some(b.config.chain_id.clone()),
+                expires_at_height: None,
+                outputs: vec![],
+                multisig: None,
+                signatures: vec![],
+                lock: None,
+            };
+            tx.id = compute_transaction_id(&tx);
+            tx
+        };
+        let in_mempool = |app: &AppState, id: &str| {
+            app.state.read().unwrap().mempool.iter().any(|t| t.id == id)
+        };
+
+        // B dials C and pushes over the link it keeps under C's URL.
+        let tx1 = transfer(1);
+        broadcast_transaction(&b, &tx1);
+        for _ in 0..50 {
+            if in_mempool(&c, &tx1.id) {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(in_mempool(&c, &tx1.id));
+        assert!(b.peer_links.lock().unwrap().links.contains_key(&c_url));
+
+        // C answers on the link B opened instead of dialing back.
+        let tx2 = transfer(2);
+        broadcast_transaction(&c, &tx2);
+        for _ in 0..50 {
+            if in_mempool(&b, &tx2.id) {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(in_mempool(&b, &tx2.id));
+        {
+            let links = c.peer_links.lock().unwrap();
+            assert!(links.links.contains_key(&b.config.node_id));
+            assert!(!links.links.contains_key(&b_url));
+        }
+
+        // A peer that does not offer WebSockets is reached over HTTP.
+        let mut d_config = test_config();
+        d_config.block_interval_ms = 60_000;
+        d_config.p2p_websocket = false;
+        let (d_addr, d_handle, d) = start_test_node(d_config).await;
+        let d_url = format!("http://{}", d_addr);
+        let tx3 = transfer(3);
+        let message = PeerMessage::Tx {
+            transaction: Box::new(tx3.clone()),
+        };
+        deliver_to_peer(&b, &d_url, &message).await.unwrap();
+        assert!(in_mempool(&d, &tx3.id));
+        assert!(!b.peer_links.lock().unwrap().links.contains_key(&d_url));
+
+        b_handle.abort();
+        c_handle.abort();
+        d_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn compact_blocks_fetch_only_missing_transactions() {
+        let mut c_config = test_config();
+        c_config.block_interval_ms = 60_000;
+        c_config.peer_sync_interval_ms = 60_000;
+        let (c_addr, c_handle, c) = start_test_node(c_config).await;
+        let c_url = format!("http://{}", c_addr);
+        let mut b_config = test_config();
+        b_config.block_interval_ms = 60_000;
+        let (_, b_handle, b) = start_test_node(b_config).await;
+        let transfer = |nonce: u64| {
+            let mut tx = Transaction {
+                id: String::new(),
+                from: test_address("alice"),
+                to: test_address("bob"),
+                amount: 1,
+                fee: 0,
+                nonce,
+                timestamp: now_iso(),
+                action: "transfer".into(),
+                memo: None,
+                public_key: None,
+                signature: None,
+                key_type: None,
+                version: TX_VERSION,
+                chain_id: Some(b.config.chain_id.clone()),
+                expires_at_height: None,
+                outputs: vec![],
+                multisig: None,
+                signatures: vec![],
+                lock: None,
+            };
+            tx.id = compute_transaction_id(&tx);
+            tx
+        };
+
+        // C already holds the first transaction; only B knows the second.
+        let (known, unknown) = (transfer(1), transfer(2));
+        c.state.write().unwrap().mempool.push(known.clone());
+        append_test_block(&b, "node-b", vec![known.clone(), unknown.clone()]);
+        let block = b.state.read().unwrap().blocks.last().unwrap().clone();
+        let message = PeerMessage::Block { block: block.clone() };
+        deliver_to_peer(&b, &c_url, &message).await.unwrap();
+
+        for _ in 0..50 {
+            if c.state.read().unwrap().blocks.last().unwrap().hash == block.hash {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        let tip = c.state.read().unwrap().blocks.last().unwrap().clone();
+        assert_eq!(tip.hash, block.hash);
+        assert_eq!(tip.transactions.len(), 2);
+        assert_eq!(c.gossip.counters.compact_blocks.load(Ordering::Relaxed), 1);
+        assert_eq!(c.gossip.counters.compact_missing.load(Ordering::Relaxed), 1);
+        assert!(c.peer_links.lock().unwrap().pending_blocks.is_empty());
+        assert!(c.state.read().unwrap().mempool.is_empty());
+
+        b_handle.abort();
+        c_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn light_node_follows_headers_and_verifies_account_proofs() {
+        let mut full_config = test_config();
+        full_config.block_interval_ms = 60_000;
+        let (full_addr, full_handle, full) = start_test_node(full_config).await;
+        let full_url = format!("http://{}", full_addr);
+        let alice = test_address("alice");
+        for name in ["alice", "carol", "erin"] {
+            let account = Account {
+                balance: 500,
+                ..Account::default()
+            };
+            full.state.write().unwrap().accounts.insert(test_address(name), account);
+        }
+        append_test_block(&full, "node-full", vec![]);
+        append_test_block(&full, "node-full", vec![]);
+
+        let mut light_config = test_config();
+        light_config.light_client = true;
+        light_config.light_quorum = 1;
+        light_config.peers = vec![full_url.clone()];
+        let (light_addr, light_handle, light) = start_test_node(light_config).await;
+        let base = format!("http://{}", light_addr);
+        let client = reqwest::Client::new();
+
+        let sources = poll_active_peers(&light).await;
+        light_sync(&light, &sources).await;
+        let tip = full.state.read().unwrap().blocks.last().unwrap().hash.clone();
+        assert_eq!(light.light_headers.lock().unwrap().last().unwrap().hash, tip);
+        assert_eq!(light.state.read().unwrap().blocks.len(), 1);
+
+        // A block the light node has not seen yet is synced on demand.
+        append_test_block(&full, "node-full", vec![]);
+        let account: serde_json::Value = client
+            .get(format!("{}/api/accounts/{}", base, alice))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(account["balance"], 500);
+        assert_eq!(account["verified"]["height"], 3);
+        assert_eq!(account["verified"]["finalized"], false);
+        let status: serde_json::Value = client
+            .get(format!("{}/api/status", base))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["mode"], "light");
+        assert_eq!(status["height"], 3);
+
+        // Absent accounts are proven absent from their neighbours.
+        let proof = account_state_proof(&full.state.read().unwrap(), &test_address("bob")).unwrap();
+        assert!(!proof.leaves.is_empty());
+        assert!(verify_account_proof(&test_address("bob"), &proof).unwrap().is_none());
+        let mut forged = account_state_proof(&full.state.read().unwrap(), &alice).unwrap();
+        forged.leaves[0].account.balance = 10_000;
+        assert!(verify_account_proof(&alice, &forged).is_err());
+
+        // State changed outside a block cannot be proven.
+        full.state.write().unwrap().accounts.get_mut(&alice).unwrap().balance = 1;
+        let resp = client
+            .get(format!("{}/api/accounts/{}", base, alice))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+        full_handle.abort();
+        light_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn low_battery_pauses_production_and_uptime() {
+        let mut config = test_config();
+        config.device_token = Some("device-secret".into());
+        let (addr, handle, app) = start_test_node(config).await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+        let report = |body: serde_json::Value| {
+            client
+                .post(format!("{}/api/device/battery", base))
+                .header("x-bulen-device-token", "device-secret")
+                .json(&body)
+                .send()
+        };
+
+        let resp = client
+            .post(format!("{}/api/device/battery", base))
+            .json(&serde_json::json!({ "level": 0.1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = report(serde_json::json!({ "level": 1.5 })).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value =
+            report(serde_json::json!({ "level": 0.1 })).await.unwrap().json().await.unwrap();
+        assert_eq!(body["sleeping"], true);
+        let mut tx = Transaction {
+            id: String::new(),
+            from: test_address("alice"),
+            to: test_address("bob"),
+            amount: 1,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(app.config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        app.state.write().unwrap().mempool.push(tx);
+        sleep(Duration::from_millis(app.config.block_interval_ms * 3)).await;
+        assert_eq!(app.state.read().unwrap().blocks.len(), 1);
+
+        // Plugged in on a metered link: producing again, syncing less often.
+        let body: serde_json::Value = report(serde_json::json!({
+            "level": 0.1,
+            "charging": true,
+            "metered": true,
+        }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert_eq!(body["sleeping"], false);
+        assert_eq!(
+            body["power"]["syncIntervalMs"],
+            app.config.peer_sync_interval_ms * app.config.power_sync_factor
+        );
+        for _ in 0..20 {
+            if app.state.read().unwrap().blocks.len() > 1 {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(app.state.read().unwrap().blocks.len(), 2);
+
+        let energy: serde_json::Value = client
+            .post(format!("{}/api/device/energy", base))
+            .header("x-bulen-device-token", "device-secret")
+            .json(&serde_json::json!({ "powerWatts": 4.0 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(energy["efficiencyBoost"], 1.2);
+
+        let status: serde_json::Value = client
+            .get(format!("{}/api/status", base))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["metrics"]["efficiencyBoost"], 1.2);
+        assert_eq!(status["power"]["metered"], true);
+
+        handle.abort();
+    }
+
+    #[test]
+    fn uptime_counts_only_attested_online_intervals() {
+        let mut config = test_config();
+        config.peers = vec!["http://127.0.0.1:9".into()];
+        let app = test_app_state(config);
+        let attest = |at: u64| app.uptime.lock().unwrap().last_attested_at = at;
+        let day = 86_400;
+
+        // No peer has vouched for us yet.
+        let t0 = 1_000_000;
+        record_heartbeat(&app, t0);
+        assert_eq!(reward_uptime_seconds(&app), 0);
+
+        attest(t0);
+        for i in 0..3 {
+            record_heartbeat(&app, t0 + i * UPTIME_HEARTBEAT_SECS);
+        }
+        assert_eq!(reward_uptime_seconds(&app), 120);
+
+        // A month offline is not credited, even though the node first
+        // started a month ago.
+        let t1 = t0 + 30 * day;
+        attest(t1);
+        record_heartbeat(&app, t1);
+        record_heartbeat(&app, t1 + UPTIME_HEARTBEAT_SECS);
+        assert_eq!(reward_uptime_seconds(&app), 180);
+        assert_eq!(compute_loyalty_boost(&app.config, reward_uptime_seconds(&app)), 1.0);
+
+        // Stale attestations and sleep on low battery earn nothing.
+        record_heartbeat(&app, t1 + 5 * UPTIME_HEARTBEAT_SECS);
+        attest(t1 + 6 * UPTIME_HEARTBEAT_SECS);
+        app.power.lock().unwrap().sleeping_since = Some(t1);
+        record_heartbeat(&app, t1 + 6 * UPTIME_HEARTBEAT_SECS);
+        assert_eq!(reward_uptime_seconds(&app), 180);
+
+        // The ledger survives restarts.
+        let ledger = load_uptime_ledger(&app.config);
+        assert_eq!(ledger.intervals.len(), 2);
+        assert_eq!(online_seconds(&ledger), 180);
+    }
+
+    #[tokio::test]
+    async fn rewards_estimate_projects_stake_uptime_and_device_class() {
+        let mut config = test_config();
+        config.device_class = "server".into();
+        config.device_protection_boosts =
+            HashMap::from([("server".to_string(), 1.0), ("phone".to_string(), 1.2)]);
+        let (addr, handle, _app) = start_test_node(config).await;
+        let client = reqwest::Client::new();
+        let estimate = |body: serde_json::Value| {
+            let client = client.clone();
+            async move {
+                let resp: serde_json::Value = client
+                    .post(format!("http://{}/api/rewards/estimate", addr))
+                    .json(&body)
+                    .send()
+                    .await
+                    .unwrap()
+                    .json()
+                    .await
+                    .unwrap();
+                assert_eq!(resp["ok"], true);
+                resp["projection"].clone()
+            }
+        };
+
+        let p = estimate(serde_json::json!({
+            "stake": 1000, "uptimeHoursPerDay": 12, "days": 7, "deviceClass": "phone",
+        }))
+        .await;
+        let hourly = p["hourly"].as_f64().unwrap();
+        assert!((p["stakeWeight"].as_f64().unwrap() - 1.1).abs() < 1e-9);
+        assert_eq!(p["deviceClass"], "phone");
+        assert_eq!(p["deviceBoost"], 1.2);
+        assert!((p["daily"].as_f64().unwrap() - hourly * 12.0).abs() < 1e-9);
+        assert!((p["weekly"].as_f64().unwrap() - hourly * 84.0).abs() < 1e-9);
+
+        // Out-of-range inputs are clamped and the node's class is the default.
+        let p = estimate(serde_json::json!({ "stake": 1e9, "uptimeHoursPerDay": 30, "days": 100 }))
+            .await;
+        assert_eq!(p["stakeWeight"], 3.0);
+        assert_eq!(p["uptimeHoursPerDay"], 24.0);
+        assert_eq!(p["days"], 30.0);
+        assert_eq!(p["deviceClass"], "server");
+        let daily = p["daily"].as_f64().unwrap();
+        assert!((p["periodTotal"].as_f64().unwrap() - daily * 30.0).abs() < 1e-6);
+        assert!((p["weekly"].as_f64().unwrap() - daily * 7.0).abs() < 1e-6);
+
+        handle.abort();
     }
 
     #[tokio::test]
-    async fn health_and_block_flow() {
-        let (addr, handle) = start_test_server().await;
-        let base = format!("http://{}", addr);
+    async fn payment_link_renders_uri_and_qr() {
+        let (addr, handle, _app) = start_test_node(test_config()).await;
         let client = reqwest::Client::new();
+        let url = format!("http://{}/api/payment-link", addr);
+        let merchant = test_address("merchant");
+
+        for body in [
+            serde_json::json!({ "amount": 5 }),
+            serde_json::json!({ "address": "not-an-address", "amount": 5 }),
+            serde_json::json!({ "address": merchant, "amount": 0 }),
+            serde_json::json!({
+                "address": merchant, "amount": 5, "label": "x".repeat(MAX_MEMO_CHARS + 1)
+            }),
+            serde_json::json!({
+                "address": merchant, "amount": 5, "memo": "é".repeat(MAX_MEMO_CHARS + 1)
+            }),
+        ] {
+            let resp = client.post(&url).json(&body).send().await.unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        }
 
-        let health = client.get(format!("{}/healthz", base)).send().await.unwrap();
-        assert!(health.status().is_success());
-
-        let faucet = client
-            .post(format!("{}/api/faucet", base))
-            .json(&serde_json::json!({"address": "alice", "amount": 1000}))
-            .send()
-            .await
-            .unwrap();
-        assert!(faucet.status().is_success());
-
-        let tx = client
-            .post(format!("{}/api/transactions", base))
+        let resp: serde_json::Value = client
+            .post(&url)
             .json(&serde_json::json!({
-                "from": "alice",
-                "to": "bob",
-                "amount": 10,
-                "fee": 1
+                "address": merchant,
+                "amount": 42,
+                "memo": "order 7/A",
+                "label": "x".repeat(64),
+                "expiresInSeconds": 600,
             }))
             .send()
             .await
+            .unwrap()
+            .json()
+            .await
             .unwrap();
-        assert!(tx.status().is_success());
-
-        tokio::time::sleep(Duration::from_millis(500)).await;
-
-        let blocks: serde_json::Value = client
-            .get(format!("{}/api/blocks", base))
+        assert_eq!(resp["ok"], true);
+        let link = resp["link"].as_str().unwrap();
+        let prefix = format!(
+            "bulen:{}?amount=42&memo=order%207%2FA&label={}&expires=",
+            merchant,
+            "x".repeat(64)
+        );
+        assert!(link.starts_with(&prefix), "{}", link);
+        let expires: u64 = link[prefix.len()..].parse().unwrap();
+        assert!(expires >= now_unix() + 590);
+
+        let qr = resp["qrDataUrl"].as_str().unwrap();
+        let svg = base64::engine::general_purpose::STANDARD
+            .decode(qr.strip_prefix("data:image/svg+xml;base64,").unwrap())
+            .unwrap();
+        assert!(String::from_utf8(svg).unwrap().contains("<svg"));
+
+        // A memo at the limit is linked whole, as transactions keep it.
+        let memo = "é".repeat(MAX_MEMO_CHARS);
+        assert_eq!(normalize_memo(&Some(memo.clone())), Some(memo.clone()));
+        let resp: serde_json::Value = client
+            .post(&url)
+            .json(&serde_json::json!({ "address": merchant, "amount": 1, "memo": memo }))
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
-        let list = blocks
-            .get("blocks")
-            .and_then(|b| b.as_array())
-            .cloned()
-            .unwrap_or_default();
-        assert!(list.len() >= 1);
+        assert_eq!(
+            resp["link"],
+            format!("bulen:{}?amount=1&memo={}", merchant, "%C3%A9".repeat(MAX_MEMO_CHARS))
+        );
 
         handle.abort();
     }
 
     #[tokio::test]
-    async fn stake_and_unstake_and_payments() {
-        let (addr, handle) = start_test_server().await;
+    async fn payment_webhooks_are_signed_and_retried() {
+        let received: Arc<Mutex<Vec<(HeaderMap, String)>>> = Arc::default();
+        let receiver = Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    let mut received = received.lock().unwrap();
+                    received.push((headers, body));
+                    if received.len() == 1 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver_handle =
+            tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let mut config = test_config();
+        config.webhook_secret = Some("whsec".into());
+        config.webhook_retry_base_ms = 50;
+        let (addr, handle, app) = start_test_node(config.clone()).await;
+        let worker = spawn_webhook_worker(app.clone());
         let base = format!("http://{}", addr);
         let client = reqwest::Client::new();
+        let merchant = test_address("merchant");
 
-        // Fund alice
-        client
-            .post(format!("{}/api/faucet", base))
-            .json(&serde_json::json!({"address": "alice", "amount": 5000}))
+        let rejected = client
+            .post(format!("{}/api/payments", base))
+            .json(&serde_json::json!({
+                "to": merchant, "amount": 25, "webhookUrl": "ftp://example.com/hook",
+            }))
             .send()
             .await
             .unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::BAD_REQUEST);
 
-        // Stake
-        let stake = client
-            .post(format!("{}/api/transactions", base))
+        let payment: serde_json::Value = client
+            .post(format!("{}/api/payments", base))
             .json(&serde_json::json!({
-                "from": "alice",
-                "to": "alice",
-                "amount": 1000,
-                "fee": 1,
-                "action": "stake",
-                "nonce": 1
+                "to": merchant, "amount": 25, "memo": "order-9", "webhookUrl": hook_url,
             }))
             .send()
             .await
+            .unwrap()
+            .json()
+            .await
             .unwrap();
-        assert!(stake.status().is_success());
+        let payment_id = payment["id"].as_str().unwrap().to_string();
+        assert_eq!(payment["webhook_url"], hook_url);
+
+        let payer = test_address("payer");
+        app.state.write().unwrap().accounts.insert(
+            payer.clone(),
+            Account { balance: 100, ..Account::default() },
+        );
+        let mut tx = Transaction {
+            id: String::new(),
+            from: payer.clone(),
+            to: merchant.clone(),
+            amount: 25,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: Some("order-9".into()),
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        tx.id = compute_transaction_id(&tx);
+        append_test_block(&app, &payer, vec![tx]);
+        update_payments(&app);
+
+        let webhooks_url = format!("{}/api/payments/{}/webhooks", base, payment_id);
+        let mut deliveries = serde_json::Value::Null;
+        for _ in 0..50 {
+            let resp: serde_json::Value =
+                client.get(&webhooks_url).send().await.unwrap().json().await.unwrap();
+            deliveries = resp["deliveries"].clone();
+            if deliveries[0]["state"] == "delivered" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(deliveries.as_array().unwrap().len(), 1);
+        assert_eq!(deliveries[0]["state"], "delivered");
+        assert_eq!(deliveries[0]["attempts"][0]["statusCode"], 500);
+        assert_eq!(deliveries[0]["attempts"][1]["statusCode"], 200);
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        let timestamp: u64 = headers["x-bulen-timestamp"].to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            headers["x-bulen-signature"].to_str().unwrap(),
+            webhook_signature("whsec", timestamp, body)
+        );
+        let event: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(event["event"], "payment.updated");
+        assert_eq!(event["payment"]["status"], "paid");
+        assert_eq!(load_webhooks(&config)[0].state, "delivered");
+
+        let missing = client.get(format!("{}/api/payments/pay_x/webhooks", base)).send().await;
+        assert_eq!(missing.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+
+        worker.abort();
+        handle.abort();
+        receiver_handle.abort();
+    }
 
-        tokio::time::sleep(Duration::from_millis(800)).await;
+    #[tokio::test]
+    async fn payments_are_listed_searched_and_cancelled() {
+        let mut config = test_config();
+        config.merchant_token = Some("shop-secret".into());
+        config.admin_token = Some("admin-secret".into());
+        let (addr, handle, app) = start_test_node(config.clone()).await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+        let shop = test_address("shop");
+        let cafe = test_address("cafe");
+
+        let mut ids = vec![];
+        for (i, to) in [&shop, &shop, &cafe, &shop, &cafe].into_iter().enumerate() {
+            let payment: serde_json::Value = client
+                .post(format!("{}/api/payments", base))
+                .json(&serde_json::json!({
+                    "to": to,
+                    "amount": 10 + i,
+                    "merchantOrderId": format!("order-{}", i),
+                    "customerReference": if i % 2 == 0 { "cust-even" } else { "cust-odd" },
+                }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            ids.push(payment["id"].as_str().unwrap().to_string());
+        }
+        let list = |query: String| {
+            let client = client.clone();
+            let url = format!("{}/api/payments?{}", base, query);
+            async move {
+                client
+                    .get(url)
+                    .header("x-bulen-merchant-token", "shop-secret")
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+        let page_ids = |body: &serde_json::Value| -> Vec<String> {
+            body["payments"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["id"].as_str().unwrap().to_string())
+                .collect()
+        };
 
-        let account: serde_json::Value = client
-            .get(format!("{}/api/accounts/{}", base, "alice"))
+        // Cursor pagination visits every payment once.
+        let mut seen = vec![];
+        let mut cursor = String::new();
+        loop {
+            let body: serde_json::Value =
+                list(format!("limit=2&cursor={}", cursor)).await.json().await.unwrap();
+            let page = page_ids(&body);
+            assert!(page.len() <= 2);
+            seen.extend(page);
+            match body["nextCursor"].as_str() {
+                Some(next) => cursor = urlencoding::encode(next).into_owned(),
+                None => break,
+            }
+        }
+        seen.sort();
+        let mut all = ids.clone();
+        all.sort();
+        assert_eq!(seen, all);
+
+        let body: serde_json::Value = list(format!("to={}", cafe)).await.json().await.unwrap();
+        assert_eq!(page_ids(&body).len(), 2);
+        let body: serde_json::Value =
+            list("merchantOrderId=order-3".into()).await.json().await.unwrap();
+        assert_eq!(page_ids(&body), vec![ids[3].clone()]);
+        let body: serde_json::Value =
+            list(format!("customerReference=cust-even&to={}", shop)).await.json().await.unwrap();
+        assert_eq!(page_ids(&body), vec![ids[0].clone()]);
+        let body: serde_json::Value =
+            list("createdFrom=2999-01-01T00:00:00Z".into()).await.json().await.unwrap();
+        assert!(page_ids(&body).is_empty());
+        for query in ["cursor=bogus", "createdTo=yesterday"] {
+            assert_eq!(list(query.into()).await.status(), reqwest::StatusCode::BAD_REQUEST);
+        }
+
+        // Listing and cancelling need the merchant or admin token.
+        let resp = client.get(format!("{}/api/payments", base)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let resp = client
+            .post(format!("{}/api/payments/{}/cancel", base, ids[0]))
+            .header("x-bulen-merchant-token", "wrong")
             .send()
             .await
-            .unwrap()
-            .json()
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let resp = client
+            .get(format!("{}/api/payments", base))
+            .header("x-bulen-admin-token", "admin-secret")
+            .send()
             .await
             .unwrap();
-        let stake_value = account.get("stake").and_then(|v| v.as_i64()).unwrap_or(0);
-        assert!(stake_value >= 1000, "stake not applied, got {}", stake_value);
-        let next_nonce = account
-            .get("nonce")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(1)
-            + 1;
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        let cancel = |id: String| {
+            let client = client.clone();
+            let url = format!("{}/api/payments/{}/cancel", base, id);
+            async move {
+                client
+                    .post(url)
+                    .header("x-bulen-merchant-token", "shop-secret")
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+        let cancelled: serde_json::Value = cancel(ids[1].clone()).await.json().await.unwrap();
+        assert_eq!(cancelled["status"], "cancelled");
+        assert_eq!(cancel(ids[1].clone()).await.status(), reqwest::StatusCode::CONFLICT);
+        assert_eq!(cancel("pay_x".into()).await.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = list("status=cancelled".into()).await.json().await.unwrap();
+        assert_eq!(page_ids(&body), vec![ids[1].clone()]);
+        let body: serde_json::Value = list("status=pending".into()).await.json().await.unwrap();
+        assert_eq!(page_ids(&body).len(), 4);
+
+        let reloaded = load_payments(&config);
+        assert_eq!(reloaded.len(), 5);
+        assert_eq!(reloaded.count_status("cancelled"), 1);
+        assert_eq!(reloaded.get(&ids[3]).unwrap().merchant_order_id.as_deref(), Some("order-3"));
+
+        // Reads do not refresh statuses; the refresher and blocks do.
+        {
+            let mut payments = app.payments.lock().unwrap();
+            let mut payment = payments.get(&ids[3]).unwrap().clone();
+            payment.expires_at = "2000-01-01T00:00:00Z".into();
+            payments.insert(payment);
+        }
+        let get = |id: String| {
+            let client = client.clone();
+            let url = format!("{}/api/payments/{}", base, id);
+            async move {
+                let body: serde_json::Value =
+                    client.get(url).send().await.unwrap().json().await.unwrap();
+                body["status"].clone()
+            }
+        };
+        assert_eq!(get(ids[3].clone()).await, "pending");
+        update_payments(&app);
+        assert_eq!(get(ids[3].clone()).await, "expired");
 
-        // Unstake
-        let unstake = client
-            .post(format!("{}/api/transactions", base))
-            .json(&serde_json::json!({
-                "from": "alice",
-                "to": "alice",
-                "amount": 500,
-                "fee": 1,
-                "action": "unstake",
-                "nonce": next_nonce
-            }))
+        handle.abort();
+    }
+
+    #[test]
+    fn reloaded_indexes_match_block_application() {
+        let config = test_config();
+        let app = test_app_state(config.clone());
+        let ghost = test_address("ghost");
+        let mut unfunded = Transaction {
+            id: String::new(),
+            from: ghost.clone(),
+            to: test_address("shop"),
+            amount: 5,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        unfunded.id = compute_transaction_id(&unfunded);
+        let unfunded_id = unfunded.id.clone();
+        append_test_block(&app, "producer", vec![unfunded]);
+        let mut state = app.state.read().unwrap().clone();
+        assert!(!state.tx_index.contains_key(&unfunded_id));
+        let rewards = state.address_index["producer"].clone();
+        assert_eq!(rewards.len(), 1);
+
+        // A state file from before the indexes existed.
+        state.tx_index.clear();
+        state.address_index.clear();
+        save_state(&config, &state);
+        let reloaded = load_state(&config);
+        assert!(!reloaded.tx_index.contains_key(&unfunded_id));
+        assert!(!reloaded.address_index.contains_key(&ghost));
+        assert_eq!(
+            serde_json::to_value(&reloaded.address_index["producer"]).unwrap(),
+            serde_json::to_value(&rewards).unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy_and_checksummed_addresses_share_one_account() {
+        let config = default_config();
+        let hash = &Sha256::digest(b"erin")[..20];
+        let legacy = format!("addr_{}", hex::encode(hash));
+        let erin = test_address("erin");
+        assert_eq!(canonical_address(&config, &legacy), erin);
+
+        let mut state = initial_state(&config);
+        state.accounts.insert(
+            erin.clone(),
+            Account {
+                balance: 100,
+                ..Default::default()
+            },
+        );
+        let tx = Transaction {
+            id: "legacy-1".into(),
+            from: legacy.clone(),
+            to: test_address("frank"),
+            amount: 40,
+            fee: 0,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(config.chain_id.clone()),
+            expires_at_height: None,
+            outputs: vec![],
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+        };
+        apply_transaction(&config, &mut state, &tx).unwrap();
+        assert!(!state.accounts.contains_key(&legacy));
+        assert_eq!(state.accounts[&erin].balance, 60);
+        assert_eq!(state.accounts[&erin].nonce, 1);
+
+        let bad = Transaction {
+            id: "bad-1".into(),
+            nonce: 2,
+            to: "frank".into(),
+            ..tx
+        };
+        assert!(apply_transaction(&config, &mut state, &bad).is_err());
+
+        state.accounts.insert(
+            legacy.clone(),
+            Account {
+                balance: 25,
+                nonce: 3,
+                ..Default::default()
+            },
+        );
+        migrate_legacy_accounts(&config, &mut state);
+        assert!(!state.accounts.contains_key(&legacy));
+        assert_eq!(state.accounts[&erin].balance, 85);
+        assert_eq!(state.accounts[&erin].nonce, 3);
+    }
+
+    #[test]
+    fn pinned_peer_keys_are_capped_and_expire() {
+        let config = test_config();
+        let mut sessions = P2pSessions::default();
+        let now = 1_000_000_000;
+        check_peer_identity(&config, &mut sessions, "old", "key-old", now).unwrap();
+        assert!(!sessions.identities_dirty);
+        check_peer_identity(&config, &mut sessions, "new", "key-new", now + 1).unwrap();
+        // Written at most once per interval; the rest waits for the next flush.
+        assert!(sessions.identities_dirty);
+        save_peer_identities(&config, &mut sessions, now + PEER_IDENTITY_SAVE_SECS);
+        assert!(!sessions.identities_dirty);
+        assert_eq!(load_p2p_sessions(&config).known_keys.len(), 2);
+
+        for i in 0..MAX_KNOWN_PEER_KEYS {
+            let id = format!("peer-{}", i);
+            check_peer_identity(&config, &mut sessions, &id, "k", now + 2).unwrap();
+        }
+        assert_eq!(sessions.known_keys.len(), MAX_KNOWN_PEER_KEYS);
+        assert!(!sessions.known_keys.contains_key("old"));
+        assert!(!sessions.known_keys.contains_key("new"));
+
+        let later = now + 2 + PEER_KEY_TTL_SECS;
+        check_peer_identity(&config, &mut sessions, "fresh", "key-fresh", later).unwrap();
+        assert_eq!(sessions.known_keys.len(), 1);
+        // A pinned key still binds its node id.
+        check_peer_identity(&config, &mut sessions, "fresh", "other", later).unwrap_err();
+    }
+
+    #[test]
+    fn private_peer_hosts_need_an_opt_in() {
+        for url in [
+            "http://127.0.0.1:4100",
+            "http://localhost:4100",
+            "http://10.1.2.3",
+            "http://192.168.0.7:80",
+            "http://169.254.169.254",
+            "http://100.64.0.1",
+            "http://[::1]:4100",
+            "http://[fd00::1]",
+            "http://[fe80::1]",
+            "http://[::ffff:127.0.0.1]",
+            "http://node.internal",
+            "http://intranet",
+        ] {
+            assert!(!is_public_host(url), "{}", url);
+        }
+        assert!(is_public_host("https://peer.example.org:4100"));
+        assert!(is_public_host("http://203.0.114.9"));
+        assert!(is_public_host("http://[2001:4860::8888]"));
+
+        let mut config = test_config();
+        config.allow_private_peers = false;
+        let mut book = HashMap::new();
+        assert!(!add_known_peer(&config, &mut book, "http://10.0.0.1:4100", "exchange"));
+        assert!(add_known_peer(&config, &mut book, "https://peer.example.org", "exchange"));
+        config.allow_private_peers = true;
+        assert!(add_known_peer(&config, &mut book, "http://10.0.0.1:4100", "exchange"));
+    }
+
+    #[test]
+    fn peer_penalties_follow_the_peer_address() {
+        let mut config = test_config();
+        config.require_signatures = true;
+        let app = test_app_state(config.clone());
+        app.peer_stats.lock().unwrap().insert(
+            "https://peer.example.org".into(),
+            PeerStat {
+                node_id: Some("node-x".into()),
+                ..new_peer_stat("exchange")
+            },
+        );
+        for (token, ip) in [("psess_1", "203.0.113.7"), ("psess_2", "127.0.0.1")] {
+            app.p2p_sessions.lock().unwrap().inbound.insert(
+                token.into(),
+                PeerSession {
+                    node_id: "node-x".into(),
+                    expires_at: u64::MAX,
+                    remote_ip: ip.into(),
+                },
+            );
+        }
+
+        // A stale nonce is a state race, not a bad signature.
+        let key = p256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let mut tx = signed_transaction(&config, &key, TX_VERSION);
+        app.state.write().unwrap().accounts.insert(
+            canonical_address(&config, &tx.from),
+            Account {
+                nonce: 3,
+                ..Default::default()
+            },
+        );
+        assert!(verify_signature(&config, &tx).is_ok());
+        accept_peer_tx(&app, "node-x", tx.clone()).unwrap();
+        assert!(app.p2p_sessions.lock().unwrap().penalties.is_empty());
+        tx.memo = Some("order-2".into());
+        tx.id = compute_transaction_id(&tx);
+        assert!(accept_peer_tx(&app, "node-x", tx).is_err());
+        assert_eq!(app.p2p_sessions.lock().unwrap().penalties["node-x"].invalid_txs, 1);
+
+        for _ in 0..2 {
+            penalize_peer(&app, "node-x", true, "invalid block hash");
+        }
+        let sessions = app.p2p_sessions.lock().unwrap();
+        let now = now_unix();
+        for key in ["node-x", "203.0.113.7", "https://peer.example.org"] {
+            assert!(peer_banned(&sessions.penalties, key, now), "{}", key);
+        }
+        assert!(!sessions.penalties.contains_key("127.0.0.1"));
+        assert!(sessions.inbound.values().all(|s| s.remote_ip == "127.0.0.1"));
+    }
+
+    #[test]
+    fn failed_branch_switch_restores_our_blocks() {
+        let app = test_app_state(test_config());
+        let (alice, bob) = (test_address("alice"), test_address("bob"));
+        app.state.write().unwrap().accounts.insert(
+            alice.clone(),
+            Account {
+                balance: 100,
+                ..Default::default()
+            },
+        );
+        let transfer = |amount: u64| {
+            let mut tx = Transaction {
+                id: String::new(),
+                from: alice.clone(),
+                to: bob.clone(),
+                amount,
+                fee: 1,
+                nonce: 1,
+                timestamp: now_iso(),
+                action: "transfer".into(),
+                memo: None,
+                public_key: None,
+                signature: None,
+                key_type: None,
+                version: TX_VERSION,
+                chain_id: Some(app.config.chain_id.clone()),
+                expires_at_height: None,
+                outputs: vec![],
+                multisig: None,
+                signatures: vec![],
+                lock: None,
+            };
+            tx.id = compute_transaction_id(&tx);
+            tx
+        };
+        append_test_block(&app, "producer", vec![]);
+        append_test_block(&app, "producer", vec![transfer(10)]);
+        append_test_block(&app, "producer", vec![]);
+        let (tip, accounts) = {
+            let state = app.state.read().unwrap();
+            (state.blocks.last().unwrap().hash.clone(), state.accounts.clone())
+        };
+
+        // The second block of the branch does not link to the first.
+        let mut fork = app.state.read().unwrap().blocks[2].clone();
+        fork.producer = "other".into();
+        fork.transactions = vec![transfer(20)];
+        fork.state_root = None;
+        fork.state_root = {
+            let mut next = app.state.read().unwrap().clone();
+            rollback_to(&mut next, 1).unwrap();
+            apply_block_contents(&app.config, &mut next, &fork).unwrap();
+            Some(account_state_root(&next.accounts))
+        };
+        fork.hash = compute_block_hash(&fork);
+        let mut broken = fork.clone();
+        broken.index = 3;
+        broken.previous_hash = "elsewhere".into();
+        broken.hash = compute_block_hash(&broken);
+
+        let mut state = app.state.write().unwrap();
+        let err = switch_to_branch(&app.config, &mut state, 1, &[fork, broken]).unwrap_err();
+        assert!(err.starts_with("block 3"));
+        assert_eq!(state.blocks.last().unwrap().hash, tip);
+        assert_eq!(state.best_tip_hash.as_ref(), Some(&tip));
+        assert_eq!(state.accounts[&bob].balance, accounts[&bob].balance);
+        assert_eq!(state.accounts["producer"].balance, accounts["producer"].balance);
+        assert!(!state.accounts.contains_key("other"));
+        assert!(state.mempool.iter().all(|t| !state.tx_index.contains_key(&t.id)));
+    }
+
+    #[test]
+    fn rejected_gossip_does_not_shadow_a_valid_copy() {
+        let mut config = test_config();
+        config.require_signatures = true;
+        let app = test_app_state(config.clone());
+        let key = p256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let tx = signed_transaction(&config, &key, TX_VERSION);
+        let mut forged = tx.clone();
+        forged.signature = signed_transaction(&config, &key, LEGACY_TX_VERSION).signature;
+        assert_eq!(forged.id, tx.id);
+
+        assert!(accept_peer_tx(&app, "node-bad", forged).is_err());
+        let body = accept_peer_tx(&app, "node-good", tx.clone()).unwrap();
+        assert_eq!(body, serde_json::json!({ "ok": true }));
+        let body = accept_peer_tx(&app, "node-other", tx.clone()).unwrap();
+        assert_eq!(body["ignored"], true);
+        let seen = app.gossip.seen.lock().unwrap();
+        let peers = &seen.entries[&tx.id].peers;
+        assert!(peers.contains("node-good") && peers.contains("node-other"));
+        assert!(!peers.contains("node-bad"));
+        drop(seen);
+
+        for i in 0..GOSSIP_SEEN_CAPACITY + 10 {
+            gossip_mark_seen(&app, &format!("msg-{}", i), None);
+        }
+        let seen = app.gossip.seen.lock().unwrap();
+        assert_eq!(seen.entries.len(), GOSSIP_SEEN_CAPACITY);
+        assert!(seen.expiry.len() <= GOSSIP_SEEN_CAPACITY);
+        assert!(!seen.entries.contains_key(&tx.id));
+        assert!(seen.entries.contains_key(&format!("msg-{}", GOSSIP_SEEN_CAPACITY + 9)));
+    }
+
+    #[tokio::test]
+    async fn links_close_with_their_session_and_header_wakes_are_debounced() {
+        let app = test_app_state(test_config());
+        let (out_tx, _out_rx) = mpsc::channel::<String>(8);
+        let (_in_tx, in_rx) = mpsc::channel::<String>(8);
+        app.peer_links.lock().unwrap().links.insert("node-x".into(), out_tx.clone());
+        // The session behind the link has just expired.
+        let (key, expires_at) = ("node-x".to_string(), now_unix());
+        let link = run_peer_link(app.clone(), key.clone(), key, expires_at, in_rx, out_tx);
+        tokio::time::timeout(Duration::from_secs(2), link).await.unwrap();
+        assert!(app.peer_links.lock().unwrap().links.is_empty());
+
+        let mut header = BlockHeader::from(&app.state.read().unwrap().blocks[0]);
+        header.hash = "unknown".into();
+        let frame = serde_json::to_string(&PeerMessage::Header { header }).unwrap();
+        let (outgoing, _) = mpsc::channel::<String>(1);
+        handle_peer_message(&app, "node-x", &frame, &outgoing).await;
+        let first = app.peer_links.lock().unwrap().last_sync_wake.unwrap();
+        handle_peer_message(&app, "node-x", &frame, &outgoing).await;
+        assert_eq!(app.peer_links.lock().unwrap().last_sync_wake, Some(first));
+    }
+
+    #[tokio::test]
+    async fn block_tx_requests_and_answers_are_checked() {
+        let app = test_app_state(test_config());
+        let tx = |nonce: u64| {
+            let mut tx = Transaction {
+                id: String::new(),
+                from: test_address("alice"),
+                to: test_address("bob"),
+                amount: 1,
+                fee: 0,
+                nonce,
+                timestamp: now_iso(),
+                action: "transfer".into(),
+                memo: None,
+                public_key: None,
+                signature: None,
+                key_type: None,
+                version: TX_VERSION,
+                chain_id: Some(app.config.chain_id.clone()),
+                expires_at_height: None,
+                outputs: vec![],
+                multisig: None,
+                signatures: vec![],
+                lock: None,
+            };
+            tx.id = compute_transaction_id(&tx);
+            tx
+        };
+        let mut block = app.state.read().unwrap().blocks[0].clone();
+        block.index = 1;
+        block.transactions = vec![tx(1), tx(2)];
+        block.hash = compute_block_hash(&block);
+        app.state.write().unwrap().block_store.insert(block.hash.clone(), block.clone());
+
+        let (outgoing, mut replies) = mpsc::channel::<String>(4);
+        for indexes in [vec![0, 0], vec![0, 1, 1], vec![0, 1, 2]] {
+            let request = PeerMessage::GetBlockTxs { hash: block.hash.clone(), indexes };
+            let frame = serde_json::to_string(&request).unwrap();
+            handle_peer_message(&app, "node-x", &frame, &outgoing).await;
+        }
+        assert!(replies.try_recv().is_err());
+        let request = PeerMessage::GetBlockTxs { hash: block.hash.clone(), indexes: vec![1] };
+        handle_peer_message(&app, "node-x", &serde_json::to_string(&request).unwrap(), &outgoing)
+            .await;
+        let reply: PeerMessage = serde_json::from_str(&replies.try_recv().unwrap()).unwrap();
+        let PeerMessage::BlockTxs { transactions, .. } = reply else {
+            panic!("expected BlockTxs");
+        };
+        assert_eq!(transactions.len(), 1);
+
+        // Only the peer that was asked can complete a parked compact block.
+        let pending = PendingBlock {
+            header: BlockHeader::from(&block),
+            transactions: vec![None, None],
+            requested: vec![0, 1],
+            retried: false,
+            received_at: now_unix(),
+        };
+        let key = (block.hash.clone(), "node-x".to_string());
+        app.peer_links.lock().unwrap().pending_blocks.insert(key.clone(), pending);
+        let answer = PeerMessage::BlockTxs {
+            hash: block.hash.clone(),
+            transactions: block.transactions.clone(),
+        };
+        let frame = serde_json::to_string(&answer).unwrap();
+        handle_peer_message(&app, "node-y", &frame, &outgoing).await;
+        assert!(app.peer_links.lock().unwrap().pending_blocks.contains_key(&key));
+        handle_peer_message(&app, "node-x", &frame, &outgoing).await;
+        assert!(app.peer_links.lock().unwrap().pending_blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_rewards_use_the_reported_power_and_sleep_survives_restarts() {
+        let mut config = test_config();
+        config.device_token = Some("device-secret".into());
+        let (addr, handle, app) = start_test_node(config.clone()).await;
+        let client = reqwest::Client::new();
+        let resp = client
+            .post(format!("http://{}/api/device/energy", addr))
+            .header("x-bulen-device-token", "device-secret")
+            .json(&serde_json::json!({ "powerWatts": 4.0, "batteryLevel": 0.1 }))
             .send()
             .await
             .unwrap();
-        if !unstake.status().is_success() {
-            let status = unstake.status();
-            let body = unstake.text().await.unwrap_or_default();
-            panic!("unstake failed: {} body={}", status, body);
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Block rewards carry the same efficiency boost as the reports.
+        let hourly = uptime_reward(&app, 0, &config.device_class).hourly;
+        assert_eq!(hourly, config.base_uptime_reward_per_hour * config.reward_weight * 1.2);
+        let mut block = app.state.read().unwrap().blocks[0].clone();
+        block.reward_claim = Some(local_reward_claim(&app, 0, &config.device_class));
+        assert_eq!(
+            block_reward(&config, &app.state.read().unwrap(), &block),
+            hourly_reward_per_block(&config, hourly)
+        );
+        handle.abort();
+
+        let restarted = test_app_state(config);
+        let power = restarted.power.lock().unwrap().clone();
+        assert!(power.sleeping_since.is_some());
+        assert_eq!(power.power_watts, Some(4.0));
+    }
+
+    #[tokio::test]
+    async fn block_rewards_come_from_capped_claims_and_inbound_peers_do_not_vouch() {
+        let mut config = test_config();
+        config.block_interval_ms = 3_600_000;
+        let app = test_app_state(config.clone());
+        let day = 86_400;
+        let t0 = 1_700_000_000;
+        let claim = RewardClaim {
+            uptime_seconds: 400 * day,
+            device_class: "desktop".into(),
+            reward_weight: 5.0,
+            power_watts: None,
+        };
+        let mut chain = vec![app.state.read().unwrap().blocks[0].clone()];
+        let mut rewards = vec![];
+        for at in [t0, t0 + 31 * day] {
+            let mut state = app.state.write().unwrap();
+            let mut block = Block {
+                index: state.blocks.len() as u64,
+                previous_hash: state.blocks.last().unwrap().hash.clone(),
+                hash: String::new(),
+                timestamp: system_time_to_iso(at),
+                producer: test_address("carol"),
+                transactions: vec![],
+                state_root: None,
+                reward_claim: Some(claim.clone()),
+            };
+            let before = state.produced_rewards;
+            apply_block_contents(&config, &mut state, &block).unwrap();
+            rewards.push(state.produced_rewards - before);
+            block.state_root = Some(account_state_root(&state.accounts));
+            block.hash = compute_block_hash(&block);
+            state.block_store.insert(block.hash.clone(), block.clone());
+            state.blocks.push(block.clone());
+            chain.push(block);
         }
+        // Uptime is capped at the chain's age and the weight at 1.0.
+        assert_eq!(rewards[0], 1.0);
+        assert!((rewards[1] - 1.05).abs() < 1e-9);
+        // Any node replaying the chain credits the same rewards.
+        let rebuilt = rebuild_state_from_chain(&config, &chain).unwrap();
+        let state = app.state.read().unwrap().clone();
+        assert_eq!(rebuilt.produced_rewards, state.produced_rewards);
+        assert_eq!(account_state_root(&rebuilt.accounts), account_state_root(&state.accounts));
+
+        // A session anyone can open does not vouch for our uptime.
+        app.p2p_sessions.lock().unwrap().inbound.insert(
+            "psess_1".into(),
+            PeerSession {
+                node_id: "node-x".into(),
+                expires_at: u64::MAX,
+                remote_ip: "203.0.113.7".into(),
+            },
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-bulen-peer-id", "node-x".parse().unwrap());
+        headers.insert("x-bulen-peer-session", "psess_1".parse().unwrap());
+        assert!(verify_peer_session(&app, &headers).is_ok());
+        for key in ["node-x", "https://peer.example.org"] {
+            let (out_tx, _out_rx) = mpsc::channel::<String>(8);
+            let (in_tx, in_rx) = mpsc::channel::<String>(8);
+            in_tx.send("{}".into()).await.unwrap();
+            drop(in_tx);
+            let link =
+                run_peer_link(app.clone(), key.into(), "node-x".into(), u64::MAX, in_rx, out_tx);
+            tokio::time::timeout(Duration::from_secs(2), link).await.unwrap();
+            let attested = app.uptime.lock().unwrap().last_attested_at > 0;
+            assert_eq!(attested, key.starts_with("https://"));
+        }
+    }
 
-        // Payment creation
-        let pay = client
-            .post(format!("{}/api/payments", base))
-            .json(&serde_json::json!({
-                "to": "bob",
-                "amount": 50,
-                "memo": "test"
-            }))
+    #[tokio::test]
+    async fn faucet_credits_go_through_blocks_and_peer_blocks_must_match_their_root() {
+        let (addr, handle, app) = start_test_node(test_config()).await;
+        let base = format!("http://{}", addr);
+        let client = reqwest::Client::new();
+        let alice = test_address("alice");
+        let body: serde_json::Value = client
+            .post(format!("{}/api/faucet", base))
+            .json(&serde_json::json!({ "address": alice, "amount": 500 }))
             .send()
             .await
             .unwrap()
-            .json::<serde_json::Value>()
+            .json()
             .await
             .unwrap();
-        let payment_id = pay.get("id").and_then(|v| v.as_str()).unwrap().to_string();
+        assert_eq!(body["newBalance"], 500);
+        let tx_id = body["txId"].as_str().unwrap().to_string();
+        let faucet_tx = {
+            let state = app.state.read().unwrap();
+            let location = state.tx_index[&tx_id].clone();
+            assert_eq!(state.accounts[FAUCET_ACCOUNT].balance, 0);
+            state.blocks[location.height as usize].transactions[location.position].clone()
+        };
 
-        // Pay it
-        client
+        // Only the faucet endpoint mints, and only on test networks.
+        let resp = client
             .post(format!("{}/api/transactions", base))
             .json(&serde_json::json!({
-                "from": "alice",
-                "to": "bob",
-                "amount": 60,
-                "fee": 1,
-                "memo": "test"
+                "from": FAUCET_ACCOUNT, "to": alice, "amount": 5, "action": "faucet"
             }))
             .send()
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let mut mainnet = test_config();
+        mainnet.chain_id = "bulencoin-mainnet-1".into();
+        let mut tx = faucet_tx.clone();
+        tx.chain_id = Some(mainnet.chain_id.clone());
+        assert!(validate_transaction(&mainnet, &initial_state(&mainnet), &tx).is_err());
+        assert!(validate_transaction(&app.config, &initial_state(&app.config), &faucet_tx).is_ok());
+
+        // A peer block is applied once, and only if it matches its state root.
+        let (mut block, root) = {
+            let state = app.state.read().unwrap();
+            let mut next = state.clone();
+            let mut block = Block {
+                index: state.blocks.len() as u64,
+                previous_hash: state.blocks.last().unwrap().hash.clone(),
+                hash: String::new(),
+                timestamp: now_iso(),
+                producer: "node-x".into(),
+                transactions: vec![],
+                state_root: None,
+                reward_claim: None,
+            };
+            apply_block_contents(&app.config, &mut next, &block).unwrap();
+            block.state_root = Some(account_state_root(&next.accounts));
+            (block, account_state_root(&state.accounts))
+        };
+        let mut forged = block.clone();
+        forged.state_root = Some(root.clone());
+        forged.hash = compute_block_hash(&forged);
+        assert!(accept_peer_block(&app, "node-x", forged.clone()).is_err());
+        {
+            let state = app.state.read().unwrap();
+            assert_eq!(account_state_root(&state.accounts), root);
+            assert!(!state.block_store.contains_key(&forged.hash));
+        }
+        // Leaving the root out does not skip the check, except below
+        // `state_root_height`.
+        let mut rootless = block.clone();
+        rootless.state_root = None;
+        rootless.hash = compute_block_hash(&rootless);
+        let err = accept_peer_block(&app, "node-y", rootless.clone()).unwrap_err();
+        assert!(err.1.ends_with("missing state root"), "{}", err.1);
+        let mut legacy = app.config.clone();
+        legacy.state_root_height = rootless.index + 1;
+        let mut next = app.state.read().unwrap().clone();
+        apply_block(&legacy, &mut next, &rootless).unwrap();
+        block.hash = compute_block_hash(&block);
+        accept_peer_block(&app, "node-x", block.clone()).unwrap();
+        {
+            let state = app.state.read().unwrap();
+            assert_eq!(state.blocks.last().unwrap().hash, block.hash);
+            assert_eq!(state.accounts[&alice].balance, 500);
+        }
 
-        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(default_config().light_quorum, 2);
+        handle.abort();
+    }
 
-        let payment_status = client
-            .get(format!("{}/api/payments/{}", base, payment_id))
+    #[tokio::test]
+    async fn webhooks_need_public_hosts_and_a_slow_receiver_does_not_block_others() {
+        let mut config = test_config();
+        config.allow_private_webhooks = false;
+        for url in ["https://127.0.0.1/hook", "https://10.1.2.3/hook", "https://shop.local/hook"] {
+            assert!(validate_webhook_url(&config, url).is_err(), "{}", url);
+        }
+        assert!(validate_webhook_url(&config, "https://shop.example.com/hook").is_ok());
+
+        // One receiver accepts connections but never answers.
+        let stalled = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stalled_url = format!("http://{}/hook", stalled.local_addr().unwrap());
+        let receiver = Router::new().route("/hook", post(|| async { StatusCode::OK }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver_handle =
+            tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let app = test_app_state(test_config());
+        for (id, url) in [("wh-stalled", &stalled_url), ("wh-ok", &hook_url)] {
+            app.webhooks.lock().unwrap().push(WebhookDelivery {
+                id: id.into(),
+                payment_id: "pay-1".into(),
+                url: url.clone(),
+                body: "{}".into(),
+                state: "pending".into(),
+                created_at: now_unix(),
+                next_attempt_at_ms: 0,
+                attempts: vec![],
+            });
+        }
+        let worker = tokio::spawn({
+            let app = app.clone();
+            async move { deliver_due_webhooks(&app).await }
+        });
+        let mut delivered = false;
+        for _ in 0..40 {
+            delivered = app.webhooks.lock().unwrap().iter().any(|d| {
+                d.id == "wh-ok" && d.state == "delivered"
+            });
+            if delivered {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(delivered);
+        assert!(!worker.is_finished());
+
+        worker.abort();
+        receiver_handle.abort();
+        drop(stalled);
+    }
+
+    #[tokio::test]
+    async fn blocks_with_forged_transaction_ids_are_refused() {
+        let app = test_app_state(test_config());
+        let alice = test_address("alice");
+        let bob = test_address("bob");
+        app.state.write().unwrap().accounts.insert(
+            alice.clone(),
+            Account {
+                balance: 100,
+                stake: 0,
+                nonce: 0,
+                reputation: 0,
+                locks: Vec::new(),
+            },
+        );
+        let mut tx = Transaction {
+            id: String::new(),
+            from: alice.clone(),
+            to: bob.clone(),
+            amount: 10,
+            fee: 1,
+            nonce: 1,
+            timestamp: now_iso(),
+            action: "transfer".into(),
+            memo: None,
+            public_key: None,
+            signature: None,
+            key_type: None,
+            version: TX_VERSION,
+            chain_id: Some(app.config.chain_id.clone()),
+            expires_at_height: None,
+            multisig: None,
+            signatures: vec![],
+            lock: None,
+            outputs: vec![],
+        };
+        tx.id = compute_transaction_id(&tx);
+        let block = |transactions: Vec<Transaction>| {
+            let state = app.state.read().unwrap();
+            let mut block = Block {
+                index: state.blocks.len() as u64,
+                previous_hash: state.blocks.last().unwrap().hash.clone(),
+                hash: String::new(),
+                timestamp: now_iso(),
+                producer: "node-x".into(),
+                transactions,
+                state_root: None,
+                reward_claim: None,
+            };
+            block.hash = compute_block_hash(&block);
+            block
+        };
+
+        let mut forged = tx.clone();
+        forged.id = "forged".into();
+        let mut forged = block(vec![forged]);
+        forged.state_root = Some(account_state_root(&app.state.read().unwrap().accounts));
+        forged.hash = compute_block_hash(&forged);
+        let err = accept_peer_block(&app, "node-x", forged).unwrap_err();
+        assert!(err.1.ends_with("transaction id does not match payload"), "{}", err.1);
+        {
+            let state = app.state.read().unwrap();
+            assert_eq!(state.accounts[&alice].balance, 100);
+            assert!(!state.tx_index.contains_key("forged"));
+            assert!(!state.address_index.contains_key(&bob));
+        }
+
+        let mut next = app.state.read().unwrap().clone();
+        apply_block_contents(&app.config, &mut next, &block(vec![tx.clone()])).unwrap();
+        assert_eq!(next.tx_index[&tx.id].position, 0);
+        assert_eq!(next.accounts[&bob].balance, 10);
+    }
+
+    #[tokio::test]
+    async fn webhooks_neither_follow_redirects_nor_resolve_to_private_hosts() {
+        let hits = Arc::new(AtomicU64::new(0));
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|| async { (StatusCode::TEMPORARY_REDIRECT, [("location", "/internal")]) }),
+            )
+            .route(
+                "/internal",
+                post({
+                    let hits = hits.clone();
+                    move || async move {
+                        hits.fetch_add(1, Ordering::SeqCst);
+                        StatusCode::OK
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver_handle =
+            tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let mut config = test_config();
+        let app = test_app_state(config.clone());
+        app.webhooks.lock().unwrap().push(WebhookDelivery {
+            id: "wh-redirect".into(),
+            payment_id: "pay-1".into(),
+            url: format!("http://127.0.0.1:{}/hook", port),
+            body: "{}".into(),
+            state: "pending".into(),
+            created_at: now_unix(),
+            next_attempt_at_ms: 0,
+            attempts: vec![],
+        });
+        deliver_due_webhooks(&app).await;
+        {
+            let outbox = app.webhooks.lock().unwrap();
+            assert_eq!(outbox[0].state, "pending");
+            assert_eq!(outbox[0].attempts[0].status_code, Some(307));
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        // A name that resolves to loopback is refused when connecting.
+        config.allow_private_webhooks = false;
+        let err = webhook_client(&config)
+            .post(format!("http://localhost:{}/internal", port))
             .send()
             .await
-            .unwrap()
-            .json::<serde_json::Value>()
-            .await
-            .unwrap();
-        let status = payment_status.get("status").and_then(|v| v.as_str()).unwrap_or("");
-        assert!(status == "paid" || status == "pending_block" || status == "pending");
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("has no public address"), "{:?}", err);
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
 
-        handle.abort();
+        receiver_handle.abort();
     }
-}
-fn slash_producer(state: &mut StateData, producer: &str) {
-    if let Some(acc) = state.accounts.get_mut(producer) {
-        if acc.stake > 0 {
-            let slash = ((acc.stake as f64) * 0.01).ceil() as i128;
-            acc.stake = (acc.stake - slash).max(0);
-        }
-        acc.reputation = acc.reputation.saturating_sub(1);
+
+    #[test]
+    fn payment_refresh_skips_final_payments_and_reads_the_address_index() {
+        let config = test_config();
+        let app = test_app_state(config.clone());
+        let alice = test_address("alice");
+        let shop = test_address("shop");
+        app.state.write().unwrap().accounts.insert(
+            alice.clone(),
+            Account {
+                balance: 100,
+                ..Default::default()
+            },
+        );
+        let transfer = |from: &str, nonce: u64| {
+            let mut tx = Transaction {
+                id: String::new(),
+                from: from.into(),
+                to: shop.clone(),
+                amount: 10,
+                fee: 0,
+                nonce,
+                timestamp: now_iso(),
+                action: "transfer".into(),
+                memo: None,
+                public_key: None,
+                signature: None,
+                key_type: None,
+                version: TX_VERSION,
+                chain_id: Some(config.chain_id.clone()),
+                expires_at_height: None,
+                outputs: vec![],
+                multisig: None,
+                signatures: vec![],
+                lock: None,
+            };
+            tx.id = compute_transaction_id(&tx);
+            tx
+        };
+        // The unfunded transfer is skipped by the block, so it must not pay.
+        let unfunded = transfer(&test_address("ghost"), 1);
+        let paid = transfer(&alice, 1);
+        append_test_block(&app, "producer", vec![unfunded, paid.clone()]);
+
+        let payment = |id: &str, status: &str| Payment {
+            id: id.into(),
+            to: shop.clone(),
+            amount: 10,
+            memo: None,
+            created_at: now_iso(),
+            expires_at: "2999-01-01T00:00:00Z".into(),
+            status: status.into(),
+            transaction_id: None,
+            block_index: None,
+            webhook_url: None,
+            merchant_order_id: None,
+            customer_reference: None,
+        };
+        let mut store =
+            PaymentStore::new(vec![payment("open", "pending"), payment("done", "expired")]);
+        let changed = store.refresh(&config, &app.state.read().unwrap());
+        assert_eq!(changed.len(), 1);
+        let open = store.get("open").unwrap();
+        assert_eq!(open.status, "paid");
+        assert_eq!(open.transaction_id.as_deref(), Some(paid.id.as_str()));
+        assert_eq!(open.block_index, Some(1));
+        assert_eq!(store.get("done").unwrap().status, "expired");
+        assert_eq!(store.count_status("paid"), 1);
+        assert!(store.refresh(&config, &app.state.read().unwrap()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn faucet_credits_from_a_non_producing_node_reach_a_producer() {
+        let (c_addr, c_handle, c) = start_test_node(test_config()).await;
+        let mut b_config = test_config();
+        b_config.block_interval_ms = 60_000;
+        b_config.peers = vec![format!("http://{}", c_addr)];
+        let (b_addr, b_handle, b) = start_test_node(b_config).await;
+        let alice = test_address("alice");
+
+        let resp = reqwest::Client::new()
+            .post(format!("http://{}/api/faucet", b_addr))
+            .json(&serde_json::json!({ "address": alice, "amount": 70 }))
+            .timeout(Duration::from_secs(20))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["newBalance"], 70);
+        let tx_id = body["txId"].as_str().unwrap();
+        assert!(c.state.read().unwrap().tx_index.contains_key(tx_id));
+        assert_eq!(b.state.read().unwrap().produced_blocks, 0);
+
+        // Only relayed faucet credits are admitted, and only on test networks.
+        let tx = {
+            let state = c.state.read().unwrap();
+            let location = state.tx_index[tx_id].clone();
+            state.blocks[location.height as usize].transactions[location.position].clone()
+        };
+        assert!(admit_action(&c.config, &tx, true).is_ok());
+        assert!(admit_action(&c.config, &tx, false).is_err());
+        let mut mainnet = test_config();
+        mainnet.chain_id = "bulencoin-mainnet-1".into();
+        assert!(admit_action(&mainnet, &tx, true).is_err());
+
+        b_handle.abort();
+        c_handle.abort();
     }
 }
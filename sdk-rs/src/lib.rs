@@ -2,8 +2,12 @@
 //! Contains both blocking (`BulenClient`) and async (`AsyncBulenClient`) variants.
 use reqwest::blocking::Client;
 use reqwest::Client as AsyncClient;
+use base64::Engine;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::time::Duration;
 
 #[derive(Clone)]
@@ -66,6 +70,15 @@ impl BulenClient {
         let url = self.base.join("rewards/estimate").expect("url");
         self.http.post(url).json(req).send()?.error_for_status()?.json()
     }
+
+    /// Submit a transaction. The returned id equals `req.id()`.
+    pub fn submit_transaction(
+        &self,
+        req: &TransactionRequest,
+    ) -> Result<TransactionResponse, reqwest::Error> {
+        let url = self.base.join("transactions").expect("url");
+        self.http.post(url).json(req).send()?.error_for_status()?.json()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -96,13 +109,34 @@ pub struct PaymentResponse {
     pub webhook_url: Option<String>,
 }
 
+/// Payment link parameters; build with `PaymentLinkRequest::new` so fields
+/// can be added without breaking callers. Memo and label: max
+/// `MAX_MEMO_CHARS` chars.
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
+#[non_exhaustive]
 pub struct PaymentLinkRequest {
     pub address: String,
     pub amount: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub memo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in_seconds: Option<u64>,
+}
+
+impl PaymentLinkRequest {
+    /// Link for `amount` to `address`, without memo, label or expiry.
+    pub fn new(address: &str, amount: u64) -> Self {
+        Self {
+            address: address.to_string(),
+            amount,
+            memo: None,
+            label: None,
+            expires_in_seconds: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -153,12 +187,312 @@ pub struct NodeStatus {
     pub reward_projection: Option<RewardProjection>,
 }
 
-/// Build a BIP21-like payment link locally.
+/// Current transaction signing format (covers action, memo, chain id, expiry).
+pub const TX_VERSION: u32 = 2;
+/// Original signing format; only accepted by nodes running with
+/// `BULEN_ALLOW_LEGACY_SIGNATURES=true`.
+pub const LEGACY_TX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionRequest {
+    pub version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+    pub fee: u64,
+    pub nonce: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at_height: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<TransferOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// `p256` (default), `ed25519` or `secp256k1`; not part of the signed
+    /// payload, the node derives `from` per scheme.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    /// Set for transactions from a multisig account (`from` must be
+    /// `derive_multisig_address(prefix, policy)`); co-signers use `co_sign`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigPolicy>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<PartialSignature>,
+    /// Locks the transferred amount in the receiver's account (see `LockSchedule`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock: Option<LockSchedule>,
+}
+
+impl TransactionRequest {
+    /// Unsigned transfer in the current signing format.
+    pub fn transfer(chain_id: &str, from: &str, to: &str, amount: u64, fee: u64, nonce: u64) -> Self {
+        Self {
+            version: TX_VERSION,
+            chain_id: Some(chain_id.to_string()),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            fee,
+            nonce,
+            action: None,
+            memo: None,
+            expires_at_height: None,
+            outputs: Vec::new(),
+            public_key: None,
+            signature: None,
+            key_type: None,
+            multisig: None,
+            signatures: Vec::new(),
+            lock: None,
+        }
+    }
+
+    /// Unsigned transfer whose amount vests on the receiver's side.
+    pub fn locked_transfer(
+        chain_id: &str,
+        from: &str,
+        to: &str,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+        lock: LockSchedule,
+    ) -> Self {
+        Self {
+            lock: Some(lock),
+            ..Self::transfer(chain_id, from, to, amount, fee, nonce)
+        }
+    }
+
+    /// Unsigned `batch_transfer`; `amount` is set to the sum of the outputs.
+    /// Nodes require `fee >= outputs * BULEN_BATCH_FEE_PER_OUTPUT`.
+    pub fn batch_transfer(
+        chain_id: &str,
+        from: &str,
+        outputs: Vec<TransferOutput>,
+        fee: u64,
+        nonce: u64,
+    ) -> Self {
+        let amount = outputs.iter().map(|o| o.amount).sum();
+        Self {
+            action: Some("batch_transfer".into()),
+            outputs,
+            ..Self::transfer(chain_id, from, "", amount, fee, nonce)
+        }
+    }
+
+    /// Payload covered by the signature, byte-for-byte as the node builds it.
+    pub fn canonical_payload(&self) -> String {
+        if self.version == LEGACY_TX_VERSION {
+            return serde_json::json!({
+                "from": self.from,
+                "to": self.to,
+                "amount": self.amount,
+                "fee": self.fee,
+                "nonce": self.nonce,
+            })
+            .to_string();
+        }
+        let action = self.action.as_deref().unwrap_or("transfer");
+        let mut payload = serde_json::json!({
+            "version": self.version,
+            "chainId": self.chain_id,
+            "from": self.from,
+            "to": self.to,
+            "amount": self.amount,
+            "fee": self.fee,
+            "nonce": self.nonce,
+            "action": action,
+            "memo": self.memo,
+            "expiresAtHeight": self.expires_at_height,
+        });
+        if let Some(lock) = &self.lock {
+            payload["lock"] = serde_json::json!({
+                "unit": lock.unit,
+                "start": lock.start,
+                "end": lock.end,
+                "cliff": lock.cliff,
+            });
+        }
+        if action == "batch_transfer" {
+            payload["outputs"] = self
+                .outputs
+                .iter()
+                .map(|o| serde_json::json!({ "to": o.to, "amount": o.amount, "memo": o.memo }))
+                .collect();
+        }
+        payload.to_string()
+    }
+
+    /// Transaction id the node will assign (hex SHA-256 of the canonical payload).
+    pub fn id(&self) -> String {
+        transaction_id(&self.canonical_payload())
+    }
+
+    /// Sign with a p256 key, filling `public_key` and `signature` (base64 DER).
+    pub fn sign(&mut self, key: &SigningKey) -> Result<(), p256::pkcs8::spki::Error> {
+        let public_key = key.verifying_key().to_public_key_pem(LineEnding::LF)?;
+        let signature: Signature = key.sign(self.canonical_payload().as_bytes());
+        self.public_key = Some(public_key);
+        self.signature = Some(
+            base64::engine::general_purpose::STANDARD.encode(signature.to_der().as_bytes()),
+        );
+        Ok(())
+    }
+
+    /// Add (or replace) this key's signature for a multisig transaction.
+    pub fn co_sign(&mut self, key: &SigningKey) -> Result<(), p256::pkcs8::spki::Error> {
+        let public_key = key.verifying_key().to_public_key_pem(LineEnding::LF)?;
+        let signature: Signature = key.sign(self.canonical_payload().as_bytes());
+        self.signatures.retain(|s| s.public_key.trim() != public_key.trim());
+        self.signatures.push(PartialSignature {
+            public_key,
+            signature: base64::engine::general_purpose::STANDARD
+                .encode(signature.to_der().as_bytes()),
+            key_type: None,
+        });
+        Ok(())
+    }
+
+    /// Sign with a PKCS#8 PEM encoded p256 private key.
+    pub fn sign_pem(&mut self, private_key_pem: &str) -> Result<(), p256::pkcs8::Error> {
+        let key = SigningKey::from_pkcs8_pem(private_key_pem)?;
+        self.sign(&key).map_err(p256::pkcs8::Error::PublicKey)
+    }
+}
+
+/// Checksummed (bech32m) address for a 20-byte account hash. The prefix is
+/// chain specific; nodes report theirs as `addressPrefix` in `/api/info`.
+pub fn encode_address(prefix: &str, hash: &[u8]) -> Result<String, String> {
+    let hrp = bech32::Hrp::parse(prefix).map_err(|e| e.to_string())?;
+    bech32::encode::<bech32::Bech32m>(hrp, hash).map_err(|e| e.to_string())
+}
+
+/// Same check nodes apply to `to` and payment addresses: a checksummed
+/// address with this chain's prefix, or a legacy `addr_`/`msig_` address.
+pub fn is_valid_address(prefix: &str, address: &str) -> bool {
+    if let Some(hex) = address
+        .strip_prefix("addr_")
+        .or_else(|| address.strip_prefix("msig_"))
+    {
+        return hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    }
+    if address != address.to_lowercase() {
+        return false;
+    }
+    match bech32::primitives::decode::CheckedHrpstring::new::<bech32::Bech32m>(address) {
+        Ok(checked) => checked.hrp().to_string() == prefix && checked.byte_iter().count() == 20,
+        Err(_) => false,
+    }
+}
+
+fn account_hash(preimage: &str) -> [u8; 20] {
+    Sha256::digest(preimage.as_bytes())[..20].try_into().unwrap()
+}
+
+/// Address controlled by a PEM public key (same derivation as the node).
+pub fn derive_address(prefix: &str, public_key_pem: &str) -> Result<String, String> {
+    encode_address(prefix, &account_hash(public_key_pem))
+}
+
+/// Pre-checksum `addr_` form of `derive_address`, still accepted by nodes
+/// running with `BULEN_ALLOW_LEGACY_ADDRESSES=true`.
+pub fn derive_legacy_address(public_key_pem: &str) -> String {
+    let hex = transaction_id(public_key_pem);
+    format!("addr_{}", &hex[..40])
+}
+
+/// Keys and threshold controlling a multisig account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultisigPolicy {
+    pub threshold: usize,
+    pub public_keys: Vec<String>,
+}
+
+/// Release schedule for a locked transfer. `unit` is `"height"` (block
+/// heights) or `"time"` (unix seconds). Nothing unlocks before `cliff`
+/// (defaults to `start`), then the amount vests linearly until `end`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockSchedule {
+    pub unit: String,
+    pub start: u64,
+    pub end: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cliff: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialSignature {
+    pub public_key: String,
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+}
+
+/// Address of a multisig account (same derivation as the node; key order
+/// does not matter).
+pub fn derive_multisig_address(prefix: &str, policy: &MultisigPolicy) -> Result<String, String> {
+    let mut keys: Vec<&str> = policy.public_keys.iter().map(|k| k.trim()).collect();
+    keys.sort_unstable();
+    let canonical = serde_json::json!({
+        "threshold": policy.threshold,
+        "publicKeys": keys,
+    })
+    .to_string();
+    encode_address(prefix, &account_hash(&canonical))
+}
+
+/// One payee of a batch transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOutput {
+    pub to: String,
+    pub amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionResponse {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+    pub fee: u64,
+    pub nonce: u64,
+    pub action: Option<String>,
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub outputs: Vec<TransferOutput>,
+}
+
+/// Hash a canonical transaction payload into its id.
+pub fn transaction_id(canonical_payload: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(canonical_payload.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Longest memo (and payment link label) in chars, as enforced by the node.
+pub const MAX_MEMO_CHARS: usize = 256;
+
+/// Build a BIP21-like payment link locally. Memos are cut to
+/// `MAX_MEMO_CHARS`, as the node does for transaction memos.
 pub fn build_payment_link(address: &str, amount: u64, memo: Option<&str>) -> String {
   let mut link = format!("bulen:{}?amount={}", address, amount);
   if let Some(m) = memo {
+    let m: String = m.chars().take(MAX_MEMO_CHARS).collect();
     link.push_str("&memo=");
-    link.push_str(&urlencoding::encode(m));
+    link.push_str(&urlencoding::encode(&m));
   }
   link
 }
@@ -170,25 +504,6 @@ pub struct AsyncBulenClient {
     http: AsyncClient,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn payment_link_encodes_memo() {
-        let link = build_payment_link("addr", 42, Some("hello world"));
-        assert!(link.contains("bulen:addr?amount=42"));
-        assert!(link.contains("memo=hello%20world"));
-    }
-
-    #[test]
-    fn client_normalizes_base() {
-        let client = BulenClient::new("http://localhost:4100/api").unwrap();
-        let url = client.base.join("payments").expect("url");
-        assert_eq!(url.as_str(), "http://localhost:4100/api/payments");
-    }
-}
-
 impl AsyncBulenClient {
     pub fn new(base: &str) -> Result<Self, url::ParseError> {
         let mut base = Url::parse(base)?;
@@ -258,4 +573,168 @@ impl AsyncBulenClient {
             .json()
             .await
     }
+
+    pub async fn submit_transaction(
+        &self,
+        req: &TransactionRequest,
+    ) -> Result<TransactionResponse, reqwest::Error> {
+        let url = self.base.join("transactions").expect("url");
+        self.http
+            .post(url)
+            .json(req)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payment_link_encodes_memo() {
+        let link = build_payment_link("addr", 42, Some("hello world"));
+        assert!(link.contains("bulen:addr?amount=42"));
+        assert!(link.contains("memo=hello%20world"));
+
+        let long = "é".repeat(MAX_MEMO_CHARS + 10);
+        let link = build_payment_link("addr", 1, Some(&long));
+        assert_eq!(link, format!("bulen:addr?amount=1&memo={}", "%C3%A9".repeat(MAX_MEMO_CHARS)));
+    }
+
+    #[test]
+    fn transaction_id_is_deterministic() {
+        let tx = TransactionRequest::transfer("bulencoin-devnet-1", "alice", "bob", 10, 1, 1);
+        assert_eq!(
+            tx.canonical_payload(),
+            r#"{"action":"transfer","amount":10,"chainId":"bulencoin-devnet-1","expiresAtHeight":null,"fee":1,"from":"alice","memo":null,"nonce":1,"to":"bob","version":2}"#
+        );
+        let mut signed = tx.clone();
+        signed.signature = Some("sig".into());
+        assert_eq!(tx.id(), signed.id());
+        assert_eq!(tx.id().len(), 64);
+
+        let mut legacy = tx.clone();
+        legacy.version = LEGACY_TX_VERSION;
+        assert_eq!(
+            legacy.canonical_payload(),
+            r#"{"amount":10,"fee":1,"from":"alice","nonce":1,"to":"bob"}"#
+        );
+    }
+
+    #[test]
+    fn batch_payload_includes_outputs() {
+        let tx = TransactionRequest::batch_transfer(
+            "bulencoin-devnet-1",
+            "treasury",
+            vec![
+                TransferOutput { to: "alice".into(), amount: 5, memo: Some("p1".into()) },
+                TransferOutput { to: "bob".into(), amount: 7, memo: None },
+            ],
+            2,
+            3,
+        );
+        assert_eq!(tx.amount, 12);
+        assert!(tx.canonical_payload().ends_with(
+            r#""outputs":[{"amount":5,"memo":"p1","to":"alice"},{"amount":7,"memo":null,"to":"bob"}],"to":"","version":2}"#
+        ));
+    }
+
+    #[test]
+    fn signing_covers_memo_and_action() {
+        use p256::ecdsa::{signature::Verifier, VerifyingKey};
+        use p256::pkcs8::DecodePublicKey;
+
+        let key = SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let mut tx = TransactionRequest::transfer("bulencoin-devnet-1", "", "bob", 10, 1, 1);
+        tx.memo = Some("order-1".into());
+        tx.sign(&key).unwrap();
+        let public_key = tx.public_key.clone().unwrap();
+        let verifying = VerifyingKey::from_public_key_pem(&public_key).unwrap();
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(tx.signature.as_ref().unwrap())
+            .unwrap();
+        let signature = Signature::from_der(&der).unwrap();
+        assert!(verifying
+            .verify(tx.canonical_payload().as_bytes(), &signature)
+            .is_ok());
+        tx.action = Some("stake".into());
+        assert!(verifying
+            .verify(tx.canonical_payload().as_bytes(), &signature)
+            .is_err());
+        assert!(derive_legacy_address(&public_key).starts_with("addr_"));
+    }
+
+    #[test]
+    fn multisig_co_signing() {
+        let keys: Vec<SigningKey> = (1u8..=3)
+            .map(|i| SigningKey::from_bytes(&[i; 32].into()).unwrap())
+            .collect();
+        let policy = MultisigPolicy {
+            threshold: 2,
+            public_keys: keys
+                .iter()
+                .map(|k| k.verifying_key().to_public_key_pem(LineEnding::LF).unwrap())
+                .collect(),
+        };
+        let from = derive_multisig_address("bulendev", &policy).unwrap();
+        assert!(from.starts_with("bulendev1"));
+        let mut tx = TransactionRequest::transfer("bulencoin-devnet-1", &from, "vendor", 100, 1, 1);
+        tx.multisig = Some(policy);
+        let id = tx.id();
+        tx.co_sign(&keys[0]).unwrap();
+        tx.co_sign(&keys[2]).unwrap();
+        tx.co_sign(&keys[0]).unwrap();
+        assert_eq!(tx.signatures.len(), 2);
+        assert_eq!(tx.id(), id);
+    }
+
+    #[test]
+    fn lock_is_part_of_payload() {
+        let plain = TransactionRequest::transfer("bulencoin-devnet-1", "treasury", "dev", 500, 1, 1);
+        let locked = TransactionRequest::locked_transfer(
+            "bulencoin-devnet-1",
+            "treasury",
+            "dev",
+            500,
+            1,
+            1,
+            LockSchedule { unit: "height".into(), start: 10, end: 20, cliff: None },
+        );
+        assert!(locked
+            .canonical_payload()
+            .contains(r#""lock":{"cliff":null,"end":20,"start":10,"unit":"height"}"#));
+        assert_ne!(plain.id(), locked.id());
+    }
+
+    #[test]
+    fn addresses_match_node_derivation() {
+        let pem = "-----BEGIN PUBLIC KEY-----\nexample\n-----END PUBLIC KEY-----\n";
+        let address = derive_address("bulendev", pem).unwrap();
+        assert!(is_valid_address("bulendev", &address));
+        assert!(!is_valid_address("bulen", &address));
+        let last = if address.ends_with('q') { 'p' } else { 'q' };
+        let typo = format!("{}{}", &address[..address.len() - 1], last);
+        assert!(!is_valid_address("bulendev", &typo));
+        assert!(!is_valid_address("bulendev", "sdk-rs-merchant"));
+        let legacy = derive_legacy_address(pem);
+        assert!(is_valid_address("bulendev", &legacy));
+        assert_eq!(hex_of(&address), legacy["addr_".len()..]);
+    }
+
+    fn hex_of(address: &str) -> String {
+        let checked =
+            bech32::primitives::decode::CheckedHrpstring::new::<bech32::Bech32m>(address).unwrap();
+        checked.byte_iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn client_normalizes_base() {
+        let client = BulenClient::new("http://localhost:4100/api").unwrap();
+        let url = client.base.join("payments").expect("url");
+        assert_eq!(url.as_str(), "http://localhost:4100/api/payments");
+    }
 }
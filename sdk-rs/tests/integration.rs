@@ -78,10 +78,10 @@ fn sdk_rust_payments_and_links() {
     assert!(local_link.contains("memo=sdk-rs-order"));
 
     let link_resp = client
-        .create_payment_link(&PaymentLinkRequest {
-            address: "sdk-rs-merchant".to_string(),
-            amount: 50,
-            memo: Some("sdk-rs-order".to_string()),
+        .create_payment_link(&{
+            let mut req = PaymentLinkRequest::new("sdk-rs-merchant", 50);
+            req.memo = Some("sdk-rs-order".to_string());
+            req
         })
         .expect("payment link");
     assert!(link_resp.ok);
@@ -124,4 +124,5 @@ fn sdk_rust_payments_and_links() {
     assert!(est.ok);
 
     node.kill().ok();
+    node.wait().ok();
 }